@@ -0,0 +1,126 @@
+//! Generates the kernel symbol table the profiler resolves addresses with.
+//!
+//! The table comes from a kernel that was linked before, named by `KERNEL_SYMBOLS`:
+//!
+//! ```sh
+//! cargo build
+//! cp target/riscv64gc-unknown-none-elf/debug/tos target/tos.syms
+//! KERNEL_SYMBOLS=target/tos.syms cargo build
+//! ```
+//!
+//! The linker script puts the table behind everything else in `.rodata`, so embedding it
+//! moves no function and the addresses of the first build hold for the second. Without
+//! `KERNEL_SYMBOLS` the table is empty.
+//!
+//! Layout, little endian: the number of symbols as a u64, then per symbol its address
+//! (u64), size (u32) and the offset of its name (u32), sorted by address, then the
+//! names, each terminated by a NUL.
+
+use std::{env, fs, path::PathBuf};
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=KERNEL_SYMBOLS");
+
+    let mut symbols = Vec::new();
+    if let Some(path) = env::var_os("KERNEL_SYMBOLS") {
+        let path = PathBuf::from(path);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let elf = fs::read(&path).unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e));
+        symbols = functions(&elf).unwrap_or_else(|| panic!("{} is no ELF64", path.display()));
+    }
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("ksyms.bin");
+    fs::write(out, table(symbols)).unwrap();
+}
+
+struct Symbol {
+    addr: u64,
+    size: u32,
+    name: String,
+}
+
+fn u16_at(b: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(off..off + 2)?.try_into().ok()?))
+}
+
+fn u32_at(b: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn u64_at(b: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(off..off + 8)?.try_into().ok()?))
+}
+
+/// The section at `offset` and of `size` bytes
+fn section(elf: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    elf.get(offset as usize..offset.checked_add(size)? as usize)
+}
+
+/// The functions in the symbol table of a little endian ELF64 file
+fn functions(elf: &[u8]) -> Option<Vec<Symbol>> {
+    if elf.get(..6)? != b"\x7fELF\x02\x01" {
+        return None;
+    }
+    let shoff = u64_at(elf, 0x28)? as usize;
+    let shentsize = u16_at(elf, 0x3a)? as usize;
+    let shnum = u16_at(elf, 0x3c)? as usize;
+    let header = |i: usize| shoff + i * shentsize;
+
+    let Some(symtab) = (0..shnum)
+        .map(header)
+        .find(|&h| u32_at(elf, h + 4) == Some(SHT_SYMTAB))
+    else {
+        return Some(Vec::new());
+    };
+    let syms = section(
+        elf,
+        u64_at(elf, symtab + 0x18)?,
+        u64_at(elf, symtab + 0x20)?,
+    )?;
+    let strtab = header(u32_at(elf, symtab + 0x28)? as usize);
+    let strs = section(
+        elf,
+        u64_at(elf, strtab + 0x18)?,
+        u64_at(elf, strtab + 0x20)?,
+    )?;
+
+    let mut symbols = Vec::new();
+    for sym in syms.as_chunks::<24>().0 {
+        let (info, addr, size) = (sym[4], u64_at(sym, 8)?, u64_at(sym, 16)?);
+        if info & 0xf != STT_FUNC || addr == 0 || size == 0 {
+            continue;
+        }
+        let name = strs.get(u32_at(sym, 0)? as usize..)?;
+        let name = &name[..name.iter().position(|&c| c == 0)?];
+        symbols.push(Symbol {
+            addr,
+            size: size.min(u32::MAX as u64) as u32,
+            name: format!(
+                "{:#}",
+                rustc_demangle::demangle(&String::from_utf8_lossy(name))
+            ),
+        });
+    }
+    symbols.sort_by_key(|s| s.addr);
+    symbols.dedup_by_key(|s| s.addr);
+    Some(symbols)
+}
+
+fn table(symbols: Vec<Symbol>) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    entries.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    for s in &symbols {
+        entries.extend_from_slice(&s.addr.to_le_bytes());
+        entries.extend_from_slice(&s.size.to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(s.name.as_bytes());
+        names.push(0);
+    }
+    entries.extend_from_slice(&names);
+    entries
+}
@@ -0,0 +1,10 @@
+//! The parts of the kernel that don't need the hardware, built for the host from the
+//! kernel's own sources so they can be unit tested. The little they use from the rest of
+//! the kernel is stood in for here.
+//!
+//! Run the tests with `cargo test` from this directory.
+
+#[path = "../../src/sbi/mod.rs"]
+pub mod sbi;
+
+mod sync;
@@ -0,0 +1,15 @@
+//! What the kernel's `sync` provides to the modules built here
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub struct SpinLock<T>(Mutex<T>);
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self(Mutex::new(data))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
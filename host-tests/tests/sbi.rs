@@ -0,0 +1,237 @@
+//! The SBI call wrappers against the mock firmware: which extension, function and
+//! arguments they pass, and how they decode what comes back.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use tos_host_tests::sbi::{
+    HartMask, SbiErrorType, SbiRet, base, dbcn,
+    hsm::{self, HartState, SuspendType},
+    legacy, mock,
+    pmu::{self, CacheId, CacheOp, CacheResult, CounterInfo, Event, HardwareEvent},
+    rfence, sta,
+};
+
+/// A call as the firmware saw it: extension, function and `a0` to `a5`
+type Call = (usize, usize, [usize; 6]);
+
+/// The mock's handler is global, so tests using it take turns
+static FIRMWARE: Mutex<()> = Mutex::new(());
+static CALLS: Mutex<Vec<Call>> = Mutex::new(Vec::new());
+static REPLY: Mutex<SbiRet> = Mutex::new(SbiRet::success(0));
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn firmware(extension_id: usize, function_id: usize, args: [usize; 6]) -> SbiRet {
+    lock(&CALLS).push((extension_id, function_id, args));
+    *lock(&REPLY)
+}
+
+/// Runs `f` against firmware that answers every call with `reply`. Returns what `f`
+/// returned and the calls it made.
+fn with_reply<R>(reply: SbiRet, f: impl FnOnce() -> R) -> (R, Vec<Call>) {
+    let _turn = lock(&FIRMWARE);
+    *lock(&REPLY) = reply;
+    lock(&CALLS).clear();
+    mock::set_handler(Some(firmware));
+    let result = f();
+    mock::set_handler(None);
+    (result, lock(&CALLS).drain(..).collect())
+}
+
+fn args<const N: usize>(args: [usize; N]) -> [usize; 6] {
+    let mut regs = [0; 6];
+    regs[..N].copy_from_slice(&args);
+    regs
+}
+
+#[test]
+fn without_firmware_everything_is_unsupported() {
+    let _turn = lock(&FIRMWARE);
+    let error = base::get_impl_id().unwrap_err();
+    assert_eq!(error.kind(), SbiErrorType::NotSupported);
+    assert_eq!(error.code(), -2);
+}
+
+#[test]
+fn spec_version_is_split_into_major_and_minor() {
+    let (version, calls) = with_reply(SbiRet::success(2 << 24 | 3), base::get_spec_version);
+    assert_eq!(version.unwrap(), (2, 3));
+    assert_eq!(
+        calls,
+        [(base::EXTENSION_ID, base::fid::GET_SPEC_VERSION, [0; 6])]
+    );
+}
+
+#[test]
+fn probe_extension_passes_the_id_and_decodes_a_bool() {
+    let (present, calls) = with_reply(SbiRet::success(1), || {
+        base::probe_extension(hsm::EXTENSION_ID)
+    });
+    assert!(present.unwrap());
+    assert_eq!(
+        calls,
+        [(
+            base::EXTENSION_ID,
+            base::fid::PROBE_EXTENSION,
+            args([hsm::EXTENSION_ID])
+        )]
+    );
+
+    let (present, _) = with_reply(SbiRet::success(0), || base::probe_extension(0x1234));
+    assert!(!present.unwrap());
+}
+
+#[test]
+fn errors_are_decoded_from_a0() {
+    let (result, _) = with_reply(SbiRet::failure(SbiErrorType::Denied), base::get_marchid);
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), SbiErrorType::Denied);
+    assert_eq!(error.code(), -4);
+
+    let (result, _) = with_reply(
+        SbiRet {
+            error: -99,
+            value: 7,
+        },
+        base::get_mimpid,
+    );
+    assert_eq!(result.unwrap_err().kind(), SbiErrorType::Unknown(-99));
+}
+
+#[test]
+fn hart_status_is_decoded() {
+    let (state, calls) = with_reply(SbiRet::success(4), || hsm::hart_get_status(3));
+    assert_eq!(state.unwrap(), HartState::Suspended);
+    assert_eq!(
+        calls,
+        [(hsm::EXTENSION_ID, hsm::fid::HART_GET_STATUS, args([3]))]
+    );
+
+    let (state, _) = with_reply(SbiRet::success(42), || hsm::hart_get_status(3));
+    assert_eq!(state.unwrap(), HartState::Unknown(42));
+}
+
+#[test]
+fn hart_suspend_encodes_the_suspend_type() {
+    let (result, calls) = with_reply(SbiRet::success(0), || unsafe {
+        hsm::hart_suspend(SuspendType::DefaultNonRetentive, 0x8020_0000, 0xabc)
+    });
+    assert!(result.is_ok());
+    assert_eq!(
+        calls,
+        [(
+            hsm::EXTENSION_ID,
+            hsm::fid::HART_SUSPEND,
+            args([0x8000_0000, 0x8020_0000, 0xabc])
+        )]
+    );
+}
+
+#[test]
+fn rfence_passes_the_hart_mask_before_the_range() {
+    let mask = HartMask::new(4).with(4).with(6);
+    let (result, calls) = with_reply(SbiRet::success(0), || {
+        rfence::remote_sfence_vma_asid(mask, 0x1000, 0x2000, 9)
+    });
+    assert!(result.is_ok());
+    assert_eq!(
+        calls,
+        [(
+            rfence::EXTENSION_ID,
+            rfence::fid::REMOTE_SFENCE_VMA_ASID,
+            args([0b101, 4, 0x1000, 0x2000, 9])
+        )]
+    );
+
+    let (_, calls) = with_reply(SbiRet::success(0), || {
+        rfence::remote_fence_i(HartMask::all())
+    });
+    assert_eq!(calls[0].2, args([0, usize::MAX]));
+}
+
+#[test]
+fn legacy_calls_are_extensions_of_their_own_returning_in_a0() {
+    let (byte, calls) = with_reply(
+        SbiRet {
+            error: 'a' as isize,
+            value: 0,
+        },
+        legacy::console_getchar,
+    );
+    assert_eq!(byte, Some(b'a'));
+    assert_eq!(calls, [(legacy::CONSOLE_GETCHAR, 0, [0; 6])]);
+
+    let (byte, _) = with_reply(
+        SbiRet {
+            error: -1,
+            value: 0,
+        },
+        legacy::console_getchar,
+    );
+    assert_eq!(byte, None);
+
+    let (result, calls) = with_reply(SbiRet::success(0), || legacy::set_timer(1234));
+    assert!(result.is_ok());
+    assert_eq!(calls, [(legacy::SET_TIMER, 0, args([1234]))]);
+}
+
+#[test]
+fn dbcn_passes_length_then_address() {
+    let bytes = b"hello";
+    let (written, calls) = with_reply(SbiRet::success(3), || dbcn::console_write(bytes));
+    assert_eq!(written.unwrap(), 3);
+    assert_eq!(
+        calls,
+        [(
+            dbcn::EXTENSION_ID,
+            dbcn::fid::CONSOLE_WRITE,
+            args([5, bytes.as_ptr() as usize, 0])
+        )]
+    );
+}
+
+#[test]
+fn pmu_counter_info_is_decoded() {
+    let hardware = 63 << 12 | 0xc02;
+    let (info, _) = with_reply(SbiRet::success(hardware), || pmu::counter_get_info(2));
+    assert_eq!(
+        info.unwrap(),
+        CounterInfo::Hardware {
+            csr: 0xc02,
+            width: 64
+        }
+    );
+
+    let (info, _) = with_reply(SbiRet::success(1 << 63), || pmu::counter_get_info(9));
+    assert_eq!(info.unwrap(), CounterInfo::Firmware);
+}
+
+#[test]
+fn pmu_events_are_encoded() {
+    let event = Event::Cache(CacheId::L1Data, CacheOp::Write, CacheResult::Miss);
+    let (counter, calls) = with_reply(SbiRet::success(5), || {
+        pmu::counter_config_matching(3, 0b111, 0, event)
+    });
+    assert_eq!(counter.unwrap(), 5);
+    assert_eq!(
+        calls,
+        [(
+            pmu::EXTENSION_ID,
+            pmu::fid::COUNTER_CONFIG_MATCHING,
+            args([3, 0b111, 0, 1 << 16 | 0b011, 0])
+        )]
+    );
+
+    assert_eq!(Event::Hardware(HardwareEvent::Instructions).index(), 2);
+    assert_eq!(Event::Raw(0xdead).index(), 2 << 16);
+    assert_eq!(Event::Raw(0xdead).data(), 0xdead);
+}
+
+#[test]
+fn disabling_shared_memory_passes_all_ones() {
+    let (result, calls) = with_reply(SbiRet::success(0), || unsafe { sta::set_shmem(None) });
+    assert!(result.is_ok());
+    assert_eq!(calls[0].2, args([usize::MAX, usize::MAX, 0]));
+}
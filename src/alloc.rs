@@ -1,6 +1,9 @@
 use core::{arch::global_asm, ptr::null_mut};
 
-use crate::{print, println};
+use crate::{
+    print, println,
+    sync::{IrqSpinLock, level},
+};
 
 global_asm!(
     ".section .rodata
@@ -16,7 +19,10 @@ unsafe extern "C" {
     pub static HEAP_SIZE: usize;
 }
 
-static mut ALLOC_START: usize = 0;
+/// The page descriptors at HEAP_START are only touched with this held.
+/// Holds the address of the first allocatable page.
+static ALLOC_START: IrqSpinLock<usize> = IrqSpinLock::with_level(0, level::ALLOC);
+
 const PAGE_ORDER: usize = 12;
 pub const PAGE_SIZE: usize = 4096;
 
@@ -64,6 +70,7 @@ impl Page {
 }
 
 pub fn init() {
+    let mut alloc_start = ALLOC_START.lock();
     unsafe {
         let num_pages = HEAP_SIZE / PAGE_SIZE;
         let ptr = HEAP_START as *mut Page;
@@ -72,13 +79,14 @@ pub fn init() {
             (*ptr.add(i)).clear();
         }
 
-        ALLOC_START = align_val(HEAP_START + num_pages * size_of::<Page>(), PAGE_ORDER);
+        *alloc_start = align_val(HEAP_START + num_pages * size_of::<Page>(), PAGE_ORDER);
     }
 }
 
 pub fn alloc(pages: usize) -> *mut u8 {
     assert!(pages > 0);
 
+    let alloc_start = ALLOC_START.lock();
     unsafe {
         let num_pages = HEAP_SIZE / PAGE_SIZE;
         let ptr = HEAP_START as *mut Page;
@@ -102,7 +110,7 @@ pub fn alloc(pages: usize) -> *mut u8 {
                 (*ptr.add(i + pages - 1)).set_flag(PageBits::Taken);
                 (*ptr.add(i + pages - 1)).set_flag(PageBits::Last);
 
-                return (ALLOC_START + PAGE_SIZE * i) as *mut u8;
+                return (*alloc_start + PAGE_SIZE * i) as *mut u8;
             }
         }
     }
@@ -127,8 +135,9 @@ pub fn zalloc(pages: usize) -> *mut u8 {
 
 pub fn dealloc(ptr: *mut u8) {
     assert!(!ptr.is_null());
+    let alloc_start = ALLOC_START.lock();
     unsafe {
-        let addr = HEAP_START + (ptr as usize - ALLOC_START) / PAGE_SIZE;
+        let addr = HEAP_START + (ptr as usize - *alloc_start) / PAGE_SIZE;
 
         // Make sure the address is in a sensible range (i.e. in the heap)
         assert!(addr >= HEAP_START && addr < HEAP_START + HEAP_SIZE);
@@ -140,7 +149,7 @@ pub fn dealloc(ptr: *mut u8) {
         }
 
         assert!(
-            (*p).is_last() == true,
+            (*p).is_last(),
             "Possible double-free detected! (Not taken found before last)"
         );
 
@@ -151,12 +160,13 @@ pub fn dealloc(ptr: *mut u8) {
 /// Print all page allocations
 /// This is mainly used for debugging.
 pub fn print_page_allocations() {
+    let alloc_start = *ALLOC_START.lock();
     unsafe {
         let num_pages = HEAP_SIZE / PAGE_SIZE;
         let mut beg = HEAP_START as *const Page;
         let end = beg.add(num_pages);
-        let alloc_beg = ALLOC_START;
-        let alloc_end = ALLOC_START + num_pages * PAGE_SIZE;
+        let alloc_beg = alloc_start;
+        let alloc_end = alloc_start + num_pages * PAGE_SIZE;
         println!();
         println!(
             "PAGE ALLOCATION TABLE\nMETA: {:p} -> {:p}\nPHYS: \
@@ -168,13 +178,13 @@ pub fn print_page_allocations() {
         while beg < end {
             if (*beg).is_taken() {
                 let start = beg as usize;
-                let memaddr = ALLOC_START + (start - HEAP_START) * PAGE_SIZE;
+                let memaddr = alloc_start + (start - HEAP_START) * PAGE_SIZE;
                 print!("0x{:x} => ", memaddr);
                 loop {
                     num += 1;
                     if (*beg).is_last() {
                         let end = beg as usize;
-                        let memaddr = ALLOC_START + (end - HEAP_START) * PAGE_SIZE + PAGE_SIZE - 1;
+                        let memaddr = alloc_start + (end - HEAP_START) * PAGE_SIZE + PAGE_SIZE - 1;
                         print!("0x{:x}: {:>3} page(s)", memaddr, (end - start + 1));
                         println!(".");
                         break;
@@ -0,0 +1,27 @@
+//! Output for the time before the console is set up.
+//!
+//! Nothing in here touches memory besides the stack, so it is usable from the first
+//! instruction after `boot`, even before BSS is cleared.
+
+use core::fmt::{self, Write};
+
+use crate::sbi::{dbcn, legacy};
+
+pub struct EarlyConsole;
+
+impl Write for EarlyConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // We can't remember whether DBCN exists without using memory, so just try it.
+        if dbcn::console_write(s.as_bytes()).is_err() {
+            for b in s.bytes() {
+                legacy::console_putchar(b);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = EarlyConsole.write_fmt(args);
+}
@@ -0,0 +1,228 @@
+//! The kernel console.
+//!
+//! Output goes to one of several backends. At boot we pick whatever the firmware offers,
+//! so that we can print before the uart is mapped, and switch over to the uart once
+//! `page::init` and `uart::init` are done.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use crate::{
+    sbi::{
+        dbcn,
+        features::{self, Extension},
+        legacy,
+    },
+    sync::{IrqSpinLock, IrqSpinLockGuard, level},
+    uart::{self, Uart},
+};
+
+pub mod early;
+
+pub trait Console: Sync {
+    fn name(&self) -> &'static str;
+
+    fn write_bytes(&self, bytes: &[u8]);
+
+    fn read_byte(&self) -> Option<u8>;
+}
+
+impl Console for Uart {
+    fn name(&self) -> &'static str {
+        "ns16550a"
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        for &b in bytes {
+            self.put(b);
+        }
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        self.get()
+    }
+}
+
+/// The SBI v2.0 Debug Console extension
+pub struct SbiDebugConsole;
+
+impl Console for SbiDebugConsole {
+    fn name(&self) -> &'static str {
+        "sbi-dbcn"
+    }
+
+    fn write_bytes(&self, mut bytes: &[u8]) {
+        // the firmware may only take part of the buffer
+        while !bytes.is_empty() {
+            match dbcn::console_write(bytes) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => bytes = &bytes[n.min(bytes.len())..],
+            }
+        }
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        let mut buf = [0u8];
+        match dbcn::console_read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+}
+
+/// The legacy SBI `console_putchar` and `console_getchar` calls
+pub struct SbiLegacyConsole;
+
+impl Console for SbiLegacyConsole {
+    fn name(&self) -> &'static str {
+        "sbi-legacy"
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        for &b in bytes {
+            legacy::console_putchar(b);
+        }
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        legacy::console_getchar()
+    }
+}
+
+static UART: Uart = Uart::new(uart::UART_BASE);
+static SBI_DEBUG_CONSOLE: SbiDebugConsole = SbiDebugConsole;
+static SBI_LEGACY_CONSOLE: SbiLegacyConsole = SbiLegacyConsole;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    Uart = 0,
+    SbiDebugConsole = 1,
+    SbiLegacy = 2,
+}
+
+impl Backend {
+    fn from_u8(val: u8) -> Self {
+        match val {
+            1 => Backend::SbiDebugConsole,
+            2 => Backend::SbiLegacy,
+            _ => Backend::Uart,
+        }
+    }
+
+    pub fn console(&self) -> &'static dyn Console {
+        match self {
+            Backend::Uart => &UART,
+            Backend::SbiDebugConsole => &SBI_DEBUG_CONSOLE,
+            Backend::SbiLegacy => &SBI_LEGACY_CONSOLE,
+        }
+    }
+}
+
+static BACKEND: AtomicU8 = AtomicU8::new(Backend::Uart as u8);
+
+/// Serializes output, so that lines from different harts don't interleave.
+static LOCK: IrqSpinLock<()> = IrqSpinLock::with_level((), level::CONSOLE);
+
+/// Set once `init` ran. This lives in `.data` rather than `.bss`, so that it can be
+/// trusted before BSS is cleared.
+#[unsafe(link_section = ".data")]
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Picks a firmware console, which works before anything else is set up.
+/// Has to be called after BSS is cleared and the firmware's features are probed.
+pub fn init() {
+    let backend = if features::has(Extension::Dbcn) {
+        Backend::SbiDebugConsole
+    } else if features::has(Extension::LegacyConsolePutchar) {
+        Backend::SbiLegacy
+    } else {
+        // nothing better available, hope the uart is reachable
+        Backend::Uart
+    };
+    set_backend(backend);
+    READY.store(true, Ordering::Release);
+}
+
+/// Whether the full console is usable, if not the `early` console has to be used.
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire)
+}
+
+pub fn set_backend(backend: Backend) {
+    BACKEND.store(backend as u8, Ordering::Release);
+}
+
+pub fn backend() -> Backend {
+    Backend::from_u8(BACKEND.load(Ordering::Acquire))
+}
+
+pub fn get() -> &'static dyn Console {
+    backend().console()
+}
+
+pub fn read_byte() -> Option<u8> {
+    get().read_byte()
+}
+
+/// A locked handle to the console
+pub struct ConsoleGuard {
+    console: &'static dyn Console,
+    _guard: IrqSpinLockGuard<'static, ()>,
+}
+
+impl ConsoleGuard {
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        self.console.write_bytes(bytes);
+    }
+}
+
+impl Write for ConsoleGuard {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.console.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn lock() -> ConsoleGuard {
+    ConsoleGuard {
+        _guard: LOCK.lock(),
+        console: get(),
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    if is_ready() {
+        let _ = lock().write_fmt(args);
+    } else {
+        early::_print(args);
+    }
+}
+
+/// Prints without waiting for the console lock, which a panicking hart may be holding.
+///
+/// Falls back to the early console if the full console isn't up yet.
+pub fn force_print(args: fmt::Arguments) {
+    if !is_ready() {
+        early::_print(args);
+        return;
+    }
+
+    let console = get();
+    // Give the current owner a chance to finish its line, but don't wait forever.
+    let guard = (0..100_000).find_map(|_| LOCK.try_lock());
+    let _ = ForcedWriter(console).write_fmt(args);
+    drop(guard);
+}
+
+struct ForcedWriter(&'static dyn Console);
+
+impl Write for ForcedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
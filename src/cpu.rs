@@ -0,0 +1,99 @@
+use core::arch::asm;
+
+/// The most harts the kernel supports
+pub const MAX_HARTS: usize = 8;
+
+/// Returns the id of the hart we are running on.
+pub fn hart_id() -> usize {
+    crate::percpu::this_cpu_raw().hart_id()
+}
+
+/// Generates a module with accessors for a CSR
+macro_rules! csr {
+    ($name:ident) => {
+        pub mod $name {
+            use core::arch::asm;
+
+            #[inline(always)]
+            pub fn read() -> usize {
+                let value: usize;
+                unsafe {
+                    asm!(concat!("csrr {}, ", stringify!($name)), out(reg) value);
+                }
+                value
+            }
+
+            #[inline(always)]
+            pub fn write(value: usize) {
+                unsafe {
+                    asm!(concat!("csrw ", stringify!($name), ", {}"), in(reg) value);
+                }
+            }
+
+            /// Sets `bits` and returns the previous value
+            #[inline(always)]
+            pub fn set(bits: usize) -> usize {
+                let value: usize;
+                unsafe {
+                    asm!(concat!("csrrs {}, ", stringify!($name), ", {}"), out(reg) value, in(reg) bits);
+                }
+                value
+            }
+
+            /// Clears `bits` and returns the previous value
+            #[inline(always)]
+            pub fn clear(bits: usize) -> usize {
+                let value: usize;
+                unsafe {
+                    asm!(concat!("csrrc {}, ", stringify!($name), ", {}"), out(reg) value, in(reg) bits);
+                }
+                value
+            }
+        }
+    };
+}
+
+csr!(sstatus);
+csr!(sie);
+csr!(sip);
+csr!(stvec);
+csr!(sepc);
+csr!(scause);
+csr!(stval);
+csr!(sscratch);
+csr!(satp);
+
+pub const SSTATUS_SIE: usize = 1 << 1;
+pub const SSTATUS_SPIE: usize = 1 << 5;
+pub const SSTATUS_SPP: usize = 1 << 8;
+/// The FP unit's state, off, initial, clean or dirty
+pub const SSTATUS_FS: usize = 3 << 13;
+pub const SSTATUS_FS_INITIAL: usize = 1 << 13;
+/// Lets S-mode access U pages
+pub const SSTATUS_SUM: usize = 1 << 18;
+
+/// Interrupt numbers, as found in `scause` and used as bit positions in `sie`/`sip`
+pub const IRQ_S_SOFT: usize = 1;
+pub const IRQ_S_TIMER: usize = 5;
+pub const IRQ_S_EXT: usize = 9;
+pub const IRQ_COUNTER_OVERFLOW: usize = 13;
+
+pub fn enable_interrupts() {
+    sstatus::set(SSTATUS_SIE);
+}
+
+/// Disables interrupts and returns whether they were enabled before.
+pub fn disable_interrupts() -> bool {
+    sstatus::clear(SSTATUS_SIE) & SSTATUS_SIE != 0
+}
+
+pub fn interrupts_enabled() -> bool {
+    sstatus::read() & SSTATUS_SIE != 0
+}
+
+/// Waits for an interrupt
+pub fn wait() {
+    unsafe {
+        asm!("wfi");
+    }
+}
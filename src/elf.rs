@@ -0,0 +1,157 @@
+//! A reader for ELF64 executables, as far as loading them needs.
+//!
+//! Only little endian RISC-V executables are accepted, statically linked ones
+//! (`ET_EXEC`) and position independent ones (`ET_DYN`).
+
+use core::fmt;
+
+pub const EM_RISCV: u16 = 243;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+pub const PT_PHDR: u32 = 6;
+
+/// Segment permissions in `p_flags`
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Header {
+    pub ident: [u8; 16],
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    NotElf64,
+    NotLittleEndian,
+    WrongMachine(u16),
+    NotExecutable(u16),
+    BadProgramHeaders,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort => write!(f, "file too short"),
+            Self::BadMagic => write!(f, "not an ELF file"),
+            Self::NotElf64 => write!(f, "not a 64 bit ELF file"),
+            Self::NotLittleEndian => write!(f, "not little endian"),
+            Self::WrongMachine(m) => write!(f, "machine {} isn't RISC-V", m),
+            Self::NotExecutable(t) => write!(f, "type {} isn't an executable", t),
+            Self::BadProgramHeaders => write!(f, "program headers out of bounds"),
+        }
+    }
+}
+
+fn read<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(size_of::<T>())?;
+    let bytes = data.get(offset..end)?;
+    Some(unsafe { (bytes.as_ptr() as *const T).read_unaligned() })
+}
+
+#[derive(Clone, Copy)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header: Header = read(data, 0).ok_or(ElfError::TooShort)?;
+        if header.ident[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != ELFCLASS64 {
+            return Err(ElfError::NotElf64);
+        }
+        if header.ident[5] != ELFDATA2LSB {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if header.e_machine != EM_RISCV {
+            return Err(ElfError::WrongMachine(header.e_machine));
+        }
+        if header.e_type != ET_EXEC && header.e_type != ET_DYN {
+            return Err(ElfError::NotExecutable(header.e_type));
+        }
+
+        let table_size = header.e_phnum as usize * header.e_phentsize as usize;
+        if (header.e_phentsize as usize) < size_of::<ProgramHeader>()
+            || (header.e_phoff as usize)
+                .checked_add(table_size)
+                .is_none_or(|end| end > data.len())
+        {
+            return Err(ElfError::BadProgramHeaders);
+        }
+        Ok(Self { data, header })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Whether the program can be loaded anywhere
+    pub fn is_pie(&self) -> bool {
+        self.header.e_type == ET_DYN
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        let offset = self.header.e_phoff as usize;
+        let size = self.header.e_phentsize as usize;
+        (0..self.header.e_phnum as usize).filter_map(move |i| read(self.data, offset + i * size))
+    }
+
+    /// The contents of a segment in the file, `None` if it doesn't fit in the file
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let start = ph.p_offset as usize;
+        self.data
+            .get(start..start.checked_add(ph.p_filesz as usize)?)
+    }
+
+    /// The path of the dynamic linker the program asks for, if any
+    pub fn interpreter(&self) -> Option<&'a [u8]> {
+        let ph = self.program_headers().find(|ph| ph.p_type == PT_INTERP)?;
+        let path = self.segment_data(&ph)?;
+        Some(path.split(|&b| b == 0).next().unwrap_or(path))
+    }
+}
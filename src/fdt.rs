@@ -0,0 +1,276 @@
+//! A minimal reader for the flattened device tree handed to us by the firmware in `a1`.
+//!
+//! Only what the kernel needs at boot is supported: walking nodes by path and reading
+//! properties. The blob lives in RAM that the page allocator does not know about, so
+//! anything needed after boot should be copied out during `init`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+static FDT_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init(addr: usize) {
+    FDT_ADDR.store(addr, Ordering::Relaxed);
+}
+
+/// Returns the device tree passed in by the firmware, if it is valid.
+pub fn get() -> Option<Fdt> {
+    unsafe { Fdt::from_ptr(FDT_ADDR.load(Ordering::Relaxed) as *const u8) }
+}
+
+/// The physical address range of the blob
+pub fn range() -> Option<(usize, usize)> {
+    let addr = FDT_ADDR.load(Ordering::Relaxed);
+    get().map(|fdt| (addr, addr + fdt.total_size))
+}
+
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+const fn align4(val: usize) -> usize {
+    (val + 3) & !3
+}
+
+fn cstr(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt {
+    total_size: usize,
+    structs: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl Fdt {
+    /// # Safety
+    /// `ptr` must be null or point to a device tree blob that stays mapped and unmodified.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Option<Self> {
+        if ptr.is_null() {
+            return None;
+        }
+
+        let header = unsafe { core::slice::from_raw_parts(ptr, 40) };
+        if be32(header, 0) != FDT_MAGIC {
+            return None;
+        }
+
+        let total_size = be32(header, 4) as usize;
+        let off_struct = be32(header, 8) as usize;
+        let off_strings = be32(header, 12) as usize;
+        let size_strings = be32(header, 32) as usize;
+        let size_struct = be32(header, 36) as usize;
+
+        let blob = unsafe { core::slice::from_raw_parts(ptr, total_size) };
+
+        Some(Self {
+            total_size,
+            structs: blob.get(off_struct..off_struct + size_struct)?,
+            strings: blob.get(off_strings..off_strings + size_strings)?,
+        })
+    }
+
+    pub fn root(&self) -> Node {
+        // The root node is the first token in the structure block.
+        self.node_at(0).expect("device tree has no root node")
+    }
+
+    /// Finds a node by its absolute path, e.g. `/chosen` or `/cpus/cpu@0`.
+    /// A path component without a unit address matches any unit address.
+    pub fn find_node(&self, path: &str) -> Option<Node> {
+        let mut node = self.root();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| child.matches(component))?;
+        }
+        Some(node)
+    }
+
+    /// Returns every node in the tree, in depth-first order.
+    pub fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            loop {
+                let token = be32(self.structs, offset);
+                match token {
+                    FDT_BEGIN_NODE => {
+                        let node = self.node_at(offset)?;
+                        offset = node.body;
+                        return Some(node);
+                    }
+                    FDT_PROP => {
+                        let len = be32(self.structs, offset + 4) as usize;
+                        offset += 12 + align4(len);
+                    }
+                    FDT_END_NODE | FDT_NOP => offset += 4,
+                    _ => return None,
+                }
+            }
+        })
+    }
+
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node> {
+        self.nodes()
+            .find(|node| node.property_u32("phandle") == Some(phandle))
+    }
+
+    fn node_at(&self, offset: usize) -> Option<Node> {
+        if be32(self.structs, offset) != FDT_BEGIN_NODE {
+            return None;
+        }
+
+        let name = cstr(&self.structs[offset + 4..]);
+        Some(Node {
+            fdt: *self,
+            name: core::str::from_utf8(name).ok()?,
+            body: offset + 4 + align4(name.len() + 1),
+        })
+    }
+
+    fn string_at(&self, offset: usize) -> &'static [u8] {
+        cstr(&self.strings[offset..])
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node {
+    fdt: Fdt,
+    name: &'static str,
+    /// Offset of the first token after the node name
+    body: usize,
+}
+
+impl Node {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The node name without its unit address
+    pub fn base_name(&self) -> &'static str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    fn matches(&self, component: &str) -> bool {
+        if component.contains('@') {
+            self.name == component
+        } else {
+            self.base_name() == component
+        }
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = (&'static [u8], &'static [u8])> + use<> {
+        let fdt = self.fdt;
+        let mut offset = self.body;
+        core::iter::from_fn(move || {
+            loop {
+                match be32(fdt.structs, offset) {
+                    FDT_PROP => {
+                        let len = be32(fdt.structs, offset + 4) as usize;
+                        let name_off = be32(fdt.structs, offset + 8) as usize;
+                        let value = &fdt.structs[offset + 12..offset + 12 + len];
+                        offset += 12 + align4(len);
+                        return Some((fdt.string_at(name_off), value));
+                    }
+                    FDT_NOP => offset += 4,
+                    _ => return None,
+                }
+            }
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'static [u8]> {
+        self.properties()
+            .find(|(n, _)| *n == name.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        let value = self.property(name)?;
+        (value.len() >= 4).then(|| be32(value, 0))
+    }
+
+    /// Reads a property that is either one or two cells wide.
+    pub fn property_usize(&self, name: &str) -> Option<usize> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 => Some(be32(value, 0) as usize),
+            8 => Some(((be32(value, 0) as usize) << 32) | be32(value, 4) as usize),
+            _ => None,
+        }
+    }
+
+    pub fn property_str(&self, name: &str) -> Option<&'static str> {
+        core::str::from_utf8(cstr(self.property(name)?)).ok()
+    }
+
+    /// Checks the null separated `compatible` string list.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").is_some_and(|list| {
+            list.split(|&b| b == 0)
+                .any(|entry| entry == compatible.as_bytes())
+        })
+    }
+
+    /// Returns the first `(address, size)` pair of the `reg` property, assuming the
+    /// usual `#address-cells = <2>` and `#size-cells = <2>` of the riscv virt machine.
+    pub fn reg(&self) -> Option<(usize, usize)> {
+        let value = self.property("reg")?;
+        if value.len() < 16 {
+            return None;
+        }
+        let addr = ((be32(value, 0) as usize) << 32) | be32(value, 4) as usize;
+        let size = ((be32(value, 8) as usize) << 32) | be32(value, 12) as usize;
+        Some((addr, size))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Node> + use<> {
+        let fdt = self.fdt;
+        let mut offset = self.body;
+        let mut depth = 0usize;
+        core::iter::from_fn(move || {
+            loop {
+                match be32(fdt.structs, offset) {
+                    FDT_BEGIN_NODE => {
+                        let node = fdt.node_at(offset)?;
+                        offset = node.body;
+                        depth += 1;
+                        if depth == 1 {
+                            return Some(node);
+                        }
+                    }
+                    FDT_END_NODE => {
+                        if depth == 0 {
+                            return None;
+                        }
+                        depth -= 1;
+                        offset += 4;
+                    }
+                    FDT_PROP => {
+                        let len = be32(fdt.structs, offset + 4) as usize;
+                        offset += 12 + align4(len);
+                    }
+                    FDT_NOP => offset += 4,
+                    FDT_END => return None,
+                    _ => return None,
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,101 @@
+//! Cooperation with the hypervisor when we run as a guest.
+//!
+//! Steal time tells the scheduler how long a hart was runnable but not running because
+//! the host scheduled something else, CPPC lets us ask the host for performance levels.
+//! Both are optional: without the extensions every call here is a cheap no-op.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    cpu::{self, MAX_HARTS},
+    debug, info,
+    sbi::{
+        SbiErrorType, SbiResult,
+        cppc::{self, Register},
+        features::{self, Extension},
+        sta::{self, StealTime},
+    },
+    warn,
+};
+
+struct StealTimeAreas([UnsafeCell<StealTime>; MAX_HARTS]);
+
+// Each hart only registers its own area, afterwards only the hypervisor writes to it.
+unsafe impl Sync for StealTimeAreas {}
+
+static STEAL_TIME: StealTimeAreas =
+    StealTimeAreas([const { UnsafeCell::new(StealTime::new()) }; MAX_HARTS]);
+
+static STEAL_TIME_ENABLED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+/// Logs what the hypervisor offers. Called once on the boot hart.
+pub fn init() {
+    if !features::has(Extension::Sta) {
+        info!("no steal-time accounting");
+    }
+
+    if features::has(Extension::Cppc) {
+        let read = |reg| cppc::read(reg).unwrap_or(0);
+        info!(
+            "CPPC performance levels: lowest {}, nominal {}, highest {}",
+            read(Register::LowestPerformance),
+            read(Register::NominalPerformance),
+            read(Register::HighestPerformance)
+        );
+    } else {
+        info!("no CPPC performance control");
+    }
+
+    init_hart();
+}
+
+/// Registers the calling hart's steal-time area. Every hart has to call this.
+pub fn init_hart() {
+    let hart = cpu::hart_id();
+    if !features::has(Extension::Sta) || hart >= MAX_HARTS {
+        return;
+    }
+
+    match unsafe { sta::set_shmem(Some(STEAL_TIME.0[hart].get())) } {
+        Ok(()) => {
+            STEAL_TIME_ENABLED[hart].store(true, Ordering::Release);
+            debug!("steal-time accounting enabled");
+        }
+        Err(e) => warn!("failed to register steal-time memory: {}", e),
+    }
+}
+
+/// Total time stolen from the calling hart, zero if the hypervisor doesn't tell us.
+pub fn steal_time() -> Duration {
+    let hart = cpu::hart_id();
+    if hart >= MAX_HARTS || !STEAL_TIME_ENABLED[hart].load(Ordering::Acquire) {
+        return Duration::ZERO;
+    }
+
+    let area = unsafe { &*STEAL_TIME.0[hart].get() };
+    Duration::from_nanos(area.steal())
+}
+
+/// Reads a CPPC register, `NotSupported` if the hypervisor doesn't implement CPPC.
+pub fn read_performance_register(register: Register) -> SbiResult<usize> {
+    if !features::has(Extension::Cppc) {
+        return Err(SbiErrorType::NotSupported.into());
+    }
+    if cppc::probe(register)? == 0 {
+        return Err(SbiErrorType::NotSupported.into());
+    }
+    cppc::read(register)
+}
+
+/// Asks for the calling hart to run at performance `level`, between the lowest and
+/// highest performance the hypervisor reports.
+pub fn request_performance(level: u64) -> SbiResult<()> {
+    if !features::has(Extension::Cppc) {
+        return Err(SbiErrorType::NotSupported.into());
+    }
+    cppc::write(Register::DesiredPerformance, level)
+}
@@ -1,17 +1,34 @@
-use crate::page;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::{alloc::zalloc, page};
+
+/// Number of pages reserved for the kernel heap
+const KMEM_ALLOC: usize = 64;
+
+static KMEM_HEAD: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static KMEM_NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static KMEM_PAGE_TABLE: AtomicPtr<page::Table> = AtomicPtr::new(core::ptr::null_mut());
 
 pub fn init() {
-    todo!()
+    let head = zalloc(KMEM_ALLOC);
+    assert!(!head.is_null(), "out of memory for the kernel heap");
+    KMEM_HEAD.store(head, Ordering::Relaxed);
+    KMEM_NUM_ALLOCATIONS.store(KMEM_ALLOC, Ordering::Relaxed);
+
+    let table = zalloc(1) as *mut page::Table;
+    assert!(!table.is_null(), "out of memory for the kernel page table");
+    KMEM_PAGE_TABLE.store(table, Ordering::Relaxed);
 }
 
+/// The root of the kernel's page table
 pub fn get_page_table() -> *mut page::Table {
-    todo!()
+    KMEM_PAGE_TABLE.load(Ordering::Relaxed)
 }
 
 pub fn get_head() -> *mut u8 {
-    todo!()
+    KMEM_HEAD.load(Ordering::Relaxed)
 }
 
 pub fn get_num_allocations() -> usize {
-    todo!()
+    KMEM_NUM_ALLOCATIONS.load(Ordering::Relaxed)
 }
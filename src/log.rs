@@ -0,0 +1,299 @@
+//! Kernel logging.
+//!
+//! Records are printed to the console prefixed with the uptime and the hart id, and
+//! are also kept in an in-memory ring buffer that can be dumped later with [`dmesg`].
+//!
+//! The level can be configured per module on the kernel command line:
+//! `log=<level>[,<module>=<level>...]`, e.g. `log=info,page=trace,sbi::base=off`.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    console::{self, ConsoleGuard},
+    cpu, fdt,
+    sync::{IrqSpinLock, level},
+    time, warn,
+};
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+const LEVEL_OFF: u8 = 0;
+const DEFAULT_LEVEL: u8 = Level::Info as u8;
+
+fn parse_level(s: &str) -> Option<u8> {
+    match s {
+        "off" => Some(LEVEL_OFF),
+        "error" => Some(Level::Error as u8),
+        "warn" => Some(Level::Warn as u8),
+        "info" => Some(Level::Info as u8),
+        "debug" => Some(Level::Debug as u8),
+        "trace" => Some(Level::Trace as u8),
+        _ => None,
+    }
+}
+
+const MAX_DIRECTIVES: usize = 8;
+const MAX_MODULE_LEN: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Directive {
+    module: [u8; MAX_MODULE_LEN],
+    len: usize,
+    level: u8,
+}
+
+impl Directive {
+    const EMPTY: Self = Self {
+        module: [0; MAX_MODULE_LEN],
+        len: 0,
+        level: LEVEL_OFF,
+    };
+
+    fn module(&self) -> &str {
+        // only ever filled from a &str, cut at a char boundary
+        core::str::from_utf8(&self.module[..self.len]).unwrap_or("")
+    }
+
+    /// Checks if `path` is the directive's module or one of its children.
+    fn matches(&self, path: &str) -> bool {
+        let module = self.module();
+        path.strip_prefix(module)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Filter {
+    default: u8,
+    directives: [Directive; MAX_DIRECTIVES],
+    len: usize,
+}
+
+impl Filter {
+    fn level_for(&self, path: &str) -> u8 {
+        self.directives[..self.len]
+            .iter()
+            .filter(|d| d.matches(path))
+            .max_by_key(|d| d.len)
+            .map_or(self.default, |d| d.level)
+    }
+
+    fn max_level(&self) -> u8 {
+        self.directives[..self.len]
+            .iter()
+            .map(|d| d.level)
+            .fold(self.default, u8::max)
+    }
+
+    fn parse(&mut self, spec: &str) {
+        for part in spec.split(',').filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                None => match parse_level(part) {
+                    Some(level) => self.default = level,
+                    None => warn!("unknown log level '{}'", part),
+                },
+                Some((module, level)) => {
+                    let Some(level) = parse_level(level) else {
+                        warn!("unknown log level '{}' for '{}'", level, module);
+                        continue;
+                    };
+                    let module = module.strip_prefix("tos::").unwrap_or(module);
+                    if self.len == MAX_DIRECTIVES || module.len() > MAX_MODULE_LEN {
+                        warn!("ignoring log directive '{}'", part);
+                        continue;
+                    }
+                    let directive = &mut self.directives[self.len];
+                    directive.module[..module.len()].copy_from_slice(module.as_bytes());
+                    directive.len = module.len();
+                    directive.level = level;
+                    self.len += 1;
+                }
+            }
+        }
+    }
+}
+
+static FILTER: IrqSpinLock<Filter> = IrqSpinLock::new(Filter {
+    default: DEFAULT_LEVEL,
+    directives: [Directive::EMPTY; MAX_DIRECTIVES],
+    len: 0,
+});
+
+/// Highest level any module has enabled, checked before taking the filter lock.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL);
+
+const RING_SIZE: usize = 16 * 1024;
+
+/// The dmesg buffer. Once full, the oldest bytes are overwritten.
+struct Ring {
+    buf: [u8; RING_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.buf[self.head] = b;
+            self.head = (self.head + 1) % RING_SIZE;
+            self.len = usize::min(self.len + 1, RING_SIZE);
+        }
+    }
+
+    /// Returns the contents in order, as two slices because the buffer may wrap around.
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        let start = (self.head + RING_SIZE - self.len) % RING_SIZE;
+        if start + self.len <= RING_SIZE {
+            (&self.buf[start..start + self.len], &[])
+        } else {
+            (&self.buf[start..], &self.buf[..self.head])
+        }
+    }
+}
+
+static RING: IrqSpinLock<Ring> = IrqSpinLock::with_level(
+    Ring {
+        buf: [0; RING_SIZE],
+        head: 0,
+        len: 0,
+    },
+    level::LOG_RING,
+);
+
+/// Reads the log configuration from the `/chosen/bootargs` kernel command line.
+pub fn init() {
+    let bootargs = fdt::get()
+        .and_then(|fdt| fdt.find_node("/chosen"))
+        .and_then(|chosen| chosen.property_str("bootargs"));
+
+    let Some(bootargs) = bootargs else {
+        return;
+    };
+
+    for arg in bootargs.split_whitespace() {
+        if let Some(spec) = arg.strip_prefix("log=") {
+            // parse into a copy, since warnings about the spec need the filter themselves
+            let mut filter = *FILTER.lock();
+            filter.parse(spec);
+            MAX_LEVEL.store(filter.max_level(), Ordering::Relaxed);
+            *FILTER.lock() = filter;
+        }
+    }
+}
+
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+    let path = module_path.strip_prefix("tos::").unwrap_or("");
+    level as u8 <= FILTER.lock().level_for(path)
+}
+
+/// Writes every byte both to the console and into the dmesg buffer.
+struct Tee<'a> {
+    ring: &'a mut Ring,
+    console: &'a mut ConsoleGuard,
+}
+
+impl Write for Tee<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.ring.push(s.as_bytes());
+        self.console.write_str(s)
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &str, args: fmt::Arguments) {
+    let uptime = time::uptime();
+    let module = module_path.strip_prefix("tos::").unwrap_or(module_path);
+
+    let mut ring = RING.lock();
+    let mut console = console::lock();
+    let mut tee = Tee {
+        ring: &mut ring,
+        console: &mut console,
+    };
+    let _ = write!(
+        tee,
+        "[{:>5}.{:06}] {} {:<5} {}: {}\r\n",
+        uptime.as_secs(),
+        uptime.subsec_micros(),
+        cpu::hart_id(),
+        level.as_str(),
+        module,
+        args
+    );
+}
+
+/// Prints the contents of the log ring buffer
+pub fn dmesg() {
+    let ring = RING.lock();
+    let (first, second) = ring.as_slices();
+    let console = console::lock();
+    console.write_bytes(first);
+    console.write_bytes(second);
+}
+
+#[macro_export]
+macro_rules! log
+{
+	($level:expr, $($args:tt)+) => ({
+		let level = $level;
+		if $crate::log::enabled(level, module_path!()) {
+			$crate::log::_log(level, module_path!(), format_args!($($args)+));
+		}
+	});
+}
+
+#[macro_export]
+macro_rules! error
+{
+	($($args:tt)+) => ($crate::log!($crate::log::Level::Error, $($args)+));
+}
+
+#[macro_export]
+macro_rules! warn
+{
+	($($args:tt)+) => ($crate::log!($crate::log::Level::Warn, $($args)+));
+}
+
+#[macro_export]
+macro_rules! info
+{
+	($($args:tt)+) => ($crate::log!($crate::log::Level::Info, $($args)+));
+}
+
+#[macro_export]
+macro_rules! debug
+{
+	($($args:tt)+) => ($crate::log!($crate::log::Level::Debug, $($args)+));
+}
+
+#[macro_export]
+macro_rules! trace
+{
+	($($args:tt)+) => ($crate::log!($crate::log::Level::Trace, $($args)+));
+}
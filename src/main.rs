@@ -1,12 +1,34 @@
 #![no_std]
 #![no_main]
 
-use core::arch::{asm, naked_asm};
+use core::{
+    arch::{asm, naked_asm},
+    sync::atomic::AtomicU32,
+};
 
 pub mod alloc;
+pub mod console;
+pub mod cpu;
+pub mod elf;
+pub mod fdt;
+pub mod guest;
 pub mod kmem;
+pub mod log;
 pub mod page;
+pub mod percpu;
+pub mod perf;
+pub mod power;
+pub mod process;
+pub mod random;
 pub mod sbi;
+pub mod sched;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
+pub mod thread;
+pub mod time;
+pub mod trap;
+pub mod uaccess;
 pub mod uart;
 
 unsafe extern "C" {
@@ -15,6 +37,19 @@ unsafe extern "C" {
     static __stack_end: *mut u8;
 }
 
+/// Cleared by the first hart that enters [`boot`]. In `.data`, zeroing `.bss` mustn't
+/// reopen it.
+static BOOT_UNCLAIMED: AtomicU32 = AtomicU32::new(1);
+
+/// The kernel entry point, jumped to by the firmware with the hart id in `a0`
+/// and the device tree in `a1`.
+///
+/// Some firmware sends every hart here. Only the first to arrive boots, on the boot
+/// stack. The others stop through HSM so that [`smp::init`] can start them later, or
+/// wait for interrupts forever if the firmware has no HSM.
+///
+/// # Safety
+/// Must only be entered once per hart, by the firmware.
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn boot() {
@@ -24,12 +59,29 @@ pub unsafe extern "C" fn boot() {
         la gp, __global_pointer;
         .option pop;
 
+        la t0, {unclaimed};
+        amoswap.w.aq t0, zero, (t0);
+        beqz t0, 2f;
+
         la t0, __stack_end;
         mv sp, t0;
-        j kernel_main"
+        mv tp, zero;
+        j kernel_main;
+
+    2:  csrw sie, zero;
+        li a7, {hsm};
+        li a6, {hart_stop};
+        ecall;
+    3:  wfi;
+        j 3b",
+        unclaimed = sym BOOT_UNCLAIMED,
+        hsm = const sbi::hsm::EXTENSION_ID,
+        hart_stop = const sbi::hsm::fid::HART_STOP,
     );
 }
 
+/// # Safety
+/// `buf` must be valid for writes of `n` bytes.
 pub unsafe fn memset(buf: *mut u8, c: u8, n: usize) -> *mut u8 {
     let mut i = 0;
     while i < n {
@@ -39,15 +91,14 @@ pub unsafe fn memset(buf: *mut u8, c: u8, n: usize) -> *mut u8 {
         i += 1;
     }
 
-    return buf;
+    buf
 }
 
 #[macro_export]
 macro_rules! print
 {
 	($($args:tt)+) => ({
-			use core::fmt::Write;
-			let _ = write!(crate::uart::Uart::new(0x1000_0000), $($args)+);
+			$crate::console::_print(format_args!($($args)+));
 	});
 }
 
@@ -55,23 +106,51 @@ macro_rules! print
 macro_rules! println
 {
 	() => ({
-		crate::print!("\r\n")
+		$crate::print!("\r\n")
 	});
 	($fmt:expr) => ({
-		crate::print!(concat!($fmt, "\r\n"))
+		$crate::print!(concat!($fmt, "\r\n"))
 	});
 	($fmt:expr, $($args:tt)+) => ({
-		crate::print!(concat!($fmt, "\r\n"), $($args)+)
+		$crate::print!(concat!($fmt, "\r\n"), $($args)+)
+	});
+}
+
+/// Prints through the firmware, usable before BSS is cleared.
+#[macro_export]
+macro_rules! early_print
+{
+	($($args:tt)+) => ({
+			$crate::console::early::_print(format_args!($($args)+));
+	});
+}
+
+#[macro_export]
+macro_rules! early_println
+{
+	() => ({
+		$crate::early_print!("\r\n")
+	});
+	($fmt:expr) => ({
+		$crate::early_print!(concat!($fmt, "\r\n"))
+	});
+	($fmt:expr, $($args:tt)+) => ({
+		$crate::early_print!(concat!($fmt, "\r\n"), $($args)+)
 	});
 }
 
 #[panic_handler]
 pub fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-    print!("Aborting: ");
+    // The console may not be set up yet, or we may have panicked while holding its lock.
     if let Some(p) = info.location() {
-        println!("line {}, file {}: {}", p.line(), p.file(), info.message());
+        console::force_print(format_args!(
+            "Aborting: line {}, file {}: {}\r\n",
+            p.line(),
+            p.file(),
+            info.message()
+        ));
     } else {
-        println!("no information available.");
+        console::force_print(format_args!("Aborting: no information available.\r\n"));
     }
     abort();
 }
@@ -86,20 +165,41 @@ extern "C" fn abort() -> ! {
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn kernel_main() -> ! {
-    // make sure only hw thread 0 is running
+pub extern "C" fn kernel_main(hart_id: usize, dtb: usize) -> ! {
+    early_println!("tOS: booting on hart {}", hart_id);
 
     unsafe { memset(__bss_start, 0, __bss_end as usize - __bss_start as usize) };
+    percpu::init_boot(hart_id, unsafe { page::STACK_END });
+
+    sbi::features::init();
+    console::init();
+    fdt::init(dtb);
+    time::init();
+    random::init();
+    log::init();
 
     alloc::init();
     kmem::init();
+    page::init();
+    power::init();
     uart::init();
-
-    let version = sbi::base::get_spec_version().unwrap();
-
-    println!("Hello, tOS!");
-
-    println!("SBI spec version: {}.{}", version.0, version.1);
+    console::set_backend(console::Backend::Uart);
+
+    percpu::init_hart();
+    trap::init();
+    trap::misaligned::init();
+    trap::events::init();
+    smp::call::init_hart();
+    thread::init_hart();
+    sched::init_hart();
+    cpu::enable_interrupts();
+    perf::init();
+    guest::init();
+    smp::init();
+
+    info!("Hello, tOS!");
+
+    sbi::features::report();
     alloc::alloc(10);
     alloc::alloc(1);
     alloc::alloc(1);
@@ -108,5 +208,6 @@ pub extern "C" fn kernel_main() -> ! {
 
     alloc::print_page_allocations();
 
-    abort()
+    process::init();
+    sched::idle_loop()
 }
@@ -2,7 +2,8 @@ use core::arch::{asm, global_asm};
 
 use crate::{
     alloc::{HEAP_SIZE, HEAP_START, PAGE_SIZE, align_val, dealloc, zalloc},
-    kmem, println,
+    fdt, kmem, println,
+    smp::tlb,
 };
 
 global_asm!(
@@ -54,8 +55,7 @@ unsafe extern "C" {
 
 pub fn init() {
     let root_ptr = kmem::get_page_table();
-    let root_u = root_ptr as usize;
-    let mut root = unsafe { root_ptr.as_mut().unwrap() };
+    let root = unsafe { root_ptr.as_mut().unwrap() };
     let kheap_head = kmem::get_head() as usize;
     let total_pages = kmem::get_num_allocations();
     println!();
@@ -73,17 +73,18 @@ pub fn init() {
         );
     }
     id_map_range(
-        &mut root,
+        root,
         kheap_head,
         kheap_head + total_pages * 4096,
         EntryBits::ReadWrite as i64,
     );
     unsafe {
-        let num_pages = HEAP_SIZE / PAGE_SIZE;
+        // The page descriptors and every page the allocator can hand out, so that
+        // memory allocated after paging is turned on is usable by the kernel.
         id_map_range(
             root,
             HEAP_START,
-            HEAP_START + num_pages,
+            HEAP_START + HEAP_SIZE,
             EntryBits::ReadWrite as i64,
         );
         id_map_range(root, TEXT_START, TEXT_END, EntryBits::ReadExecute as i64);
@@ -107,10 +108,24 @@ pub fn init() {
         0,
     );
 
-    let root_ppn = root_u >> 12;
-    let satp_val = 8 << 60 | root_ppn;
+    // The device tree is still read after boot, e.g. by drivers looking for their nodes.
+    if let Some((start, end)) = fdt::range() {
+        id_map_range(root, start, end, EntryBits::Read as i64);
+    }
+
+    init_hart();
+}
+
+/// Turns on paging with the kernel page table. Every hart has to call this.
+pub fn init_hart() {
+    activate(kmem::get_page_table());
+}
+
+/// Switches the calling hart to the Sv39 page table `root`.
+pub fn activate(root: *mut Table) {
+    let satp_val = 8 << 60 | root as usize >> 12;
     unsafe {
-        asm!("csrw satp, {}", in(reg) satp_val);
+        asm!("csrw satp, {}", "sfence.vma", in(reg) satp_val);
     }
 }
 
@@ -120,11 +135,17 @@ pub enum EntryBits {
     Read = 1 << 1,
     Write = 1 << 2,
     Execute = 1 << 3,
+    User = 1 << 4,
+    Global = 1 << 5,
     Accessed = 1 << 6,
     Dirty = 1 << 7,
 
     ReadWrite = Self::Read as i64 | Self::Write as i64,
     ReadExecute = Self::Read as i64 | Self::Execute as i64,
+
+    UserRead = Self::User as i64 | Self::Read as i64,
+    UserReadWrite = Self::User as i64 | Self::Read as i64 | Self::Write as i64,
+    UserReadExecute = Self::User as i64 | Self::Read as i64 | Self::Execute as i64,
 }
 pub struct Entry {
     pub entry: i64,
@@ -167,18 +188,27 @@ impl Table {
 }
 
 pub fn map(root: &mut Table, vaddr: usize, paddr: usize, bits: i64, level: usize) {
+    assert!(
+        try_map(root, vaddr, paddr, bits, level),
+        "out of memory for a page table"
+    );
+}
+
+/// Like [`map`], but returns false instead of panicking if a table on the way to the
+/// entry couldn't be allocated.
+pub fn try_map(root: &mut Table, vaddr: usize, paddr: usize, bits: i64, level: usize) -> bool {
     assert!(bits & 0xe != 0);
 
     let vpn = [
         (vaddr >> 12) & 0x1ff,
-        (vaddr >> 22) & 0x1ff,
+        (vaddr >> 21) & 0x1ff,
         (vaddr >> 30) & 0x1ff,
     ];
 
     let ppn = [
         (paddr >> 12) & 0x1ff,
-        (paddr >> 22) & 0x1ff,
-        (paddr >> 30) & 0x1ff,
+        (paddr >> 21) & 0x1ff,
+        (paddr >> 30) & 0x3ff_ffff,
     ];
 
     let mut v = &mut root.entries[vpn[2]];
@@ -186,7 +216,9 @@ pub fn map(root: &mut Table, vaddr: usize, paddr: usize, bits: i64, level: usize
     for i in (level..2).rev() {
         if !v.is_valid() {
             let page = zalloc(1);
-
+            if page.is_null() {
+                return false;
+            }
             v.set_entry((page as i64 >> 2) | EntryBits::Valid as i64);
         }
 
@@ -201,16 +233,17 @@ pub fn map(root: &mut Table, vaddr: usize, paddr: usize, bits: i64, level: usize
         | EntryBits::Valid as i64;
 
     v.set_entry(entry);
+    true
 }
 
+/// Frees all the branch tables of `root`. Other harts may still have cached some of
+/// its translations, so they get flushed.
 pub fn unmap(root: &mut Table) {
-    for lv2 in 0..Table::len() {
-        let entry_lv2 = &root.entries[lv2];
+    for entry_lv2 in root.entries.iter() {
         if entry_lv2.is_valid() && entry_lv2.is_branch() {
             let memaddr_lv1 = (entry_lv2.get_entry() & !0x3ff) << 2;
             let table_lv1 = unsafe { (memaddr_lv1 as *mut Table).as_mut().unwrap() };
-            for lv1 in 0..Table::len() {
-                let entry_lv1 = &table_lv1.entries[lv1];
+            for entry_lv1 in table_lv1.entries.iter() {
                 if entry_lv1.is_valid() && entry_lv1.is_branch() {
                     let memaddr_lv0 = (entry_lv1.get_entry() & !0x3ff) << 2;
                     dealloc(memaddr_lv0 as *mut u8);
@@ -219,12 +252,60 @@ pub fn unmap(root: &mut Table) {
             dealloc(memaddr_lv1 as *mut u8);
         }
     }
+    tlb::flush_all();
+}
+
+/// Removes the mappings of `start..end` and flushes them from every hart's TLB.
+/// Leaves that only partly overlap the range are removed as a whole.
+pub fn unmap_range(root: &mut Table, start: usize, end: usize) {
+    let mut vaddr = start & !(PAGE_SIZE - 1);
+    while vaddr < end {
+        let vpn = [
+            (vaddr >> 12) & 0x1ff,
+            (vaddr >> 21) & 0x1ff,
+            (vaddr >> 30) & 0x1ff,
+        ];
+
+        let mut v = &mut root.entries[vpn[2]];
+        let mut level = 2;
+        while v.is_valid() && v.is_branch() && level > 0 {
+            let entry = ((v.get_entry() & !0x3ff) << 2) as *mut Entry;
+            level -= 1;
+            v = unsafe { entry.add(vpn[level]).as_mut().unwrap() };
+        }
+
+        let leaf_size = 1 << (12 + level * 9);
+        if v.is_valid() && v.is_leaf() {
+            v.set_entry(0);
+        }
+        vaddr = (vaddr & !(leaf_size - 1)) + leaf_size;
+    }
+    tlb::flush_range(start, end - start);
+}
+
+/// The leaf entry mapping `vaddr`, as long as it is a 4 KiB page
+pub fn leaf_entry(root: &Table, vaddr: usize) -> Option<i64> {
+    let vpn = [
+        (vaddr >> 12) & 0x1ff,
+        (vaddr >> 21) & 0x1ff,
+        (vaddr >> 30) & 0x1ff,
+    ];
+
+    let mut v = &root.entries[vpn[2]];
+    for i in (0..2).rev() {
+        if v.is_invalid() || v.is_leaf() {
+            return None;
+        }
+        let entry = ((v.get_entry() & !0x3ff) << 2) as *const Entry;
+        v = unsafe { entry.add(vpn[i]).as_ref().unwrap() };
+    }
+    (v.is_valid() && v.is_leaf()).then(|| v.get_entry())
 }
 
 pub fn virt_to_phys(root: &Table, vaddr: usize) -> Option<usize> {
     let vpn = [
         (vaddr >> 12) & 0x1ff,
-        (vaddr >> 22) & 0x1ff,
+        (vaddr >> 21) & 0x1ff,
         (vaddr >> 30) & 0x1ff,
     ];
 
@@ -0,0 +1,243 @@
+//! Data private to each hart, found through the `tp` register.
+//!
+//! `tp` points at the calling hart's [`PerCpu`] from early boot on, so getting to it is a
+//! single register read. `sscratch` holds the same pointer, for the trap vector to find
+//! the area when `tp` can't be trusted, i.e. when coming from U-mode.
+//!
+//! A preemptible thread may be moved to another hart at any time, after which the area
+//! it looked up belongs to someone else. [`this_cpu`] therefore disables preemption for
+//! as long as its guard lives.
+
+use core::{
+    arch::asm,
+    mem::offset_of,
+    ops::Deref,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    alloc::{PAGE_SIZE, zalloc},
+    cpu::{self, MAX_HARTS},
+    sched::RunQueue,
+    sync::IrqSpinLock,
+};
+
+/// Pages of the stack traps from U-mode start on
+const TRAP_STACK_PAGES: usize = 4;
+
+/// Event counters of a hart
+#[derive(Default)]
+pub struct Stats {
+    pub interrupts: AtomicU64,
+    pub exceptions: AtomicU64,
+    pub context_switches: AtomicU64,
+    pub syscalls: AtomicU64,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            interrupts: AtomicU64::new(0),
+            exceptions: AtomicU64::new(0),
+            context_switches: AtomicU64::new(0),
+            syscalls: AtomicU64::new(0),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct PerCpu {
+    /// Top of the hart's boot stack, loaded by `smp::secondary_entry`
+    stack_top: AtomicUsize,
+    /// Top of the stack traps from U-mode switch to, loaded by the trap vector. The
+    /// scheduler points it at the kernel stack of each thread it switches to.
+    trap_stack_top: AtomicUsize,
+    /// Where the trap vector parks a register while it finds out where the trap came from
+    scratch: AtomicUsize,
+    hart_id: AtomicUsize,
+    /// The task running on this hart, 0 before the scheduler runs anything
+    current_task: AtomicUsize,
+    /// How many traps are being handled on this hart, i.e. nested interrupts
+    irq_depth: AtomicUsize,
+    /// Preemption is off while this isn't 0
+    preempt_count: AtomicUsize,
+    pub run_queue: IrqSpinLock<RunQueue>,
+    pub stats: Stats,
+}
+
+pub const STACK_TOP_OFFSET: usize = offset_of!(PerCpu, stack_top);
+pub const TRAP_STACK_TOP_OFFSET: usize = offset_of!(PerCpu, trap_stack_top);
+pub const SCRATCH_OFFSET: usize = offset_of!(PerCpu, scratch);
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            stack_top: AtomicUsize::new(0),
+            trap_stack_top: AtomicUsize::new(0),
+            scratch: AtomicUsize::new(0),
+            hart_id: AtomicUsize::new(0),
+            current_task: AtomicUsize::new(0),
+            irq_depth: AtomicUsize::new(0),
+            preempt_count: AtomicUsize::new(0),
+            run_queue: IrqSpinLock::new(RunQueue::new()),
+            stats: Stats::new(),
+        }
+    }
+
+    /// Sets up the area for `hart_id` before the hart is started.
+    pub fn init(&self, hart_id: usize, stack_top: usize) {
+        self.hart_id.store(hart_id, Ordering::Relaxed);
+        self.stack_top.store(stack_top, Ordering::Release);
+    }
+
+    pub fn hart_id(&self) -> usize {
+        self.hart_id.load(Ordering::Relaxed)
+    }
+
+    pub fn stack_top(&self) -> usize {
+        self.stack_top.load(Ordering::Relaxed)
+    }
+
+    pub fn trap_stack_top(&self) -> usize {
+        self.trap_stack_top.load(Ordering::Relaxed)
+    }
+
+    pub fn set_trap_stack_top(&self, top: usize) {
+        self.trap_stack_top.store(top, Ordering::Relaxed);
+    }
+
+    pub fn current_task(&self) -> usize {
+        self.current_task.load(Ordering::Relaxed)
+    }
+
+    pub fn set_current_task(&self, task: usize) {
+        self.current_task.store(task, Ordering::Relaxed);
+    }
+
+    pub fn irq_depth(&self) -> usize {
+        self.irq_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn preempt_count(&self) -> usize {
+        self.preempt_count.load(Ordering::Relaxed)
+    }
+}
+
+static CPUS: [PerCpu; MAX_HARTS] = [const { PerCpu::new() }; MAX_HARTS];
+
+pub fn get(hart_id: usize) -> Option<&'static PerCpu> {
+    CPUS.get(hart_id)
+}
+
+/// Points `tp` at the boot hart's area. Has to run right after BSS is cleared, since
+/// everything that asks for the hart id goes through `tp`.
+pub fn init_boot(hart_id: usize, stack_top: usize) {
+    let cpu = get(hart_id).expect("boot hart id is larger than MAX_HARTS");
+    cpu.init(hart_id, stack_top);
+    unsafe {
+        asm!("mv tp, {}", in(reg) cpu);
+    }
+}
+
+/// Allocates the calling hart's trap stack and points `sscratch` at its area. Every hart
+/// has to call this once the page allocator is up.
+pub fn init_hart() {
+    let cpu = this_cpu_raw();
+    let stack = zalloc(TRAP_STACK_PAGES);
+    assert!(!stack.is_null(), "out of memory for a trap stack");
+    cpu.trap_stack_top.store(
+        stack as usize + TRAP_STACK_PAGES * PAGE_SIZE,
+        Ordering::Relaxed,
+    );
+    cpu::sscratch::write(cpu as *const PerCpu as usize);
+}
+
+/// The calling hart's area, or `None` before `init_boot` pointed `tp` at it
+pub fn try_this_cpu() -> Option<&'static PerCpu> {
+    let ptr: *const PerCpu;
+    unsafe {
+        asm!("mv {}, tp", out(reg) ptr);
+        ptr.as_ref()
+    }
+}
+
+/// The calling hart's area, without any protection against migration. Only use this
+/// where preemption is off anyway, e.g. in trap handlers or with interrupts disabled.
+#[inline(always)]
+pub fn this_cpu_raw() -> &'static PerCpu {
+    let ptr: *const PerCpu;
+    unsafe {
+        asm!("mv {}, tp", out(reg) ptr);
+        &*ptr
+    }
+}
+
+/// The calling hart's area. Preemption stays disabled until the guard is dropped.
+pub fn this_cpu() -> CpuGuard {
+    preempt_disable();
+    CpuGuard {
+        cpu: this_cpu_raw(),
+    }
+}
+
+pub struct CpuGuard {
+    cpu: &'static PerCpu,
+}
+
+impl Deref for CpuGuard {
+    type Target = PerCpu;
+
+    fn deref(&self) -> &PerCpu {
+        self.cpu
+    }
+}
+
+impl Drop for CpuGuard {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+/// Reading `tp` and bumping the count has to happen on the same hart, hence the
+/// interrupts being off in between.
+///
+/// Does nothing before `tp` is set up, when only the boot hart runs anyway.
+pub fn preempt_disable() {
+    let enabled = cpu::disable_interrupts();
+    if let Some(cpu) = try_this_cpu() {
+        cpu.preempt_count.fetch_add(1, Ordering::Relaxed);
+    }
+    if enabled {
+        cpu::enable_interrupts();
+    }
+}
+
+pub fn preempt_enable() {
+    let enabled = cpu::disable_interrupts();
+    if let Some(cpu) = try_this_cpu() {
+        let prev = cpu.preempt_count.fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(prev != 0, "unbalanced preempt_enable");
+    }
+    if enabled {
+        cpu::enable_interrupts();
+    }
+}
+
+/// Whether the scheduler may switch away from the running task right now
+pub fn preemptible() -> bool {
+    let cpu = this_cpu_raw();
+    cpu.preempt_count() == 0 && cpu.irq_depth() == 0
+}
+
+/// Called by the trap handler around every trap
+pub fn irq_enter() {
+    this_cpu_raw().irq_depth.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn irq_exit() {
+    this_cpu_raw().irq_depth.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub fn in_interrupt() -> bool {
+    this_cpu_raw().irq_depth() != 0
+}
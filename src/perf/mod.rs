@@ -0,0 +1,160 @@
+//! Performance counters, backed by the SBI PMU extension.
+//!
+//! Hardware counters are read directly through their CSR, firmware counters through SBI.
+
+use core::{
+    arch::asm,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{
+    alloc::{dealloc, zalloc},
+    debug, info,
+    sbi::{
+        SbiErrorType, SbiResult,
+        features::{self, Extension},
+        pmu::{self, CounterInfo, Event, Snapshot, config_flags, start_flags, stop_flags},
+    },
+};
+
+pub mod profiler;
+mod symbols;
+
+static NUM_COUNTERS: AtomicUsize = AtomicUsize::new(0);
+static SNAPSHOT: AtomicPtr<Snapshot> = AtomicPtr::new(null_mut());
+
+pub fn init() {
+    if !features::has(Extension::Pmu) {
+        info!("no SBI PMU, performance counters are unavailable");
+        return;
+    }
+
+    let num = pmu::num_counters().unwrap_or(0).min(usize::BITS as usize);
+    NUM_COUNTERS.store(num, Ordering::Relaxed);
+    info!("{} performance counters", num);
+    for i in 0..num {
+        match pmu::counter_get_info(i) {
+            Ok(CounterInfo::Hardware { csr, width }) => {
+                debug!("counter {}: hardware, csr 0x{:x}, {} bits", i, csr, width)
+            }
+            Ok(CounterInfo::Firmware) => debug!("counter {}: firmware", i),
+            Err(e) => debug!("counter {}: {}", i, e),
+        }
+    }
+
+    let snapshot = zalloc(1) as *mut Snapshot;
+    match unsafe { pmu::snapshot_set_shmem(Some(snapshot)) } {
+        Ok(()) => SNAPSHOT.store(snapshot, Ordering::Relaxed),
+        Err(e) => {
+            debug!("no counter snapshots: {}", e);
+            dealloc(snapshot as *mut u8);
+        }
+    }
+}
+
+pub fn is_available() -> bool {
+    NUM_COUNTERS.load(Ordering::Relaxed) != 0
+}
+
+fn all_counters_mask() -> usize {
+    match NUM_COUNTERS.load(Ordering::Relaxed) {
+        64 => usize::MAX,
+        n => (1 << n) - 1,
+    }
+}
+
+/// Reads a hardware counter CSR, `cycle` (0xC00) up to `hpmcounter31` (0xC1F).
+fn read_counter_csr(csr: u16) -> u64 {
+    macro_rules! read_csr {
+        ($($n:literal),*) => {
+            match csr {
+                $($n => {
+                    let value: u64;
+                    unsafe {
+                        asm!(concat!("csrr {}, ", stringify!($n)), out(reg) value);
+                    }
+                    value
+                })*
+                _ => 0,
+            }
+        };
+    }
+
+    read_csr!(
+        0xC00, 0xC01, 0xC02, 0xC03, 0xC04, 0xC05, 0xC06, 0xC07, 0xC08, 0xC09, 0xC0A, 0xC0B, 0xC0C,
+        0xC0D, 0xC0E, 0xC0F, 0xC10, 0xC11, 0xC12, 0xC13, 0xC14, 0xC15, 0xC16, 0xC17, 0xC18, 0xC19,
+        0xC1A, 0xC1B, 0xC1C, 0xC1D, 0xC1E, 0xC1F
+    )
+}
+
+/// A counter configured to count one event. It is released again when dropped.
+pub struct Counter {
+    idx: usize,
+    info: CounterInfo,
+}
+
+impl Counter {
+    /// Configures a counter for `event`, not counting while in M-mode.
+    pub fn new(event: Event) -> SbiResult<Self> {
+        Self::with_flags(event, config_flags::SET_MINH)
+    }
+
+    /// Configures a counter for `event`, see [`pmu::config_flags`].
+    pub fn with_flags(event: Event, flags: usize) -> SbiResult<Self> {
+        if !is_available() {
+            return Err(SbiErrorType::NotSupported.into());
+        }
+
+        let flags = flags | config_flags::CLEAR_VALUE;
+        let idx = pmu::counter_config_matching(0, all_counters_mask(), flags, event)?;
+        let info = pmu::counter_get_info(idx)?;
+        Ok(Self { idx, info })
+    }
+
+    pub fn index(&self) -> usize {
+        self.idx
+    }
+
+    pub fn info(&self) -> CounterInfo {
+        self.info
+    }
+
+    pub fn start(&self) -> SbiResult<()> {
+        pmu::counter_start(self.idx, 1, 0, 0)
+    }
+
+    pub fn start_at(&self, initial_value: u64) -> SbiResult<()> {
+        pmu::counter_start(self.idx, 1, start_flags::SET_INIT_VALUE, initial_value)
+    }
+
+    pub fn stop(&self) -> SbiResult<()> {
+        pmu::counter_stop(self.idx, 1, 0)
+    }
+
+    pub fn read(&self) -> u64 {
+        match self.info {
+            CounterInfo::Hardware { csr, .. } => read_counter_csr(csr),
+            CounterInfo::Firmware => pmu::counter_fw_read(self.idx).unwrap_or(0),
+        }
+    }
+
+    /// Stops the counter and reads its value from the snapshot shared memory, if the
+    /// firmware supports snapshots.
+    pub fn stop_and_snapshot(&self) -> Option<u64> {
+        let snapshot = SNAPSHOT.load(Ordering::Relaxed);
+        if snapshot.is_null() {
+            return None;
+        }
+
+        pmu::counter_stop(self.idx, 1, stop_flags::TAKE_SNAPSHOT).ok()?;
+        let value = unsafe { (&raw const (*snapshot).counter_values[self.idx]).read_volatile() };
+        Some(value)
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        let _ = pmu::counter_stop(self.idx, 1, stop_flags::RESET);
+    }
+}
@@ -0,0 +1,183 @@
+//! A sampling profiler.
+//!
+//! A hardware counter is started close to overflowing, so that it raises a local counter
+//! overflow interrupt (Sscofpmf) every `period` events. Each interrupt counts a sample
+//! for the function the interrupted `sepc` lies in, and the report lists the functions
+//! with the most samples. That needs a kernel built with a symbol table (see build.rs),
+//! without one every address is counted on its own.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    cpu::{self, IRQ_COUNTER_OVERFLOW},
+    page::{TEXT_END, TEXT_START},
+    println,
+    sbi::{
+        SbiErrorType, SbiResult,
+        pmu::{self, CounterInfo, Event, config_flags, start_flags, stop_flags},
+    },
+    sync::IrqSpinLock,
+    trap::TrapFrame,
+};
+
+use super::symbols;
+
+const NO_COUNTER: usize = usize::MAX;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(NO_COUNTER);
+/// The value the counter is restarted at after each sample
+static INITIAL_VALUE: AtomicU64 = AtomicU64::new(0);
+
+const SLOTS: usize = 1024;
+
+/// Sample counts per function start address, an open addressing hash table
+struct Histogram {
+    pcs: [usize; SLOTS],
+    counts: [u32; SLOTS],
+    total: usize,
+    dropped: usize,
+}
+
+impl Histogram {
+    fn record(&mut self, pc: usize) {
+        self.total += 1;
+        let start = (pc >> 1) % SLOTS;
+        for i in 0..SLOTS {
+            let slot = (start + i) % SLOTS;
+            if self.counts[slot] == 0 {
+                self.pcs[slot] = pc;
+            }
+            if self.pcs[slot] == pc {
+                self.counts[slot] += 1;
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+}
+
+static HISTOGRAM: IrqSpinLock<Histogram> = IrqSpinLock::new(Histogram {
+    pcs: [0; SLOTS],
+    counts: [0; SLOTS],
+    total: 0,
+    dropped: 0,
+});
+
+/// Starts sampling the kernel every `period` occurrences of `event`.
+pub fn start(event: Event, period: u64) -> SbiResult<()> {
+    if !super::is_available() || COUNTER.load(Ordering::Relaxed) != NO_COUNTER {
+        return Err(SbiErrorType::NotSupported.into());
+    }
+
+    // only sample the kernel itself
+    let flags = config_flags::CLEAR_VALUE
+        | config_flags::SET_UINH
+        | config_flags::SET_MINH
+        | config_flags::SET_VUINH
+        | config_flags::SET_VSINH;
+    let idx = pmu::counter_config_matching(0, super::all_counters_mask(), flags, event)?;
+
+    // firmware counters can't raise overflow interrupts
+    let CounterInfo::Hardware { width, .. } = pmu::counter_get_info(idx)? else {
+        let _ = pmu::counter_stop(idx, 1, stop_flags::RESET);
+        return Err(SbiErrorType::NotSupported.into());
+    };
+
+    let max = if width >= 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    };
+    let initial = max - period.min(max) + 1;
+    INITIAL_VALUE.store(initial, Ordering::Relaxed);
+    COUNTER.store(idx, Ordering::Release);
+
+    cpu::sie::set(1 << IRQ_COUNTER_OVERFLOW);
+    pmu::counter_start(idx, 1, start_flags::SET_INIT_VALUE, initial)
+}
+
+pub fn stop() {
+    let idx = COUNTER.swap(NO_COUNTER, Ordering::AcqRel);
+    if idx != NO_COUNTER {
+        let _ = pmu::counter_stop(idx, 1, stop_flags::RESET);
+    }
+    cpu::sie::clear(1 << IRQ_COUNTER_OVERFLOW);
+}
+
+pub fn reset() {
+    let mut h = HISTOGRAM.lock();
+    h.counts = [0; SLOTS];
+    h.total = 0;
+    h.dropped = 0;
+}
+
+/// Called from the trap handler for local counter overflow interrupts
+pub fn handle_overflow(frame: &TrapFrame) {
+    cpu::sip::clear(1 << IRQ_COUNTER_OVERFLOW);
+
+    let idx = COUNTER.load(Ordering::Acquire);
+    if idx == NO_COUNTER {
+        return;
+    }
+
+    let _ = pmu::counter_stop(idx, 1, 0);
+    let pc = frame.sepc;
+    HISTOGRAM
+        .lock()
+        .record(symbols::function_start(pc).unwrap_or(pc));
+    let _ = pmu::counter_start(
+        idx,
+        1,
+        start_flags::SET_INIT_VALUE,
+        INITIAL_VALUE.load(Ordering::Relaxed),
+    );
+}
+
+/// Prints the `top` functions with the most samples.
+pub fn report(top: usize) {
+    let (mut samples, total, dropped) = {
+        let h = HISTOGRAM.lock();
+        let mut samples = [(0usize, 0u32); SLOTS];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = (h.pcs[i], h.counts[i]);
+        }
+        (samples, h.total, h.dropped)
+    };
+    samples.sort_unstable_by_key(|&(_, count)| core::cmp::Reverse(count));
+
+    println!();
+    println!("PROFILE: {} samples, {} not recorded", total, dropped);
+    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+    println!("  samples      %  function");
+    for &(start, count) in samples.iter().take(top).filter(|(_, c)| *c != 0) {
+        let permille = count as usize * 1000 / total.max(1);
+        println!(
+            "{:>9} {:>3}.{}%  {}",
+            count,
+            permille / 10,
+            permille % 10,
+            Function(start)
+        );
+    }
+    println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+}
+
+/// The name of the function starting at the address, or the address if it has no symbol
+struct Function(usize);
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.0;
+        let (text_start, text_end) = unsafe { (TEXT_START, TEXT_END) };
+        if let Some((name, _)) = symbols::lookup(start) {
+            write!(f, "{}", name)
+        } else if (text_start..text_end).contains(&start) {
+            write!(f, "0x{:x} (.text+0x{:x})", start, start - text_start)
+        } else {
+            write!(f, "0x{:x}", start)
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! The kernel's own function symbols, embedded by build.rs.
+
+use core::{arch::global_asm, ffi::CStr};
+
+macro_rules! table {
+    () => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin"))
+    };
+}
+
+#[repr(C, align(8))]
+struct Aligned<T>(T);
+
+/// Only reached through `KSYMS_START`, so no code depends on the table's size
+#[used]
+#[unsafe(link_section = "__ksyms")]
+static KSYMS: Aligned<[u8; table!().len()]> = Aligned(*table!());
+
+global_asm!(
+    ".section .rodata
+.global KSYMS_START
+KSYMS_START: .dword __ksyms_start
+
+.global KSYMS_END
+KSYMS_END: .dword __ksyms_end
+"
+);
+
+unsafe extern "C" {
+    static KSYMS_START: usize;
+    static KSYMS_END: usize;
+}
+
+#[repr(C)]
+struct Symbol {
+    addr: usize,
+    size: u32,
+    /// Offset of the name behind the symbols
+    name: u32,
+}
+
+/// The symbols sorted by address, and their names
+fn table() -> (&'static [Symbol], &'static [u8]) {
+    unsafe {
+        if KSYMS_END - KSYMS_START < size_of::<u64>() {
+            return (&[], &[]);
+        }
+        let len = *(KSYMS_START as *const u64) as usize;
+        let symbols = (KSYMS_START + size_of::<u64>()) as *const Symbol;
+        let names = symbols.add(len) as usize;
+        (
+            core::slice::from_raw_parts(symbols, len),
+            core::slice::from_raw_parts(names as *const u8, KSYMS_END - names),
+        )
+    }
+}
+
+/// The symbol of the function `addr` lies in
+fn find(symbols: &[Symbol], addr: usize) -> Option<&Symbol> {
+    let i = symbols.partition_point(|s| s.addr <= addr).checked_sub(1)?;
+    let sym = &symbols[i];
+    (addr - sym.addr < sym.size as usize).then_some(sym)
+}
+
+/// The start of the function `addr` lies in. Cheaper than [`lookup`], since the name
+/// isn't needed.
+pub fn function_start(addr: usize) -> Option<usize> {
+    find(table().0, addr).map(|sym| sym.addr)
+}
+
+/// The function `addr` lies in and the offset into it
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let (symbols, names) = table();
+    let sym = find(symbols, addr)?;
+    let offset = addr - sym.addr;
+    let name = CStr::from_bytes_until_nul(names.get(sym.name as usize..)?).ok()?;
+    Some((name.to_str().ok()?, offset))
+}
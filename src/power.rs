@@ -0,0 +1,230 @@
+//! Shutting down, rebooting and suspending the machine.
+//!
+//! We prefer the SBI System Reset and System Suspend extensions. Without SRST we fall back
+//! to the `syscon-poweroff` and `syscon-reboot` devices from the device tree (the
+//! `sifive,test` device on qemu) and finally to the legacy SBI shutdown call.
+
+use core::{
+    arch::{asm, naked_asm},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{
+    fdt, kmem,
+    page::{self, EntryBits},
+    sbi::{
+        SbiError, SbiErrorType, SbiResult,
+        features::{self, Extension},
+        legacy,
+        srst::{self, ResetReason, ResetType},
+        susp,
+    },
+    smp, warn,
+};
+
+/// A register write that triggers a syscon action, `addr` is 0 if there is no such device.
+struct SysconTarget {
+    addr: AtomicUsize,
+    value: AtomicU32,
+}
+
+impl SysconTarget {
+    const fn new() -> Self {
+        Self {
+            addr: AtomicUsize::new(0),
+            value: AtomicU32::new(0),
+        }
+    }
+
+    fn trigger(&self) {
+        let addr = self.addr.load(Ordering::Relaxed);
+        if addr != 0 {
+            unsafe { (addr as *mut u32).write_volatile(self.value.load(Ordering::Relaxed)) };
+        }
+    }
+}
+
+static SYSCON_POWEROFF: SysconTarget = SysconTarget::new();
+static SYSCON_REBOOT: SysconTarget = SysconTarget::new();
+
+fn find_syscon(fdt: &fdt::Fdt, compatible: &str) -> Option<(usize, u32)> {
+    let node = fdt.find_compatible(compatible)?;
+    let regmap = node.property_u32("regmap")?;
+    let offset = node.property_u32("offset")?;
+    let value = node.property_u32("value")?;
+    let (base, _) = fdt.find_phandle(regmap)?.reg()?;
+    Some((base + offset as usize, value))
+}
+
+/// Looks up the syscon devices and maps their registers. Has to run after `page::init`.
+pub fn init() {
+    let Some(fdt) = fdt::get() else {
+        return;
+    };
+
+    let root = unsafe { kmem::get_page_table().as_mut().unwrap() };
+    for (target, compatible) in [
+        (&SYSCON_POWEROFF, "syscon-poweroff"),
+        (&SYSCON_REBOOT, "syscon-reboot"),
+    ] {
+        if let Some((addr, value)) = find_syscon(&fdt, compatible) {
+            page::id_map_range(root, addr, addr + 4, EntryBits::ReadWrite as i64);
+            target.value.store(value, Ordering::Relaxed);
+            target.addr.store(addr, Ordering::Relaxed);
+        }
+    }
+
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+pub fn shutdown() -> ! {
+    if features::has(Extension::Srst) {
+        let e = srst::system_reset(ResetType::Shutdown, ResetReason::NoReason);
+        warn!("SBI shutdown failed: {}", e);
+    }
+
+    SYSCON_POWEROFF.trigger();
+
+    legacy::shutdown()
+}
+
+pub fn reboot() -> ! {
+    if features::has(Extension::Srst) {
+        let e = srst::system_reset(ResetType::ColdReboot, ResetReason::NoReason);
+        warn!("SBI reboot failed: {}", e);
+    }
+
+    SYSCON_REBOOT.trigger();
+
+    warn!("no way to reboot, halting");
+    halt()
+}
+
+/// Everything the resume path needs to continue where `suspend_to_ram` left off.
+#[repr(C)]
+#[derive(Default)]
+struct SuspendContext {
+    ra: usize,
+    sp: usize,
+    gp: usize,
+    tp: usize,
+    s: [usize; 12],
+    satp: usize,
+    stvec: usize,
+    sie: usize,
+    sscratch: usize,
+    /// SIE and SUM in particular, the firmware resumes with both cleared
+    sstatus: usize,
+}
+
+/// Suspends the machine to RAM and returns once it woke up again. The firmware only
+/// suspends with every other hart stopped, so this fails with `Denied` while secondary
+/// harts are online.
+pub fn suspend() -> SbiResult<()> {
+    if !features::has(Extension::Susp) {
+        return Err(SbiErrorType::NotSupported.into());
+    }
+    if smp::num_online() > 1 {
+        return Err(SbiErrorType::Denied.into());
+    }
+
+    let mut ctx = SuspendContext::default();
+    match unsafe { suspend_to_ram(&mut ctx) } {
+        0 => Ok(()),
+        e => Err(SbiError::new(e)),
+    }
+}
+
+/// Saves the calling context to `ctx` and asks the firmware to suspend to RAM. Returns the
+/// error code if the firmware refused, or 0 after resuming through `resume_from_ram`.
+#[unsafe(naked)]
+unsafe extern "C" fn suspend_to_ram(ctx: *mut SuspendContext) -> isize {
+    naked_asm!(
+        "sd ra, 0(a0)
+        sd sp, 8(a0)
+        sd gp, 16(a0)
+        sd tp, 24(a0)
+        sd s0, 32(a0)
+        sd s1, 40(a0)
+        sd s2, 48(a0)
+        sd s3, 56(a0)
+        sd s4, 64(a0)
+        sd s5, 72(a0)
+        sd s6, 80(a0)
+        sd s7, 88(a0)
+        sd s8, 96(a0)
+        sd s9, 104(a0)
+        sd s10, 112(a0)
+        sd s11, 120(a0)
+        csrr t0, satp
+        sd t0, 128(a0)
+        csrr t0, stvec
+        sd t0, 136(a0)
+        csrr t0, sie
+        sd t0, 144(a0)
+        csrr t0, sscratch
+        sd t0, 152(a0)
+        csrr t0, sstatus
+        sd t0, 160(a0)
+
+        mv a2, a0
+        la a1, {resume}
+        li a0, 0
+        li a6, {fid}
+        li a7, {eid}
+        ecall
+        ret",
+        resume = sym resume_from_ram,
+        eid = const susp::EXTENSION_ID,
+        fid = const susp::fid::SYSTEM_SUSPEND,
+    );
+}
+
+/// Entered by the firmware with the MMU off, the hart id in `a0` and the context in `a1`.
+#[unsafe(naked)]
+unsafe extern "C" fn resume_from_ram() {
+    naked_asm!(
+        "ld t0, 128(a1)
+        csrw satp, t0
+        sfence.vma
+        ld t0, 136(a1)
+        csrw stvec, t0
+        ld t0, 144(a1)
+        csrw sie, t0
+        ld t0, 152(a1)
+        csrw sscratch, t0
+
+        ld ra, 0(a1)
+        ld sp, 8(a1)
+        ld gp, 16(a1)
+        ld tp, 24(a1)
+        ld s0, 32(a1)
+        ld s1, 40(a1)
+        ld s2, 48(a1)
+        ld s3, 56(a1)
+        ld s4, 64(a1)
+        ld s5, 72(a1)
+        ld s6, 80(a1)
+        ld s7, 88(a1)
+        ld s8, 96(a1)
+        ld s9, 104(a1)
+        ld s10, 112(a1)
+        ld s11, 120(a1)
+
+        # last, interrupts may come in right away
+        ld t0, 160(a1)
+        csrw sstatus, t0
+        li a0, 0
+        ret"
+    );
+}
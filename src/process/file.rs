@@ -0,0 +1,65 @@
+//! Open files of a process.
+//!
+//! There is no file system yet, so all a process can open are the console and
+//! `/dev/null`.
+
+pub const MAX_FILES: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum File {
+    Console,
+    Null,
+}
+
+impl File {
+    /// Opens the device at `path`.
+    pub fn open(path: &[u8]) -> Option<File> {
+        match path {
+            b"/dev/console" | b"/dev/tty" => Some(File::Console),
+            b"/dev/null" => Some(File::Null),
+            _ => None,
+        }
+    }
+}
+
+/// The files of a process by descriptor
+#[derive(Clone)]
+pub struct FileTable {
+    files: [Option<File>; MAX_FILES],
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        Self {
+            files: [None; MAX_FILES],
+        }
+    }
+
+    /// A table with stdin, stdout and stderr on the console
+    pub fn with_console() -> Self {
+        let mut table = Self::new();
+        table.files[..3].fill(Some(File::Console));
+        table
+    }
+
+    pub fn get(&self, fd: usize) -> Option<File> {
+        *self.files.get(fd)?
+    }
+
+    /// Returns the lowest free descriptor, or `None` if the table is full.
+    pub fn insert(&mut self, file: File) -> Option<usize> {
+        let fd = self.files.iter().position(Option::is_none)?;
+        self.files[fd] = Some(file);
+        Some(fd)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Option<File> {
+        self.files.get_mut(fd)?.take()
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
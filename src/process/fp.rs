@@ -0,0 +1,120 @@
+//! Floating point state of user threads.
+//!
+//! The kernel itself doesn't touch the FP registers, so they only need to be switched
+//! when a different process runs.
+
+use core::arch::naked_asm;
+
+use crate::cpu::SSTATUS_FS_INITIAL;
+
+#[repr(C)]
+#[derive(Default)]
+pub struct FpState {
+    pub f: [u64; 32],
+    pub fcsr: u64,
+}
+
+macro_rules! fp_regs {
+    ($op:literal) => {
+        concat!(
+            $op,
+            " f0, 0(a0)\n",
+            $op,
+            " f1, 8(a0)\n",
+            $op,
+            " f2, 16(a0)\n",
+            $op,
+            " f3, 24(a0)\n",
+            $op,
+            " f4, 32(a0)\n",
+            $op,
+            " f5, 40(a0)\n",
+            $op,
+            " f6, 48(a0)\n",
+            $op,
+            " f7, 56(a0)\n",
+            $op,
+            " f8, 64(a0)\n",
+            $op,
+            " f9, 72(a0)\n",
+            $op,
+            " f10, 80(a0)\n",
+            $op,
+            " f11, 88(a0)\n",
+            $op,
+            " f12, 96(a0)\n",
+            $op,
+            " f13, 104(a0)\n",
+            $op,
+            " f14, 112(a0)\n",
+            $op,
+            " f15, 120(a0)\n",
+            $op,
+            " f16, 128(a0)\n",
+            $op,
+            " f17, 136(a0)\n",
+            $op,
+            " f18, 144(a0)\n",
+            $op,
+            " f19, 152(a0)\n",
+            $op,
+            " f20, 160(a0)\n",
+            $op,
+            " f21, 168(a0)\n",
+            $op,
+            " f22, 176(a0)\n",
+            $op,
+            " f23, 184(a0)\n",
+            $op,
+            " f24, 192(a0)\n",
+            $op,
+            " f25, 200(a0)\n",
+            $op,
+            " f26, 208(a0)\n",
+            $op,
+            " f27, 216(a0)\n",
+            $op,
+            " f28, 224(a0)\n",
+            $op,
+            " f29, 232(a0)\n",
+            $op,
+            " f30, 240(a0)\n",
+            $op,
+            " f31, 248(a0)\n",
+        )
+    };
+}
+
+/// Saves the FP registers, turning the FP unit on if it is off.
+///
+/// # Safety
+/// `state` must be valid for writes.
+#[unsafe(naked)]
+pub unsafe extern "C" fn save(state: *mut FpState) {
+    naked_asm!(
+        "li t0, {fs_initial}
+        csrs sstatus, t0",
+        fp_regs!("fsd"),
+        "frcsr t0
+        sd t0, 256(a0)
+        ret",
+        fs_initial = const SSTATUS_FS_INITIAL,
+    );
+}
+
+/// Loads the FP registers, turning the FP unit on if it is off.
+///
+/// # Safety
+/// `state` must be valid for reads.
+#[unsafe(naked)]
+pub unsafe extern "C" fn restore(state: *const FpState) {
+    naked_asm!(
+        "li t0, {fs_initial}
+        csrs sstatus, t0",
+        fp_regs!("fld"),
+        "ld t0, 256(a0)
+        fscsr t0
+        ret",
+        fs_initial = const SSTATUS_FS_INITIAL,
+    );
+}
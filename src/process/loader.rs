@@ -0,0 +1,251 @@
+//! Loading ELF executables into an address space.
+//!
+//! The `PT_LOAD` segments are mapped with the permissions they ask for, position
+//! independent programs at [`PIE_BASE`]. The initial stack is laid out the way the Linux
+//! RISC-V ABI expects it, so a C runtime finds `argc`, `argv`, `envp` and the auxiliary
+//! vector at `sp`. There is no dynamic linker, programs have to be statically linked.
+
+use core::fmt;
+
+use crate::{
+    alloc::PAGE_SIZE,
+    elf::{Elf, ElfError, PF_R, PF_W, PF_X, PT_LOAD, PT_PHDR, ProgramHeader},
+    page::EntryBits,
+    random,
+};
+
+use super::mm::{AddressSpace, is_user_range};
+
+/// Where position independent programs get loaded
+pub const PIE_BASE: usize = 0x20_0000_0000;
+
+/// Bytes of the stack the strings in `argv` and `envp` may take up
+const MAX_ARG_BYTES: usize = 32 * 1024;
+
+/// Auxiliary vector keys
+mod at {
+    pub const NULL: usize = 0;
+    pub const PHDR: usize = 3;
+    pub const PHENT: usize = 4;
+    pub const PHNUM: usize = 5;
+    pub const PAGESZ: usize = 6;
+    pub const BASE: usize = 7;
+    pub const ENTRY: usize = 9;
+    pub const UID: usize = 11;
+    pub const EUID: usize = 12;
+    pub const GID: usize = 13;
+    pub const EGID: usize = 14;
+    pub const HWCAP: usize = 16;
+    pub const CLKTCK: usize = 17;
+    pub const SECURE: usize = 23;
+    pub const RANDOM: usize = 25;
+}
+
+/// One bit per single letter ISA extension, for `AT_HWCAP`
+const fn hwcap(extensions: &[u8]) -> usize {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < extensions.len() {
+        mask |= 1 << (extensions[i] - b'a');
+        i += 1;
+    }
+    mask
+}
+
+const HWCAP_IMAFDC: usize = hwcap(b"imafdc");
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// The program needs a dynamic linker
+    Interpreter,
+    BadSegment,
+    NoMemory,
+    TooManyArgs,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        Self::Elf(e)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Elf(e) => write!(f, "{}", e),
+            Self::Interpreter => write!(f, "dynamically linked programs aren't supported"),
+            Self::BadSegment => write!(f, "segment outside of user space"),
+            Self::NoMemory => write!(f, "out of memory"),
+            Self::TooManyArgs => write!(f, "arguments too long"),
+        }
+    }
+}
+
+/// Where a program ended up
+#[derive(Clone, Copy, Debug)]
+pub struct Image {
+    pub entry: usize,
+    /// Address of the program headers in memory, 0 if they aren't loaded
+    pub phdr: usize,
+    pub phnum: usize,
+    pub phent: usize,
+    /// What the addresses in the file are relative to, 0 unless the program is PIE
+    pub base: usize,
+    /// The end of the highest segment, where the heap can start
+    pub end: usize,
+}
+
+fn segment_bits(ph: &ProgramHeader) -> i64 {
+    let mut bits = 0;
+    // RISC-V has no write-only or inaccessible pages
+    if ph.p_flags & (PF_R | PF_W) != 0 || ph.p_flags & PF_X == 0 {
+        bits |= EntryBits::Read as i64;
+    }
+    if ph.p_flags & PF_W != 0 {
+        bits |= EntryBits::Write as i64;
+    }
+    if ph.p_flags & PF_X != 0 {
+        bits |= EntryBits::Execute as i64;
+    }
+    bits
+}
+
+/// Maps the segments of `elf` into `space`. The frames start out zeroed, so only the
+/// part of each segment that is in the file needs to be copied and BSS stays zero.
+pub fn load(space: &mut AddressSpace, elf: &Elf) -> Result<Image, LoadError> {
+    if elf.interpreter().is_some() {
+        return Err(LoadError::Interpreter);
+    }
+    let header = elf.header();
+    let base = if elf.is_pie() { PIE_BASE } else { 0 };
+
+    let mut end = 0;
+    let mut phdr = 0;
+    for ph in elf.program_headers() {
+        if ph.p_type == PT_PHDR {
+            phdr = base + ph.p_vaddr as usize;
+        }
+        if ph.p_type != PT_LOAD {
+            continue;
+        }
+
+        let data = elf.segment_data(&ph).ok_or(LoadError::BadSegment)?;
+        let start = base
+            .checked_add(ph.p_vaddr as usize)
+            .ok_or(LoadError::BadSegment)?;
+        let seg_end = start
+            .checked_add(ph.p_memsz as usize)
+            .ok_or(LoadError::BadSegment)?;
+        if ph.p_filesz > ph.p_memsz || !is_user_range(start, seg_end) {
+            return Err(LoadError::BadSegment);
+        }
+        if !space.map_zeroed(start, seg_end, segment_bits(&ph)) || !space.write(start, data) {
+            return Err(LoadError::NoMemory);
+        }
+
+        // the program headers are usually part of the first segment
+        let phoff = header.e_phoff;
+        if phdr == 0 && (ph.p_offset..ph.p_offset + ph.p_filesz).contains(&phoff) {
+            phdr = start + (phoff - ph.p_offset) as usize;
+        }
+        end = end.max(seg_end);
+    }
+    if end == 0 {
+        return Err(LoadError::BadSegment);
+    }
+
+    Ok(Image {
+        entry: base + header.e_entry as usize,
+        phdr,
+        phnum: header.e_phnum as usize,
+        phent: header.e_phentsize as usize,
+        base,
+        end,
+    })
+}
+
+/// Lays out the initial stack below `top` and returns the program's `sp`:
+///
+/// ```text
+/// sp -> argc
+///       argv[0] .. argv[argc - 1], NULL
+///       envp[0] .. NULL
+///       auxv pairs .. AT_NULL, 0
+///       AT_RANDOM bytes
+///       argument and environment strings
+/// top
+/// ```
+///
+/// See [`random`](crate::random) for how good the `AT_RANDOM` bytes are.
+pub fn setup_stack(
+    space: &mut AddressSpace,
+    top: usize,
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+    image: &Image,
+) -> Result<usize, LoadError> {
+    let strings_len: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    if strings_len > MAX_ARG_BYTES {
+        return Err(LoadError::TooManyArgs);
+    }
+
+    let strings = top - strings_len;
+    let mut addr = strings;
+    for s in argv.iter().chain(envp) {
+        if !space.write(addr, s) || !space.write(addr + s.len(), &[0]) {
+            return Err(LoadError::NoMemory);
+        }
+        addr += s.len() + 1;
+    }
+
+    let random_bytes = (strings - 16) & !0xf;
+    let mut bytes = [0u8; 16];
+    random::fill(&mut bytes);
+    if !space.write(random_bytes, &bytes) {
+        return Err(LoadError::NoMemory);
+    }
+
+    let auxv = [
+        (at::PHDR, image.phdr),
+        (at::PHENT, image.phent),
+        (at::PHNUM, image.phnum),
+        (at::PAGESZ, PAGE_SIZE),
+        (at::BASE, 0),
+        (at::ENTRY, image.entry),
+        (at::UID, 0),
+        (at::EUID, 0),
+        (at::GID, 0),
+        (at::EGID, 0),
+        (at::HWCAP, HWCAP_IMAFDC),
+        (at::CLKTCK, 100),
+        (at::SECURE, 0),
+        (at::RANDOM, random_bytes),
+        (at::NULL, 0),
+    ];
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + auxv.len() * 2;
+    let sp = (random_bytes - words * size_of::<usize>()) & !0xf;
+
+    let mut cursor = sp;
+    let mut push = |word: usize| {
+        let ok = space.write(cursor, &word.to_le_bytes());
+        cursor += size_of::<usize>();
+        ok
+    };
+    let mut ok = push(argv.len());
+    let mut string = strings;
+    for list in [argv, envp] {
+        for s in list {
+            ok &= push(string);
+            string += s.len() + 1;
+        }
+        ok &= push(0);
+    }
+    for (key, value) in auxv {
+        ok &= push(key) & push(value);
+    }
+    if !ok {
+        return Err(LoadError::NoMemory);
+    }
+    Ok(sp)
+}
@@ -0,0 +1,395 @@
+//! User address spaces.
+//!
+//! Every process has its own Sv39 root table. The kernel's mappings are shared with it,
+//! supervisor-only: the root entries point at the kernel's own tables, except for the
+//! first gigabyte, which holds both the devices and the usual link address of programs,
+//! so the process gets a copy of the kernel's table for it. User pages can go anywhere
+//! below [`USER_END`] that the kernel doesn't use, and their frames and tables belong
+//! to the process.
+
+use crate::{
+    alloc::{PAGE_SIZE, alloc, dealloc, zalloc},
+    kmem,
+    page::{self, Entry, EntryBits, Table},
+};
+
+/// The end of the lower half of Sv39, where user space ends
+pub const USER_END: usize = 1 << 38;
+
+fn align_up(addr: usize) -> usize {
+    addr.next_multiple_of(PAGE_SIZE)
+}
+
+fn table_of(entry: &Entry) -> *mut Table {
+    ((entry.get_entry() & !0x3ff) << 2) as *mut Table
+}
+
+fn kernel_root() -> &'static Table {
+    unsafe { &*kmem::get_page_table() }
+}
+
+/// Whether the kernel has a table for the first gigabyte that processes get a copy of
+fn kernel_first_gigabyte() -> Option<&'static Table> {
+    let entry = &kernel_root().entries[0];
+    (entry.is_valid() && entry.is_branch()).then(|| unsafe { &*table_of(entry) })
+}
+
+/// Whether the page at `vaddr` can be mapped for a process
+pub fn is_user(vaddr: usize) -> bool {
+    if vaddr >= USER_END {
+        return false;
+    }
+    let vpn2 = (vaddr >> 30) & 0x1ff;
+    let vpn1 = (vaddr >> 21) & 0x1ff;
+    match kernel_first_gigabyte() {
+        Some(table) if vpn2 == 0 => table.entries[vpn1].is_invalid(),
+        _ => kernel_root().entries[vpn2].is_invalid(),
+    }
+}
+
+/// Whether all of `start..end` can be mapped for a process
+pub fn is_user_range(start: usize, end: usize) -> bool {
+    start <= end
+        && end <= USER_END
+        && (start & !(PAGE_SIZE - 1)..end)
+            .step_by(PAGE_SIZE)
+            .all(is_user)
+}
+
+/// Frees a table of the process, with everything it maps.
+fn free_table(table: *mut Table) {
+    for entry in unsafe { &*table }.entries.iter() {
+        if entry.is_invalid() {
+            continue;
+        }
+        if entry.is_leaf() {
+            dealloc(((entry.get_entry() >> 10) << 12) as *mut u8);
+        } else {
+            free_table(table_of(entry));
+        }
+    }
+    dealloc(table as *mut u8);
+}
+
+/// Where anonymous mappings start, they grow down from below the main thread's stack
+pub const MMAP_TOP: usize = USER_END - (1 << 30);
+
+pub struct AddressSpace {
+    root: *mut Table,
+    /// Start and end of the heap `brk` grows
+    brk_start: usize,
+    brk: usize,
+    /// The lowest anonymous mapping so far
+    mmap_bottom: usize,
+}
+
+unsafe impl Send for AddressSpace {}
+
+impl AddressSpace {
+    /// Creates an address space with only the kernel's mappings.
+    pub fn new() -> Option<Self> {
+        let root = zalloc(1) as *mut Table;
+        if root.is_null() {
+            return None;
+        }
+        let r = unsafe { &mut *root };
+        for (i, entry) in kernel_root().entries.iter().enumerate() {
+            r.entries[i].set_entry(entry.get_entry());
+        }
+
+        if let Some(kernel) = kernel_first_gigabyte() {
+            let table = zalloc(1) as *mut Table;
+            if table.is_null() {
+                dealloc(root as *mut u8);
+                return None;
+            }
+            let t = unsafe { &mut *table };
+            for (i, entry) in kernel.entries.iter().enumerate() {
+                t.entries[i].set_entry(entry.get_entry());
+            }
+            r.entries[0].set_entry((table as i64 >> 2) | EntryBits::Valid as i64);
+        }
+        Some(Self {
+            root,
+            brk_start: 0,
+            brk: 0,
+            mmap_bottom: MMAP_TOP,
+        })
+    }
+
+    pub fn root(&self) -> *mut Table {
+        self.root
+    }
+
+    /// Maps the page at `vaddr` to the frame at `paddr`, `bits` gets the U bit added.
+    /// Returns false if the page isn't available to processes or there was no memory for
+    /// a page table.
+    pub fn map(&mut self, vaddr: usize, paddr: usize, bits: i64) -> bool {
+        is_user(vaddr)
+            && page::try_map(
+                unsafe { &mut *self.root },
+                vaddr,
+                paddr,
+                bits | EntryBits::User as i64,
+                0,
+            )
+    }
+
+    /// Maps fresh zeroed frames over `start..end`. Pages that are mapped already keep
+    /// their frame and get `bits` added to their permissions. On failure a part of the
+    /// range may be mapped already, which is freed along with the address space.
+    pub fn map_zeroed(&mut self, start: usize, end: usize, bits: i64) -> bool {
+        if !is_user_range(start, end) {
+            return false;
+        }
+        let mut vaddr = start & !(PAGE_SIZE - 1);
+        while vaddr < end {
+            match page::leaf_entry(unsafe { &*self.root }, vaddr) {
+                Some(entry) if entry & bits != bits => {
+                    let frame = ((entry >> 10) << 12) as usize;
+                    if !self.map(vaddr, frame, (entry & 0xff) | bits) {
+                        return false;
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    let frame = zalloc(1);
+                    if frame.is_null() {
+                        return false;
+                    }
+                    if !self.map(vaddr, frame as usize, bits) {
+                        dealloc(frame);
+                        return false;
+                    }
+                }
+            }
+            vaddr += PAGE_SIZE;
+        }
+        true
+    }
+
+    /// Unmaps `start..end` and frees the frames that were mapped there.
+    pub fn unmap(&mut self, start: usize, end: usize) -> bool {
+        if !is_user_range(start, end) {
+            return false;
+        }
+        let start = start & !(PAGE_SIZE - 1);
+        for vaddr in (start..end).step_by(PAGE_SIZE) {
+            if let Some(frame) = self.translate(vaddr) {
+                dealloc(frame as *mut u8);
+            }
+        }
+        page::unmap_range(unsafe { &mut *self.root }, start, end);
+        true
+    }
+
+    /// Whether `entry`, the `j`th entry of the table the `i`th root entry points at, is
+    /// one of the kernel's instead of the process'
+    fn is_kernel_entry(&self, i: usize, j: usize, entry: &Entry) -> bool {
+        match kernel_first_gigabyte() {
+            Some(table) if i == 0 => entry.get_entry() == table.entries[j].get_entry(),
+            _ => {
+                let root = unsafe { &*self.root };
+                root.entries[i].get_entry() == kernel_root().entries[i].get_entry()
+            }
+        }
+    }
+
+    /// Calls `f` with the address and leaf entry of every page the process mapped.
+    fn for_each_page(&self, mut f: impl FnMut(usize, i64)) {
+        let root = unsafe { &*self.root };
+        for (i, entry) in root.entries.iter().enumerate().take(USER_END >> 30) {
+            if entry.is_invalid() || entry.is_leaf() {
+                continue;
+            }
+            for (j, e1) in unsafe { &*table_of(entry) }.entries.iter().enumerate() {
+                if e1.is_invalid() || e1.is_leaf() || self.is_kernel_entry(i, j, e1) {
+                    continue;
+                }
+                for (k, e0) in unsafe { &*table_of(e1) }.entries.iter().enumerate() {
+                    if e0.is_valid() && e0.is_leaf() {
+                        f(i << 30 | j << 21 | k << 12, e0.get_entry());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Creates a copy of the address space, with copies of all of its pages.
+    pub fn duplicate(&self) -> Option<Self> {
+        let mut copy = Self::new()?;
+        let mut ok = true;
+        self.for_each_page(|vaddr, entry| {
+            if !ok {
+                return;
+            }
+            let frame = alloc(1);
+            if frame.is_null() {
+                ok = false;
+                return;
+            }
+            let from = ((entry >> 10) << 12) as *const u8;
+            unsafe { core::ptr::copy_nonoverlapping(from, frame, PAGE_SIZE) };
+            if !copy.map(vaddr, frame as usize, entry & 0xff) {
+                dealloc(frame);
+                ok = false;
+            }
+        });
+        copy.brk_start = self.brk_start;
+        copy.brk = self.brk;
+        copy.mmap_bottom = self.mmap_bottom;
+        ok.then_some(copy)
+    }
+
+    /// Unmaps everything the process mapped and frees its frames and tables. The
+    /// address space must not be active on any hart.
+    pub fn clear(&mut self) {
+        let root = unsafe { &mut *self.root };
+        let kernel = kernel_root();
+        for (i, entry) in root.entries.iter_mut().enumerate() {
+            if entry.is_invalid() || entry.is_leaf() {
+                continue;
+            }
+            match kernel_first_gigabyte() {
+                Some(kernel_table) if i == 0 => {
+                    let table = unsafe { &mut *table_of(entry) };
+                    for (j, e) in table.entries.iter_mut().enumerate() {
+                        let kernel_entry = kernel_table.entries[j].get_entry();
+                        if e.is_valid() && e.is_branch() && e.get_entry() != kernel_entry {
+                            free_table(table_of(e));
+                            e.set_entry(kernel_entry);
+                        }
+                    }
+                }
+                _ if entry.get_entry() != kernel.entries[i].get_entry() => {
+                    free_table(table_of(entry));
+                    entry.set_entry(kernel.entries[i].get_entry());
+                }
+                _ => {}
+            }
+        }
+        self.brk_start = 0;
+        self.brk = 0;
+        self.mmap_bottom = MMAP_TOP;
+    }
+
+    /// Whether every page of `start..end` is mapped for the process with at least the
+    /// permissions in `bits`
+    pub fn is_accessible(&self, start: usize, end: usize, bits: i64) -> bool {
+        let bits = bits | EntryBits::User as i64;
+        let root = unsafe { &*self.root };
+        start <= end
+            && end <= USER_END
+            && (start & !(PAGE_SIZE - 1)..end)
+                .step_by(PAGE_SIZE)
+                .all(|vaddr| page::leaf_entry(root, vaddr).is_some_and(|e| e & bits == bits))
+    }
+
+    pub fn translate(&self, vaddr: usize) -> Option<usize> {
+        page::virt_to_phys(unsafe { &*self.root }, vaddr)
+    }
+
+    /// Copies from `vaddr` into `buf` through the frames. Returns false if part of the
+    /// range isn't mapped.
+    pub fn read(&self, vaddr: usize, buf: &mut [u8]) -> bool {
+        let mut done = 0;
+        while done < buf.len() {
+            let va = vaddr + done;
+            let Some(pa) = self.translate(va) else {
+                return false;
+            };
+            let len = (PAGE_SIZE - va % PAGE_SIZE).min(buf.len() - done);
+            unsafe {
+                core::ptr::copy_nonoverlapping(pa as *const u8, buf[done..].as_mut_ptr(), len);
+            }
+            done += len;
+        }
+        true
+    }
+
+    /// Lets the heap start at `addr`, usually right after the program's data.
+    pub fn set_brk_start(&mut self, addr: usize) {
+        let addr = align_up(addr);
+        self.brk_start = addr;
+        self.brk = addr;
+    }
+
+    /// Moves the end of the heap to `brk` and returns the new end, or the old one if
+    /// `brk` is out of range or there is no memory.
+    pub fn set_brk(&mut self, brk: usize) -> usize {
+        if brk < self.brk_start || brk > self.mmap_bottom {
+            return self.brk;
+        }
+        let old_end = align_up(self.brk);
+        let new_end = align_up(brk);
+        if new_end > old_end {
+            if !self.map_zeroed(old_end, new_end, EntryBits::ReadWrite as i64) {
+                self.unmap(old_end, new_end);
+                return self.brk;
+            }
+        } else if new_end < old_end {
+            self.unmap(new_end, old_end);
+        }
+        self.brk = brk;
+        brk
+    }
+
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Maps `len` bytes of zeroed memory somewhere below the previous anonymous mappings,
+    /// or at `fixed`. `bits` of 0 only reserves the range.
+    pub fn map_anonymous(&mut self, fixed: Option<usize>, len: usize, bits: i64) -> Option<usize> {
+        let len = len.checked_next_multiple_of(PAGE_SIZE)?;
+        let start = match fixed {
+            Some(addr) if addr.is_multiple_of(PAGE_SIZE) => addr,
+            Some(_) => return None,
+            None => self.mmap_bottom.checked_sub(len)?,
+        };
+        let end = start.checked_add(len)?;
+        if !is_user_range(start, end) || (fixed.is_none() && start < self.brk) {
+            return None;
+        }
+
+        // replaces whatever was there
+        self.unmap(start, end);
+        if bits != 0 && !self.map_zeroed(start, end, bits) {
+            self.unmap(start, end);
+            return None;
+        }
+        if fixed.is_none() {
+            self.mmap_bottom = start;
+        }
+        Some(start)
+    }
+
+    /// Copies `data` to `vaddr` through the frames, so it works for read-only pages
+    /// too. Returns false if part of the range isn't mapped.
+    pub fn write(&mut self, vaddr: usize, data: &[u8]) -> bool {
+        let mut done = 0;
+        while done < data.len() {
+            let va = vaddr + done;
+            let Some(pa) = self.translate(va) else {
+                return false;
+            };
+            let len = (PAGE_SIZE - va % PAGE_SIZE).min(data.len() - done);
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[done..].as_ptr(), pa as *mut u8, len);
+            }
+            done += len;
+        }
+        true
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees the user mappings. The address space must not be active on any hart.
+    fn drop(&mut self) {
+        self.clear();
+        if kernel_first_gigabyte().is_some() {
+            dealloc(table_of(&unsafe { &*self.root }.entries[0]) as *mut u8);
+        }
+        dealloc(self.root as *mut u8);
+    }
+}
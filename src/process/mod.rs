@@ -0,0 +1,549 @@
+//! User processes.
+//!
+//! A process is an address space of its own and a kernel thread that runs the program in
+//! U-mode. The thread starts out in S-mode, puts the program's initial registers at the
+//! top of its kernel stack and returns into U-mode through the trap vector. Every trap
+//! from U-mode comes back onto that stack, so a faulting program takes down only its own
+//! process.
+//!
+//! Processes form a tree. A process that exits stays around as a zombie holding its wait
+//! status until its parent collects it with `wait4`. Its children go to init, pid 1.
+//! Faults in a program become signals, see [`signal`].
+
+use core::{
+    arch::naked_asm,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{
+    alloc::{PAGE_SIZE, dealloc, zalloc},
+    cpu::{
+        self, SSTATUS_FS, SSTATUS_FS_INITIAL, SSTATUS_SIE, SSTATUS_SPIE, SSTATUS_SPP, SSTATUS_SUM,
+    },
+    debug,
+    elf::Elf,
+    info, kmem,
+    page::{self, EntryBits, Table},
+    percpu,
+    sched::{self, Policy},
+    sync::{IrqSpinLock, SpinLock, WaitQueue},
+    syscall::{self, Errno},
+    thread::{self, Thread},
+    trap::{
+        self, EXC_BREAKPOINT, EXC_ECALL_U, EXC_ILLEGAL_INSTRUCTION, EXC_INSTRUCTION_MISALIGNED,
+        EXC_INSTRUCTION_PAGE_FAULT, EXC_LOAD_MISALIGNED, EXC_LOAD_PAGE_FAULT, EXC_STORE_MISALIGNED,
+        EXC_STORE_PAGE_FAULT, TrapFrame,
+    },
+    warn,
+};
+
+pub mod file;
+pub mod fp;
+pub mod loader;
+pub mod mm;
+pub mod programs;
+pub mod signal;
+pub mod tty;
+
+use file::FileTable;
+use fp::FpState;
+use loader::LoadError;
+use mm::{AddressSpace, USER_END};
+use signal::{SigInfo, Signals};
+
+pub const MAX_PROCESSES: usize = 64;
+
+/// The process orphans are handed to
+pub const INIT_PID: usize = 1;
+
+/// `Process::status` while the process hasn't exited
+const RUNNING: usize = usize::MAX;
+
+/// `wait` options
+pub const WNOHANG: usize = 1;
+pub const WUNTRACED: usize = 2;
+pub const WCONTINUED: usize = 8;
+
+/// 64 KiB of stack for the main thread, right below the end of user space
+const USER_STACK_PAGES: usize = 16;
+pub const USER_STACK_TOP: usize = USER_END;
+
+pub struct Process {
+    pid: usize,
+    /// The root of `space`, which the scheduler needs without taking the lock
+    root: AtomicPtr<Table>,
+    pub space: SpinLock<AddressSpace>,
+    pub files: SpinLock<FileTable>,
+    /// The registers the program starts with
+    start: TrapFrame,
+    fp: UnsafeCell<FpState>,
+    /// The parent's pid, 0 if nobody waits for the process. Only changes with the
+    /// process table locked.
+    parent: AtomicUsize,
+    /// The wait status once the process exited, [`RUNNING`] before
+    status: AtomicUsize,
+    pub signals: Signals,
+    /// The thread running the program, null until it is started. It lives at least as
+    /// long as the process isn't a zombie.
+    thread: AtomicPtr<Thread>,
+    /// Where `wait` sleeps until a child exits, stops or continues
+    children: WaitQueue,
+    /// How often children exited, stopped or continued so far, for `wait` to notice a
+    /// change it hasn't seen yet
+    child_changes: AtomicUsize,
+    /// A stop or continue the parent hasn't collected with `wait4` yet, as a wait status,
+    /// 0 if there is none. Only changes with the process table locked.
+    unreported: AtomicUsize,
+    /// Where the process' thread sleeps while the process is stopped
+    continued: WaitQueue,
+}
+
+// the FP state is only touched by the hart running the process' thread
+unsafe impl Sync for Process {}
+
+impl Process {
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub fn parent(&self) -> usize {
+        self.parent.load(Ordering::Relaxed)
+    }
+
+    fn is_zombie(&self) -> bool {
+        self.status.load(Ordering::Acquire) != RUNNING
+    }
+
+    /// Wakes the process' thread if it sleeps, so that a sleep a signal interrupts
+    /// notices the signal. The process table has to be locked.
+    fn interrupt(&self) {
+        let thread = self.thread.load(Ordering::Acquire);
+        if !thread.is_null() && !self.is_zombie() {
+            // through the queues the process sleeps on itself, whose lock orders the
+            // wakeup against their check for signals
+            self.children.wake_all();
+            self.continued.wake_all();
+            sched::wake(thread);
+        }
+    }
+
+    /// Wakes the process if it waits for a child to change state.
+    fn child_changed(&self) {
+        self.child_changes.fetch_add(1, Ordering::Release);
+        self.children.wake_all();
+    }
+}
+
+/// Every live process, 0 for free slots. Signals are sent from interrupt handlers too.
+static PROCESSES: IrqSpinLock<[usize; MAX_PROCESSES]> = IrqSpinLock::new([0; MAX_PROCESSES]);
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+fn alloc_process(space: AddressSpace, start: TrapFrame, parent: usize) -> Option<*mut Process> {
+    let mut table = PROCESSES.lock();
+    let slot = table.iter().position(|&p| p == 0)?;
+    let process = zalloc(size_of::<Process>().div_ceil(PAGE_SIZE)) as *mut Process;
+    if process.is_null() {
+        return None;
+    }
+    unsafe {
+        process.write(Process {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            root: AtomicPtr::new(space.root()),
+            space: SpinLock::new(space),
+            files: SpinLock::new(FileTable::with_console()),
+            start,
+            fp: UnsafeCell::new(FpState::default()),
+            parent: AtomicUsize::new(parent),
+            status: AtomicUsize::new(RUNNING),
+            signals: Signals::new(),
+            thread: AtomicPtr::new(core::ptr::null_mut()),
+            children: WaitQueue::new(),
+            child_changes: AtomicUsize::new(0),
+            unreported: AtomicUsize::new(0),
+            continued: WaitQueue::new(),
+        });
+    }
+    table[slot] = process as usize;
+    Some(process)
+}
+
+/// Frees a process that is no longer in the process table.
+fn destroy(process: *mut Process) {
+    unsafe { process.drop_in_place() };
+    dealloc(process as *mut u8);
+}
+
+fn free_process(process: *mut Process) {
+    let mut table = PROCESSES.lock();
+    if let Some(slot) = table.iter_mut().find(|p| **p == process as usize) {
+        *slot = 0;
+    }
+    drop(table);
+    destroy(process);
+}
+
+/// The registers a program starts with: everything zero but the stack, returning to
+/// U-mode with interrupts and the FP unit on.
+pub fn initial_frame(entry: usize, sp: usize) -> TrapFrame {
+    let mut frame = TrapFrame::default();
+    frame.regs[2] = sp;
+    frame.sepc = entry;
+    frame.sstatus = (cpu::sstatus::read()
+        & !(SSTATUS_SPP | SSTATUS_SIE | SSTATUS_SUM | SSTATUS_FS))
+        | SSTATUS_SPIE
+        | SSTATUS_FS_INITIAL;
+    frame
+}
+
+/// Gives `space` a stack for the main thread and returns its top.
+pub fn map_stack(space: &mut AddressSpace) -> Option<usize> {
+    let bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+    space
+        .map_zeroed(bottom, USER_STACK_TOP, EntryBits::ReadWrite as i64)
+        .then_some(USER_STACK_TOP)
+}
+
+/// Starts a process in `space` that begins with the registers in `start`. Returns its
+/// pid.
+pub fn spawn(space: AddressSpace, start: TrapFrame) -> Option<usize> {
+    start_process(alloc_process(space, start, 0)?)
+}
+
+/// Starts the thread of a new process. Returns its pid.
+fn start_process(process: *mut Process) -> Option<usize> {
+    let p = unsafe { &*process };
+    let Some(handle) = thread::spawn_with(run, process as usize, Policy::default(), usize::MAX)
+    else {
+        free_process(process);
+        return None;
+    };
+    let thread = handle.thread() as *const Thread as *mut Thread;
+    p.thread.store(thread, Ordering::Release);
+    Some(p.pid)
+}
+
+/// Starts a process running a flat binary loaded at `load_addr`, which is also its
+/// entry point.
+pub fn spawn_flat(code: &[u8], load_addr: usize) -> Option<usize> {
+    let mut space = AddressSpace::new()?;
+    let end = load_addr + code.len();
+    if !space.map_zeroed(load_addr, end, EntryBits::ReadExecute as i64)
+        || !space.write(load_addr, code)
+    {
+        return None;
+    }
+    space.set_brk_start(end);
+    let sp = map_stack(&mut space)?;
+    spawn(space, initial_frame(load_addr, sp))
+}
+
+/// Loads the ELF executable in `data` into a new address space with a stack that holds
+/// `argv` and `envp`. Returns the space and the registers to start the program with.
+pub fn load_program(
+    data: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<(AddressSpace, TrapFrame), LoadError> {
+    let elf = Elf::parse(data)?;
+    let mut space = AddressSpace::new().ok_or(LoadError::NoMemory)?;
+    let image = loader::load(&mut space, &elf)?;
+    space.set_brk_start(image.end);
+    let top = map_stack(&mut space).ok_or(LoadError::NoMemory)?;
+    if !signal::map_trampoline(&mut space) {
+        return Err(LoadError::NoMemory);
+    }
+    let sp = loader::setup_stack(&mut space, top, argv, envp, &image)?;
+    Ok((space, initial_frame(image.entry, sp)))
+}
+
+/// Starts a process running the ELF executable in `data`. Returns its pid.
+pub fn spawn_elf(data: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> Result<usize, LoadError> {
+    let (space, start) = load_program(data, argv, envp)?;
+    spawn(space, start).ok_or(LoadError::NoMemory)
+}
+
+/// Starts a child of the calling process with a copy of its memory, files and FP
+/// registers, that begins with the registers in `start`. Returns its pid.
+pub fn fork(start: TrapFrame) -> Option<usize> {
+    let parent = current()?;
+    let space = parent.space.lock().duplicate()?;
+    let child = alloc_process(space, start, parent.pid)?;
+    let c = unsafe { &*child };
+    *c.files.lock() = parent.files.lock().clone();
+    c.signals.inherit(&parent.signals);
+    // the parent's registers are live, it's the calling process
+    unsafe { fp::save(c.fp.get()) };
+    start_process(child)
+}
+
+/// Replaces the program of the calling process with the ELF executable in `data`.
+/// `frame` gets the registers of the new program. On failure the old program
+/// continues.
+pub fn exec(
+    frame: &mut TrapFrame,
+    data: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<(), LoadError> {
+    let process = current().expect("exec from a kernel thread");
+    let (space, start) = load_program(data, argv, envp)?;
+
+    let irqs_enabled = cpu::disable_interrupts();
+    process.root.store(space.root(), Ordering::Relaxed);
+    page::activate(space.root());
+    let old = core::mem::replace(&mut *process.space.lock(), space);
+    if irqs_enabled {
+        cpu::enable_interrupts();
+    }
+    drop(old);
+    process.signals.reset_handlers();
+
+    let fp = process.fp.get();
+    unsafe {
+        fp.write(FpState::default());
+        fp::restore(fp);
+    }
+    *frame = start;
+    Ok(())
+}
+
+/// The process' thread, on its way to U-mode
+fn run(process: usize) {
+    let process = process as *mut Process;
+    let thread = thread::current();
+
+    cpu::disable_interrupts();
+    thread.set_process(process);
+    page::activate(unsafe { &*process }.root.load(Ordering::Relaxed));
+    let top = percpu::this_cpu_raw().trap_stack_top();
+    unsafe { enter_user(&(*process).start, top) }
+}
+
+/// Copies `frame` to the top of the kernel stack and returns to U-mode with it.
+/// Interrupts have to be off.
+#[unsafe(naked)]
+unsafe extern "C" fn enter_user(frame: *const TrapFrame, stack_top: usize) -> ! {
+    naked_asm!(
+        "addi sp, a1, -{size}
+        li t0, 0
+        li t1, {size}
+    1:
+        add t2, a0, t0
+        ld t3, 0(t2)
+        add t2, sp, t0
+        sd t3, 0(t2)
+        addi t0, t0, 8
+        blt t0, t1, 1b
+        j trap_return",
+        size = const trap::TRAP_FRAME_SIZE,
+    );
+}
+
+/// The calling thread's process
+pub fn current() -> Option<&'static Process> {
+    thread::current().process()
+}
+
+/// Ends the calling thread's process.
+pub fn exit(code: i32) -> ! {
+    let pid = current().map_or(0, Process::pid);
+    info!("process {} exited with {}", pid, code);
+    do_exit((code as usize & 0xff) << 8)
+}
+
+/// Ends the calling thread's process with the wait status `status`. The process stays
+/// a zombie until its parent waits for it.
+fn do_exit(status: usize) -> ! {
+    let thread = thread::current();
+    let process = thread.process_ptr();
+    assert!(!process.is_null(), "exit from a kernel thread");
+    let p = unsafe { &*process };
+
+    let irqs_enabled = cpu::disable_interrupts();
+    thread.set_process(core::ptr::null_mut());
+    page::activate(kmem::get_page_table());
+    if irqs_enabled {
+        cpu::enable_interrupts();
+    }
+    p.space.lock().clear();
+    *p.files.lock() = FileTable::new();
+
+    let mut table = PROCESSES.lock();
+    let init_alive = table.iter().any(|&other| {
+        let other = unsafe { (other as *const Process).as_ref() };
+        other.is_some_and(|o| o.pid == INIT_PID && !o.is_zombie())
+    });
+    let heir = if init_alive && p.pid != INIT_PID {
+        INIT_PID
+    } else {
+        0
+    };
+    let mut adopted_zombie = false;
+    for slot in table.iter_mut() {
+        let Some(child) = (unsafe { (*slot as *const Process).as_ref() }) else {
+            continue;
+        };
+        if child.parent() != p.pid {
+            continue;
+        }
+        child.parent.store(heir, Ordering::Relaxed);
+        if child.is_zombie() {
+            adopted_zombie = true;
+            // nobody is going to wait for it anymore
+            if heir == 0 {
+                destroy(*slot as *mut Process);
+                *slot = 0;
+            }
+        }
+    }
+    if adopted_zombie && heir != 0 {
+        let init = table
+            .iter()
+            .filter_map(|&other| unsafe { (other as *const Process).as_ref() })
+            .find(|o| o.pid == heir);
+        if let Some(init) = init {
+            init.child_changed();
+        }
+    }
+
+    p.status.store(status, Ordering::Release);
+    signal::notify_parent(&*table, p, status);
+    if p.parent() == 0 {
+        if let Some(slot) = table.iter_mut().find(|slot| **slot == process as usize) {
+            *slot = 0;
+        }
+        destroy(process);
+    }
+    drop(table);
+    thread::exit()
+}
+
+/// Waits for a child of the calling process to exit, the one with pid `pid` or any if
+/// `pid` isn't positive. With `WUNTRACED` or `WCONTINUED` in `options` a child that
+/// stopped or continued since it was last waited for counts too. Returns the child's
+/// pid and wait status and frees the child if it exited, or `None` if no child changed
+/// yet and `options` has `WNOHANG`.
+pub fn wait(pid: isize, options: usize) -> Result<Option<(usize, usize)>, Errno> {
+    let process = current().ok_or(Errno::Child)?;
+    let me = process.pid;
+    loop {
+        let seen = process.child_changes.load(Ordering::Acquire);
+        let mut table = PROCESSES.lock();
+        let mut has_child = false;
+        for slot in table.iter_mut() {
+            let Some(child) = (unsafe { (*slot as *const Process).as_ref() }) else {
+                continue;
+            };
+            if child.parent() != me || (pid > 0 && child.pid != pid as usize) {
+                continue;
+            }
+            has_child = true;
+            if child.is_zombie() {
+                let reaped = (child.pid, child.status.load(Ordering::Relaxed));
+                let process = *slot as *mut Process;
+                *slot = 0;
+                drop(table);
+                destroy(process);
+                return Ok(Some(reaped));
+            }
+            let status = child.unreported.load(Ordering::Relaxed);
+            let wanted = match status {
+                0 => false,
+                signal::CONTINUED_STATUS => options & WCONTINUED != 0,
+                _ => options & WUNTRACED != 0,
+            };
+            if wanted {
+                child.unreported.store(0, Ordering::Relaxed);
+                return Ok(Some((child.pid, status)));
+            }
+        }
+        drop(table);
+
+        if !has_child {
+            return Err(Errno::Child);
+        }
+        if options & WNOHANG != 0 {
+            return Ok(None);
+        }
+        if signal::interrupted() {
+            return Err(Errno::Intr);
+        }
+        process.children.wait_until(|| {
+            process.child_changes.load(Ordering::Acquire) != seen || signal::interrupted()
+        });
+    }
+}
+
+/// Handles an exception from U-mode. Runs with interrupts on.
+pub fn handle_exception(frame: &mut TrapFrame, scause: usize, stval: usize) {
+    if scause == EXC_ECALL_U {
+        syscall::dispatch(frame);
+        return;
+    }
+
+    let pid = current().map_or(0, Process::pid);
+    debug!(
+        "process {}: exception {} ({}) at 0x{:x}, stval 0x{:x}",
+        pid,
+        scause,
+        trap::exception_name(scause),
+        frame.sepc,
+        stval
+    );
+    let unmapped = || current().is_some_and(|p| p.space.lock().translate(stval).is_none());
+    let (sig, info) = match scause {
+        EXC_INSTRUCTION_MISALIGNED | EXC_LOAD_MISALIGNED | EXC_STORE_MISALIGNED => {
+            (signal::SIGBUS, SigInfo::fault(signal::BUS_ADRALN, stval))
+        }
+        EXC_ILLEGAL_INSTRUCTION => (
+            signal::SIGILL,
+            SigInfo::fault(signal::ILL_ILLOPC, frame.sepc),
+        ),
+        EXC_BREAKPOINT => (
+            signal::SIGTRAP,
+            SigInfo::fault(signal::TRAP_BRKPT, frame.sepc),
+        ),
+        EXC_INSTRUCTION_PAGE_FAULT | EXC_LOAD_PAGE_FAULT | EXC_STORE_PAGE_FAULT if unmapped() => {
+            (signal::SIGSEGV, SigInfo::fault(signal::SEGV_MAPERR, stval))
+        }
+        _ => (signal::SIGSEGV, SigInfo::fault(signal::SEGV_ACCERR, stval)),
+    };
+    signal::force(sig, info);
+}
+
+/// Called by the scheduler right before it switches from `prev` to `next`, to switch
+/// the address space, the FP registers and the stack traps from U-mode land on.
+pub(crate) fn switch(prev: &Thread, next: &Thread) {
+    let prev_process = prev.process_ptr();
+    let next_process = next.process_ptr();
+
+    if let Some(p) = unsafe { prev_process.as_ref() } {
+        unsafe { fp::save(p.fp.get()) };
+    }
+    if !next.is_idle() {
+        percpu::this_cpu_raw().set_trap_stack_top(next.stack_top());
+    }
+    if prev_process != next_process {
+        match unsafe { next_process.as_ref() } {
+            Some(p) => page::activate(p.root.load(Ordering::Relaxed)),
+            None => page::activate(kmem::get_page_table()),
+        }
+    }
+    if let Some(p) = unsafe { next_process.as_ref() } {
+        unsafe { fp::restore(p.fp.get()) };
+    }
+}
+
+/// Starts the first process.
+pub fn init() {
+    let Some(data) = programs::find(b"/init") else {
+        warn!("no init program");
+        return;
+    };
+    match spawn_elf(data, &[b"/init"], &[]) {
+        Ok(pid) => info!("started init as process {}", pid),
+        Err(e) => warn!("failed to start init: {}", e),
+    }
+}
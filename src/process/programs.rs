@@ -0,0 +1,48 @@
+//! Executables built into the kernel, which `execve` runs until there is a file system
+//! to load programs from.
+
+/// A minimal static executable that writes a greeting to stdout and exits with 0:
+///
+/// ```text
+/// 0x10078: li a0, 1; auipc a1, 0; addi a1, a1, 32; li a2, 23; li a7, 64; ecall
+///          li a0, 0; li a7, 93; ecall
+/// 0x1009c: "Hello from user space!\n"
+/// ```
+#[rustfmt::skip]
+const HELLO: [u8; 179] = [
+    // ELF header: ELF64, little endian, ET_EXEC, EM_RISCV, entry 0x10078, one program
+    // header at offset 64
+    0x7f, 0x45, 0x4c, 0x46, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0xf3, 0x00, 0x01, 0x00, 0x00, 0x00,
+    0x78, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x05, 0x00, 0x00, 0x00, 0x40, 0x00, 0x38, 0x00, 0x01, 0x00, 0x40, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    // PT_LOAD R+X: the whole file at 0x10000
+    0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb3, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xb3, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // code
+    0x13, 0x05, 0x10, 0x00, 0x97, 0x05, 0x00, 0x00, 0x93, 0x85, 0x05, 0x02,
+    0x13, 0x06, 0x70, 0x01, 0x93, 0x08, 0x00, 0x04, 0x73, 0x00, 0x00, 0x00,
+    0x13, 0x05, 0x00, 0x00, 0x93, 0x08, 0xd0, 0x05, 0x73, 0x00, 0x00, 0x00,
+    // message
+    0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x66, 0x72, 0x6f, 0x6d, 0x20, 0x75,
+    0x73, 0x65, 0x72, 0x20, 0x73, 0x70, 0x61, 0x63, 0x65, 0x21, 0x0a,
+];
+
+/// Where `spawn_flat` binaries usually go, the default link address of Linux programs
+pub const FLAT_LOAD_ADDR: usize = 0x1_0000;
+
+/// Paths and contents of the built-in executables
+static PROGRAMS: [(&[u8], &[u8]); 1] = [(b"/init", &HELLO)];
+
+/// The executable at `path`
+pub fn find(path: &[u8]) -> Option<&'static [u8]> {
+    PROGRAMS
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, data)| *data)
+}
@@ -0,0 +1,587 @@
+//! POSIX signals.
+//!
+//! A signal stays pending on its process until the process next returns to U-mode,
+//! where the trap handler calls [`deliver`]. Signals without a handler take their
+//! default action. A handler runs on the process' stack, below a Linux compatible
+//! `rt_sigframe` with the interrupted registers, and returns to a trampoline page that
+//! calls `rt_sigreturn` to restore them.
+
+use core::{mem::offset_of, sync::atomic::Ordering};
+
+use crate::{
+    alloc::PAGE_SIZE,
+    info,
+    page::EntryBits,
+    sync::IrqSpinLock,
+    syscall::{Errno, SysResult},
+    trap::TrapFrame,
+    uaccess::{copy_from_user, copy_to_user},
+};
+
+use super::{
+    INIT_PID, PROCESSES, Process,
+    fp::{self, FpState},
+    mm::{AddressSpace, MMAP_TOP},
+};
+
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+/// `sa_handler` values that aren't handlers
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// `sa_flags`
+pub const SA_NOCLDSTOP: usize = 1;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// `si_code` values
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const ILL_ILLOPC: i32 = 1;
+pub const TRAP_BRKPT: i32 = 1;
+pub const BUS_ADRALN: i32 = 1;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// Set in the wait status of a process killed by a signal that dumps core. No core
+/// file is written, there is nowhere to write it to.
+pub const WCOREFLAG: usize = 0x80;
+
+/// The wait status of a process that continued after a stop
+pub const CONTINUED_STATUS: usize = 0xffff;
+
+/// The wait status of a process stopped by `sig`
+pub const fn stopped_status(sig: usize) -> usize {
+    (sig << 8) | 0x7f
+}
+
+/// Where the page with the code handlers return to is mapped
+pub const TRAMPOLINE: usize = MMAP_TOP;
+/// `li a7, 139; ecall`, i.e. `rt_sigreturn()`
+const TRAMPOLINE_CODE: [u8; 8] = [0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+
+/// `ss_flags` of a process without an alternate signal stack
+const SS_DISABLE: usize = 2;
+
+pub const fn bit(sig: usize) -> u64 {
+    1 << (sig - 1)
+}
+
+/// Signals that can't be caught, blocked or ignored
+const UNBLOCKABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+const STOP_SIGNALS: u64 = bit(SIGSTOP) | bit(SIGTSTP) | bit(SIGTTIN) | bit(SIGTTOU);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DefaultAction {
+    Terminate,
+    Core,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::Core,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// `struct sigaction` as the kernel takes it on RISC-V, which has no `sa_restorer`
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: u64,
+}
+
+impl SigAction {
+    fn ignores(&self, sig: usize) -> bool {
+        self.handler == SIG_IGN
+            || (self.handler == SIG_DFL && default_action(sig) == DefaultAction::Ignore)
+    }
+}
+
+/// What a handler learns about its signal
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SigInfo {
+    pub code: i32,
+    /// The sender's pid, or the faulting address for faults
+    pub value: usize,
+}
+
+impl SigInfo {
+    pub fn user(pid: usize) -> Self {
+        Self {
+            code: SI_USER,
+            value: pid,
+        }
+    }
+
+    pub fn kernel() -> Self {
+        Self {
+            code: SI_KERNEL,
+            value: 0,
+        }
+    }
+
+    pub fn fault(code: i32, addr: usize) -> Self {
+        Self { code, value: addr }
+    }
+}
+
+struct State {
+    pending: u64,
+    blocked: u64,
+    info: [SigInfo; NSIG],
+    actions: [SigAction; NSIG],
+}
+
+/// The signal state of a process. Signals can be sent from interrupt handlers.
+pub struct Signals(IrqSpinLock<State>);
+
+impl Signals {
+    pub const fn new() -> Self {
+        Self(IrqSpinLock::new(State {
+            pending: 0,
+            blocked: 0,
+            info: [SigInfo { code: 0, value: 0 }; NSIG],
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: 0,
+                mask: 0,
+            }; NSIG],
+        }))
+    }
+
+    /// Takes over the actions and the mask of `parent`, as a child does on fork.
+    pub fn inherit(&self, parent: &Signals) {
+        let (actions, blocked) = {
+            let p = parent.0.lock();
+            (p.actions, p.blocked)
+        };
+        let mut s = self.0.lock();
+        s.actions = actions;
+        s.blocked = blocked;
+    }
+
+    /// Resets the handlers to the default action, as exec does. Ignored signals stay
+    /// ignored.
+    pub fn reset_handlers(&self) {
+        for action in self.0.lock().actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.0.lock().pending
+    }
+
+    /// Whether a signal is waiting to be delivered, which interrupts blocking calls
+    pub fn has_deliverable(&self) -> bool {
+        let s = self.0.lock();
+        s.pending & !s.blocked != 0
+    }
+
+    pub fn blocked(&self) -> u64 {
+        self.0.lock().blocked
+    }
+
+    pub fn set_blocked(&self, mask: u64) {
+        self.0.lock().blocked = mask & !UNBLOCKABLE;
+    }
+
+    pub fn action(&self, sig: usize) -> SigAction {
+        self.0.lock().actions[sig - 1]
+    }
+
+    /// Installs `action` for `sig` and returns the previous one.
+    pub fn set_action(&self, sig: usize, mut action: SigAction) -> SigAction {
+        action.mask &= !UNBLOCKABLE;
+        let mut s = self.0.lock();
+        if action.ignores(sig) {
+            s.pending &= !bit(sig);
+        }
+        core::mem::replace(&mut s.actions[sig - 1], action)
+    }
+
+    /// Makes `sig` pending, unless the process ignores it.
+    pub fn send(&self, sig: usize, info: SigInfo) {
+        let mut s = self.0.lock();
+        if sig == SIGCONT {
+            s.pending &= !STOP_SIGNALS;
+        } else if bit(sig) & STOP_SIGNALS != 0 {
+            s.pending &= !bit(SIGCONT);
+        }
+        // a stopped process waits for SIGCONT to be pending even if it's ignored
+        if sig != SIGCONT && s.actions[sig - 1].ignores(sig) {
+            return;
+        }
+        s.pending |= bit(sig);
+        s.info[sig - 1] = info;
+    }
+
+    /// Makes `sig` pending even if it is blocked or ignored, for faults the process
+    /// can't continue after.
+    pub fn force(&self, sig: usize, info: SigInfo) {
+        let mut s = self.0.lock();
+        s.blocked &= !bit(sig);
+        if s.actions[sig - 1].handler == SIG_IGN {
+            s.actions[sig - 1] = SigAction::default();
+        }
+        s.pending |= bit(sig);
+        s.info[sig - 1] = info;
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends `sig` to `process`. Init only gets the signals it has a handler for.
+fn send_to(process: &Process, sig: usize, info: SigInfo) {
+    if process.is_zombie()
+        || (process.pid == INIT_PID && process.signals.action(sig).handler == SIG_DFL)
+    {
+        return;
+    }
+    process.signals.send(sig, info);
+    process.interrupt();
+}
+
+/// Sends `sig` to the processes `pid` stands for, as `kill` does: one process, or all
+/// but init and the caller for -1. There are no process groups, the group of a process
+/// is just itself. A `sig` of 0 only checks that there is such a process.
+pub fn kill(pid: isize, sig: usize) -> Result<(), Errno> {
+    if sig > NSIG {
+        return Err(Errno::Inval);
+    }
+    let me = super::current().map_or(0, Process::pid);
+    let target = match pid {
+        -1 => None,
+        0 => Some(me),
+        pid => Some(pid.unsigned_abs()),
+    };
+
+    let table = PROCESSES.lock();
+    let mut found = false;
+    for &p in table.iter() {
+        let Some(p) = (unsafe { (p as *const Process).as_ref() }) else {
+            continue;
+        };
+        let matches = match target {
+            None => p.pid != INIT_PID && p.pid != me,
+            Some(pid) => p.pid == pid,
+        };
+        if matches && !p.is_zombie() {
+            found = true;
+            if sig != 0 {
+                send_to(p, sig, SigInfo::user(me));
+            }
+        }
+    }
+    if found { Ok(()) } else { Err(Errno::Srch) }
+}
+
+/// Sends SIGINT to every process, for a Ctrl-C on the console. Can be called from
+/// interrupt handlers.
+pub fn interrupt_all() {
+    let table = PROCESSES.lock();
+    for &p in table.iter() {
+        if let Some(p) = unsafe { (p as *const Process).as_ref() } {
+            send_to(p, SIGINT, SigInfo::kernel());
+        }
+    }
+}
+
+/// Tells the parent of `child` that it exited, stopped or continued with wait status
+/// `status`, and wakes it if it waits. A parent with `SA_NOCLDSTOP` gets no SIGCHLD for
+/// stops and continues. The process table has to be locked, `table` is its contents.
+pub(super) fn notify_parent(table: &[usize], child: &Process, status: usize) {
+    let code = match status {
+        CONTINUED_STATUS => CLD_CONTINUED,
+        _ if status & 0xff == 0x7f => CLD_STOPPED,
+        _ if status & 0x7f == 0 => CLD_EXITED,
+        _ if status & WCOREFLAG != 0 => CLD_DUMPED,
+        _ => CLD_KILLED,
+    };
+    let parent = table
+        .iter()
+        .filter_map(|&p| unsafe { (p as *const Process).as_ref() })
+        .find(|p| p.pid == child.parent());
+    if let Some(parent) = parent {
+        let quiet = matches!(code, CLD_STOPPED | CLD_CONTINUED)
+            && parent.signals.action(SIGCHLD).flags & SA_NOCLDSTOP != 0;
+        if !quiet {
+            send_to(
+                parent,
+                SIGCHLD,
+                SigInfo {
+                    code,
+                    value: child.pid,
+                },
+            );
+        }
+        parent.child_changed();
+    }
+}
+
+/// Leaves `status` for the parent of the calling process to collect with `wait4`.
+fn report(process: &Process, status: usize) {
+    let table = PROCESSES.lock();
+    process.unreported.store(status, Ordering::Relaxed);
+    notify_parent(&*table, process, status);
+}
+
+/// Whether the calling process has a signal to handle, which interrupts blocking calls
+pub fn interrupted() -> bool {
+    super::current().is_some_and(|p| p.signals.has_deliverable())
+}
+
+/// Sends `sig` to the calling process for a fault in it.
+pub fn force(sig: usize, info: SigInfo) {
+    if let Some(process) = super::current() {
+        process.signals.force(sig, info);
+    }
+}
+
+/// Maps the page handlers return to.
+pub fn map_trampoline(space: &mut AddressSpace) -> bool {
+    space.map_zeroed(
+        TRAMPOLINE,
+        TRAMPOLINE + PAGE_SIZE,
+        EntryBits::ReadExecute as i64,
+    ) && space.write(TRAMPOLINE, &TRAMPOLINE_CODE)
+}
+
+/// `siginfo_t`
+#[repr(C)]
+struct RawSigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    /// `si_pid` and `si_uid`, or `si_addr`
+    fields: [usize; 14],
+}
+
+/// `struct __riscv_fp_state`, the D extension variant of the union
+#[repr(C)]
+struct FpContext {
+    f: [u64; 32],
+    fcsr: u32,
+    _reserved: [u8; 268],
+}
+
+/// `struct sigcontext`: `pc` in place of `x0`, then `x1` to `x31`
+#[repr(C, align(16))]
+struct MContext {
+    regs: [usize; 32],
+    fp: FpContext,
+}
+
+/// `struct ucontext`
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `ss_sp`, `ss_flags`, `ss_size`
+    stack: [usize; 3],
+    sigmask: u64,
+    _unused: [u8; 120],
+    _pad: u64,
+    mcontext: MContext,
+}
+
+/// `struct rt_sigframe`, what a handler finds on its stack
+#[repr(C)]
+struct SigFrame {
+    info: RawSigInfo,
+    uc: UContext,
+}
+
+const _: () = assert!(size_of::<RawSigInfo>() == 128);
+const _: () = assert!(size_of::<UContext>() == 960);
+const _: () = assert!(size_of::<SigFrame>() == 1088);
+
+impl SigFrame {
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, size_of::<Self>()) }
+    }
+}
+
+/// Pushes a signal frame for `sig` and points `frame` at `handler`.
+fn setup_frame(
+    frame: &mut TrapFrame,
+    sig: usize,
+    info: &SigInfo,
+    handler: usize,
+    mask: u64,
+) -> Result<(), Errno> {
+    // all integers, all zero is a valid frame
+    let mut f: SigFrame = unsafe { core::mem::zeroed() };
+    f.info.signo = sig as i32;
+    f.info.code = info.code;
+    f.info.fields[0] = info.value;
+    f.uc.stack[1] = SS_DISABLE;
+    f.uc.sigmask = mask;
+    f.uc.mcontext.regs[0] = frame.sepc;
+    f.uc.mcontext.regs[1..].copy_from_slice(&frame.regs[1..]);
+
+    // the process' FP registers are live, it's the calling process
+    let mut fp = FpState::default();
+    unsafe { fp::save(&mut fp) };
+    f.uc.mcontext.fp.f = fp.f;
+    f.uc.mcontext.fp.fcsr = fp.fcsr as u32;
+
+    let addr = frame.regs[2]
+        .checked_sub(size_of::<SigFrame>())
+        .ok_or(Errno::Fault)?
+        & !0xf;
+    copy_to_user(addr, f.bytes_mut())?;
+
+    frame.sepc = handler;
+    frame.regs[1] = TRAMPOLINE;
+    frame.regs[2] = addr;
+    frame.regs[10] = sig;
+    frame.regs[11] = addr + offset_of!(SigFrame, info);
+    frame.regs[12] = addr + offset_of!(SigFrame, uc);
+    Ok(())
+}
+
+/// `rt_sigreturn`: restores the registers and the mask saved in the signal frame at
+/// `sp`. Returns `a0` as it was before the signal.
+pub fn sigreturn(frame: &mut TrapFrame) -> SysResult {
+    let process = super::current().ok_or(Errno::Fault)?;
+    let mut f: SigFrame = unsafe { core::mem::zeroed() };
+    if let Err(e) = copy_from_user(f.bytes_mut(), frame.regs[2]) {
+        process.signals.force(SIGSEGV, SigInfo::kernel());
+        return Err(e);
+    }
+
+    frame.sepc = f.uc.mcontext.regs[0];
+    frame.regs[1..].copy_from_slice(&f.uc.mcontext.regs[1..]);
+    process.signals.set_blocked(f.uc.sigmask);
+
+    let fp = FpState {
+        f: f.uc.mcontext.fp.f,
+        fcsr: f.uc.mcontext.fp.fcsr as u64,
+    };
+    unsafe { fp::restore(&fp) };
+    Ok(frame.regs[10])
+}
+
+/// Stops the process for `sig` until SIGCONT or SIGKILL.
+fn stop(process: &Process, sig: usize) {
+    const RESUME: u64 = bit(SIGCONT) | bit(SIGKILL);
+
+    info!("process {} stopped by signal {}", process.pid, sig);
+    report(process, stopped_status(sig));
+    process
+        .continued
+        .wait_until(|| process.signals.pending() & RESUME != 0);
+    if process.signals.pending() & bit(SIGKILL) == 0 {
+        info!("process {} continued", process.pid);
+        report(process, CONTINUED_STATUS);
+    }
+}
+
+/// Ends the calling process for `sig`.
+fn terminate(process: &Process, sig: usize, core: bool) -> ! {
+    info!("process {} killed by signal {}", process.pid, sig);
+    super::do_exit(sig | if core { WCOREFLAG } else { 0 })
+}
+
+/// Delivers the pending signals of the calling process before it returns to U-mode
+/// with `frame`: takes their default actions or sets up a handler to run.
+pub fn deliver(frame: &mut TrapFrame) {
+    let Some(process) = super::current() else {
+        return;
+    };
+    loop {
+        let mut s = process.signals.0.lock();
+        let ready = s.pending & !s.blocked;
+        if ready == 0 {
+            return;
+        }
+        let sig = ready.trailing_zeros() as usize + 1;
+        s.pending &= !bit(sig);
+        let info = s.info[sig - 1];
+        let action = s.actions[sig - 1];
+        let mask = s.blocked;
+        if action.handler > SIG_IGN {
+            if action.flags & SA_RESETHAND != 0 {
+                s.actions[sig - 1] = SigAction::default();
+            }
+            s.blocked |= action.mask;
+            if action.flags & SA_NODEFER == 0 {
+                s.blocked |= bit(sig);
+            }
+            s.blocked &= !UNBLOCKABLE;
+        }
+        drop(s);
+
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => stop(process, sig),
+                DefaultAction::Terminate => terminate(process, sig, false),
+                DefaultAction::Core => terminate(process, sig, true),
+            },
+            handler => {
+                if setup_frame(frame, sig, &info, handler, mask).is_err() {
+                    terminate(process, SIGSEGV, true);
+                }
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The console as the terminal of the processes.
+//!
+//! There are no console interrupts, so input is polled on every timer tick into a buffer
+//! that `read` takes it from, and readers sleep until the tick brings them something.
+//! That way a Ctrl-C reaches the processes as SIGINT even if none of them is reading.
+//!
+//! [`AVAILABLE`] counts the bytes in the buffer, so a reader can tell whether there is
+//! one without locking it. Readers take turns through [`READER`] and wait for input on
+//! [`ARRIVED`], which the tick notifies.
+
+use crate::{
+    console,
+    sync::{Condvar, IrqSpinLock, Mutex, Semaphore},
+};
+
+use super::signal;
+
+const INPUT_SIZE: usize = 256;
+
+/// What Ctrl-C sends
+const CTRL_C: u8 = 0x03;
+
+/// A ring buffer of the bytes nobody has read yet
+struct Input {
+    buf: [u8; INPUT_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Input {
+    /// Drops `byte` and returns false if the buffer is full.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == INPUT_SIZE {
+            return false;
+        }
+        self.buf[(self.head + self.len) % INPUT_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % INPUT_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+static INPUT: IrqSpinLock<Input> = IrqSpinLock::new(Input {
+    buf: [0; INPUT_SIZE],
+    head: 0,
+    len: 0,
+});
+
+/// One unit for every byte in [`INPUT`]. A reader takes a unit before it takes a byte,
+/// which may be gone by then if a Ctrl-C threw it away.
+static AVAILABLE: Semaphore = Semaphore::new(0);
+
+/// Held for a whole `read`, so that concurrent reads don't split a line between them
+static READER: Mutex<()> = Mutex::new(());
+
+/// Notified when input or a Ctrl-C arrived
+static ARRIVED: Condvar = Condvar::new();
+
+/// Moves what arrived on the console into the input buffer. Called from the timer
+/// interrupt.
+pub fn poll() {
+    // one hart at a time is enough
+    let Some(mut input) = INPUT.try_lock() else {
+        return;
+    };
+    let mut interrupt = false;
+    let mut arrived = false;
+    while let Some(byte) = console::read_byte() {
+        arrived = true;
+        if byte == CTRL_C {
+            // like a terminal, throw away what the interrupted program didn't read
+            input.len = 0;
+            while AVAILABLE.try_acquire() {}
+            interrupt = true;
+        } else if input.push(byte) {
+            AVAILABLE.release();
+        }
+    }
+    drop(input);
+
+    if interrupt {
+        signal::interrupt_all();
+    }
+    if arrived {
+        ARRIVED.notify_all();
+    }
+}
+
+/// Fills `buf` with input, sleeping until there is at least one byte. Returns `None`
+/// if a signal for the calling process interrupted the sleep.
+pub fn read(buf: &mut [u8]) -> Option<usize> {
+    let mut reader = READER.lock();
+    let mut len = 0;
+    while len < buf.len() {
+        if len == 0 {
+            let mut taken = false;
+            reader = ARRIVED.wait_while(reader, |_| {
+                taken = AVAILABLE.try_acquire();
+                !taken && !signal::interrupted()
+            });
+            if !taken {
+                return None;
+            }
+        } else if !AVAILABLE.try_acquire() {
+            break;
+        }
+        if let Some(byte) = INPUT.lock().pop() {
+            buf[len] = byte;
+            len += 1;
+        }
+    }
+    Some(len)
+}
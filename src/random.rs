@@ -0,0 +1,113 @@
+//! Random bytes for programs, such as the `AT_RANDOM` bytes musl takes its stack
+//! protector canary from.
+//!
+//! If every hart implements Zkr, the `seed` CSR supplies real entropy. Otherwise the bytes
+//! mix the timer, the cycle counter, the hart id and how often [`fill`] was called since
+//! boot. The cycle counter makes them hard to guess from outside, but that fallback is
+//! no cryptographic source. There is no virtio-rng driver to ask instead yet.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{cpu, fdt, info, time};
+
+/// `seed` status in bits 31:30: 16 bits of entropy are in bits 15:0
+const SEED_ES16: usize = 0b10;
+/// The entropy source failed for good
+const SEED_DEAD: usize = 0b11;
+/// How often to read `seed` for each 16 bits before giving up on it
+const SEED_TRIES: usize = 100;
+
+static HAS_SEED: AtomicBool = AtomicBool::new(false);
+/// Advanced on every call, so two calls within one timer tick differ anyway
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Checks the device tree for Zkr. The firmware gives S-mode access to `seed` on harts
+/// that have it. Has to run after `fdt::init`.
+pub fn init() {
+    let Some(cpus) = fdt::get().and_then(|fdt| fdt.find_node("/cpus")) else {
+        return;
+    };
+    let mut harts = cpus
+        .children()
+        .filter(|node| node.property_str("device_type") == Some("cpu"))
+        .peekable();
+    let zkr = harts.peek().is_some() && harts.all(|hart| has_zkr(&hart));
+    HAS_SEED.store(zkr, Ordering::Relaxed);
+    if zkr {
+        info!("random: using the Zkr seed CSR");
+    }
+}
+
+/// Looks in both the new `riscv,isa-extensions` list and the older `riscv,isa` string.
+fn has_zkr(hart: &fdt::Node) -> bool {
+    let in_list = hart.property("riscv,isa-extensions").is_some_and(|list| {
+        list.split(|&b| b == 0)
+            .any(|entry| entry.eq_ignore_ascii_case(b"zkr"))
+    });
+    let in_isa = hart
+        .property_str("riscv,isa")
+        .is_some_and(|isa| isa.split('_').any(|ext| ext.eq_ignore_ascii_case("zkr")));
+    in_list || in_isa
+}
+
+/// 16 bits of entropy from the `seed` CSR, `None` if it has none to give
+fn read_seed() -> Option<u16> {
+    for _ in 0..SEED_TRIES {
+        let value: usize;
+        // `seed` has to be accessed with a write
+        unsafe {
+            asm!("csrrw {}, 0x015, zero", out(reg) value);
+        }
+        match value >> 30 & 0b11 {
+            SEED_ES16 => return Some(value as u16),
+            SEED_DEAD => {
+                HAS_SEED.store(false, Ordering::Relaxed);
+                return None;
+            }
+            // still collecting or testing itself
+            _ => core::hint::spin_loop(),
+        }
+    }
+    None
+}
+
+/// 64 bits from the `seed` CSR, or 0 if it has none
+fn seed64() -> u64 {
+    if !HAS_SEED.load(Ordering::Relaxed) {
+        return 0;
+    }
+    (0..4).fold(0, |word, _| word << 16 | read_seed().unwrap_or(0) as u64)
+}
+
+fn read_cycle() -> u64 {
+    let cycle: u64;
+    unsafe {
+        asm!("rdcycle {}", out(reg) cycle);
+    }
+    cycle
+}
+
+/// splitmix64, so that every input bit affects every output bit
+fn mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Fills `buf` with random bytes.
+pub fn fill(buf: &mut [u8]) {
+    let call = CALLS.fetch_add(1, Ordering::Relaxed);
+    let mut state =
+        time::read_time() ^ (cpu::hart_id() as u64).rotate_right(8) ^ call.rotate_right(24);
+    for chunk in buf.chunks_mut(size_of::<u64>()) {
+        // the cycle counter moves on between chunks, the seed is fresh for each
+        state ^= read_cycle().rotate_left(32) ^ seed64();
+        let word = mix(&mut state).to_le_bytes();
+        chunk.copy_from_slice(&word[..chunk.len()]);
+    }
+}
@@ -1,33 +1,51 @@
-use crate::sbi::{SbiResult, call_sbi0, call_sbi1};
+//! The Base extension, always present from SBI v0.2 on
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x10;
+
+pub mod fid {
+    pub const GET_SPEC_VERSION: usize = 0;
+    pub const GET_IMPL_ID: usize = 1;
+    pub const GET_IMPL_VERSION: usize = 2;
+    pub const PROBE_EXTENSION: usize = 3;
+    pub const GET_MVENDORID: usize = 4;
+    pub const GET_MARCHID: usize = 5;
+    pub const GET_MIMPID: usize = 6;
+}
 
 pub fn get_spec_version() -> SbiResult<(usize, usize)> {
-    unsafe { call_sbi0(0x10, 0) }.map(|v| {
-        let major = v >> 24;
-        let minor = v & 0xFFFFFF;
-        (major, minor)
-    })
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_SPEC_VERSION, []) }
+        .into_result()
+        .map(|v| {
+            let major = v >> 24;
+            let minor = v & 0xFFFFFF;
+            (major, minor)
+        })
 }
 
 pub fn get_impl_id() -> SbiResult<usize> {
-    unsafe { call_sbi0(0x10, 1) }
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_IMPL_ID, []) }.into_result()
 }
 
 pub fn get_impl_version() -> SbiResult<usize> {
-    unsafe { call_sbi0(0x10, 2) }
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_IMPL_VERSION, []) }.into_result()
 }
 
 pub fn probe_extension(extension_id: usize) -> SbiResult<bool> {
-    unsafe { call_sbi1(0x10, 3, extension_id) }.map(|v| v != 0)
+    unsafe { sbi_call(EXTENSION_ID, fid::PROBE_EXTENSION, [extension_id]) }
+        .into_result()
+        .map(|v| v != 0)
 }
 
 pub fn get_mvendroid() -> SbiResult<usize> {
-    unsafe { call_sbi0(0x10, 4) }
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_MVENDORID, []) }.into_result()
 }
 
 pub fn get_marchid() -> SbiResult<usize> {
-    unsafe { call_sbi0(0x10, 5) }
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_MARCHID, []) }.into_result()
 }
 
 pub fn get_mimpid() -> SbiResult<usize> {
-    unsafe { call_sbi0(0x10, 6) }
+    unsafe { sbi_call(EXTENSION_ID, fid::GET_MIMPID, []) }.into_result()
 }
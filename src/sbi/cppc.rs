@@ -0,0 +1,67 @@
+//! The Collaborative Processor Performance Control extension (CPPC)
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x43505043;
+
+pub mod fid {
+    pub const PROBE: usize = 0;
+    pub const READ: usize = 1;
+    pub const READ_HI: usize = 2;
+    pub const WRITE: usize = 3;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    HighestPerformance,
+    NominalPerformance,
+    LowestNonlinearPerformance,
+    LowestPerformance,
+    GuaranteedPerformance,
+    DesiredPerformance,
+    MinimumPerformance,
+    MaximumPerformance,
+    PerformanceReductionTolerance,
+    TimeWindow,
+    CounterWraparoundTime,
+    ReferencePerformanceCounter,
+    DeliveredPerformanceCounter,
+    PerformanceLimited,
+    CppcEnable,
+    AutonomousSelectionEnable,
+    AutonomousActivityWindow,
+    EnergyPerformancePreference,
+    ReferencePerformance,
+    LowestFrequency,
+    NominalFrequency,
+    TransitionLatency,
+}
+
+impl Register {
+    pub fn id(&self) -> usize {
+        match self {
+            Register::TransitionLatency => 0x8000_0000,
+            reg => *reg as usize,
+        }
+    }
+}
+
+/// Returns the width of the register in bits, 0 if it isn't implemented.
+pub fn probe(register: Register) -> SbiResult<usize> {
+    unsafe { sbi_call(EXTENSION_ID, fid::PROBE, [register.id()]) }.into_result()
+}
+
+pub fn read(register: Register) -> SbiResult<usize> {
+    unsafe { sbi_call(EXTENSION_ID, fid::READ, [register.id()]) }.into_result()
+}
+
+/// Reads the upper 32 bits of a 64 bit register, only needed on rv32.
+pub fn read_hi(register: Register) -> SbiResult<usize> {
+    unsafe { sbi_call(EXTENSION_ID, fid::READ_HI, [register.id()]) }.into_result()
+}
+
+pub fn write(register: Register, value: u64) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, fid::WRITE, [register.id(), value as usize]) }
+        .into_result()
+        .map(|_| ())
+}
@@ -0,0 +1,33 @@
+//! The Debug Console extension (DBCN), added in SBI v2.0
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x4442434E;
+
+pub mod fid {
+    pub const CONSOLE_WRITE: usize = 0;
+    pub const CONSOLE_READ: usize = 1;
+    pub const CONSOLE_WRITE_BYTE: usize = 2;
+}
+
+/// Writes as many bytes of `bytes` as the firmware accepts and returns how many were written.
+///
+/// The firmware expects a physical address, so `bytes` has to be identity mapped.
+pub fn console_write(bytes: &[u8]) -> SbiResult<usize> {
+    let addr = bytes.as_ptr() as usize;
+    unsafe { sbi_call(EXTENSION_ID, fid::CONSOLE_WRITE, [bytes.len(), addr, 0]) }.into_result()
+}
+
+/// Reads up to `buf.len()` bytes without blocking and returns how many were read.
+///
+/// The firmware expects a physical address, so `buf` has to be identity mapped.
+pub fn console_read(buf: &mut [u8]) -> SbiResult<usize> {
+    let addr = buf.as_mut_ptr() as usize;
+    unsafe { sbi_call(EXTENSION_ID, fid::CONSOLE_READ, [buf.len(), addr, 0]) }.into_result()
+}
+
+pub fn console_write_byte(byte: u8) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, fid::CONSOLE_WRITE_BYTE, [byte as usize]) }
+        .into_result()
+        .map(|_| ())
+}
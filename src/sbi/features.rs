@@ -0,0 +1,291 @@
+//! What the firmware supports, probed once at boot.
+//!
+//! Subsystems should check [`has`] before calling into an extension instead of
+//! probing it themselves.
+
+use core::fmt;
+
+use crate::{
+    info,
+    sbi::{base, cppc, dbcn, fwft, hsm, ipi, legacy, pmu, rfence, srst, sse, sta, susp, time},
+    sync::Once,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Extension {
+    LegacySetTimer,
+    LegacyConsolePutchar,
+    LegacyConsoleGetchar,
+    LegacyClearIpi,
+    LegacySendIpi,
+    LegacyRemoteFenceI,
+    LegacyRemoteSfenceVma,
+    LegacyRemoteSfenceVmaAsid,
+    LegacyShutdown,
+    Time,
+    Ipi,
+    Rfence,
+    Hsm,
+    Srst,
+    Pmu,
+    Dbcn,
+    Susp,
+    Cppc,
+    Nacl,
+    Sta,
+    Sse,
+    Fwft,
+    Mpxy,
+}
+
+impl Extension {
+    pub const ALL: [Extension; 23] = [
+        Extension::LegacySetTimer,
+        Extension::LegacyConsolePutchar,
+        Extension::LegacyConsoleGetchar,
+        Extension::LegacyClearIpi,
+        Extension::LegacySendIpi,
+        Extension::LegacyRemoteFenceI,
+        Extension::LegacyRemoteSfenceVma,
+        Extension::LegacyRemoteSfenceVmaAsid,
+        Extension::LegacyShutdown,
+        Extension::Time,
+        Extension::Ipi,
+        Extension::Rfence,
+        Extension::Hsm,
+        Extension::Srst,
+        Extension::Pmu,
+        Extension::Dbcn,
+        Extension::Susp,
+        Extension::Cppc,
+        Extension::Nacl,
+        Extension::Sta,
+        Extension::Sse,
+        Extension::Fwft,
+        Extension::Mpxy,
+    ];
+
+    pub fn id(&self) -> usize {
+        match self {
+            Extension::LegacySetTimer => legacy::SET_TIMER,
+            Extension::LegacyConsolePutchar => legacy::CONSOLE_PUTCHAR,
+            Extension::LegacyConsoleGetchar => legacy::CONSOLE_GETCHAR,
+            Extension::LegacyClearIpi => legacy::CLEAR_IPI,
+            Extension::LegacySendIpi => legacy::SEND_IPI,
+            Extension::LegacyRemoteFenceI => legacy::REMOTE_FENCE_I,
+            Extension::LegacyRemoteSfenceVma => legacy::REMOTE_SFENCE_VMA,
+            Extension::LegacyRemoteSfenceVmaAsid => legacy::REMOTE_SFENCE_VMA_ASID,
+            Extension::LegacyShutdown => legacy::SHUTDOWN,
+            Extension::Time => time::EXTENSION_ID,
+            Extension::Ipi => ipi::EXTENSION_ID,
+            Extension::Rfence => rfence::EXTENSION_ID,
+            Extension::Hsm => hsm::EXTENSION_ID,
+            Extension::Srst => srst::EXTENSION_ID,
+            Extension::Pmu => pmu::EXTENSION_ID,
+            Extension::Dbcn => dbcn::EXTENSION_ID,
+            Extension::Susp => susp::EXTENSION_ID,
+            Extension::Cppc => cppc::EXTENSION_ID,
+            Extension::Nacl => 0x4E41434C,
+            Extension::Sta => sta::EXTENSION_ID,
+            Extension::Sse => sse::EXTENSION_ID,
+            Extension::Fwft => fwft::EXTENSION_ID,
+            Extension::Mpxy => 0x4D505859,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::LegacySetTimer => "legacy set_timer",
+            Extension::LegacyConsolePutchar => "legacy console_putchar",
+            Extension::LegacyConsoleGetchar => "legacy console_getchar",
+            Extension::LegacyClearIpi => "legacy clear_ipi",
+            Extension::LegacySendIpi => "legacy send_ipi",
+            Extension::LegacyRemoteFenceI => "legacy remote_fence_i",
+            Extension::LegacyRemoteSfenceVma => "legacy remote_sfence_vma",
+            Extension::LegacyRemoteSfenceVmaAsid => "legacy remote_sfence_vma_asid",
+            Extension::LegacyShutdown => "legacy shutdown",
+            Extension::Time => "TIME (timer)",
+            Extension::Ipi => "IPI",
+            Extension::Rfence => "RFENCE (remote fences)",
+            Extension::Hsm => "HSM (hart state management)",
+            Extension::Srst => "SRST (system reset)",
+            Extension::Pmu => "PMU (performance monitoring)",
+            Extension::Dbcn => "DBCN (debug console)",
+            Extension::Susp => "SUSP (system suspend)",
+            Extension::Cppc => "CPPC (performance control)",
+            Extension::Nacl => "NACL (nested acceleration)",
+            Extension::Sta => "STA (steal-time accounting)",
+            Extension::Sse => "SSE (supervisor software events)",
+            Extension::Fwft => "FWFT (firmware features)",
+            Extension::Mpxy => "MPXY (message proxy)",
+        }
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Implementation {
+    Bbl,
+    OpenSbi,
+    Xvisor,
+    Kvm,
+    RustSbi,
+    Diosix,
+    Coffer,
+    Xen,
+    PolarFireHss,
+    Coreboot,
+    Oreboot,
+    Bhyve,
+    Unknown(usize),
+}
+
+impl Implementation {
+    pub fn from_id(id: usize) -> Self {
+        match id {
+            0 => Self::Bbl,
+            1 => Self::OpenSbi,
+            2 => Self::Xvisor,
+            3 => Self::Kvm,
+            4 => Self::RustSbi,
+            5 => Self::Diosix,
+            6 => Self::Coffer,
+            7 => Self::Xen,
+            8 => Self::PolarFireHss,
+            9 => Self::Coreboot,
+            10 => Self::Oreboot,
+            11 => Self::Bhyve,
+            id => Self::Unknown(id),
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bbl => write!(f, "Berkeley Boot Loader (BBL)"),
+            Self::OpenSbi => write!(f, "OpenSBI"),
+            Self::Xvisor => write!(f, "Xvisor"),
+            Self::Kvm => write!(f, "KVM"),
+            Self::RustSbi => write!(f, "RustSBI"),
+            Self::Diosix => write!(f, "Diosix"),
+            Self::Coffer => write!(f, "Coffer"),
+            Self::Xen => write!(f, "Xen Project"),
+            Self::PolarFireHss => write!(f, "PolarFire Hart Software Services"),
+            Self::Coreboot => write!(f, "coreboot"),
+            Self::Oreboot => write!(f, "oreboot"),
+            Self::Bhyve => write!(f, "bhyve"),
+            Self::Unknown(id) => write!(f, "unknown implementation {}", id),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SbiFeatures {
+    /// `(major, minor)`, firmware without the base extension implements v0.1
+    pub spec_version: (usize, usize),
+    pub implementation: Implementation,
+    pub impl_version: usize,
+    pub mvendorid: usize,
+    pub marchid: usize,
+    pub mimpid: usize,
+    extensions: u32,
+}
+
+impl SbiFeatures {
+    const UNPROBED: Self = Self {
+        spec_version: (0, 1),
+        implementation: Implementation::Unknown(usize::MAX),
+        impl_version: 0,
+        mvendorid: 0,
+        marchid: 0,
+        mimpid: 0,
+        extensions: 0,
+    };
+
+    pub fn has(&self, extension: Extension) -> bool {
+        self.extensions & extension.bit() != 0
+    }
+
+    fn probe() -> Self {
+        let Ok(spec_version) = base::get_spec_version() else {
+            // v0.1 firmware only has the legacy extensions and no way to probe them
+            let legacy = Extension::ALL
+                .iter()
+                .filter(|e| e.id() <= legacy::SHUTDOWN)
+                .fold(0, |mask, e| mask | e.bit());
+            return Self {
+                extensions: legacy,
+                ..Self::UNPROBED
+            };
+        };
+
+        let extensions = Extension::ALL
+            .iter()
+            .filter(|e| base::probe_extension(e.id()).unwrap_or(false))
+            .fold(0, |mask, e| mask | e.bit());
+
+        Self {
+            spec_version,
+            implementation: base::get_impl_id()
+                .map(Implementation::from_id)
+                .unwrap_or(Implementation::Unknown(usize::MAX)),
+            impl_version: base::get_impl_version().unwrap_or(0),
+            mvendorid: base::get_mvendroid().unwrap_or(0),
+            marchid: base::get_marchid().unwrap_or(0),
+            mimpid: base::get_mimpid().unwrap_or(0),
+            extensions,
+        }
+    }
+}
+
+/// Written once at boot and read without a lock after that, since [`has`] is also called
+/// from the timer interrupt through `time::set_timer`
+static FEATURES: Once<SbiFeatures> = Once::new();
+
+/// Probes the firmware. Only makes SBI calls, so it can run right after BSS is cleared.
+pub fn init() {
+    FEATURES.call_once(SbiFeatures::probe);
+}
+
+/// What the firmware supports, nothing before [`init`]
+pub fn features() -> SbiFeatures {
+    FEATURES.get().copied().unwrap_or(SbiFeatures::UNPROBED)
+}
+
+pub fn has(extension: Extension) -> bool {
+    features().has(extension)
+}
+
+/// Logs the capability table.
+pub fn report() {
+    let features = features();
+    info!(
+        "SBI v{}.{}, {}",
+        features.spec_version.0, features.spec_version.1, features.implementation
+    );
+    match features.implementation {
+        // OpenSBI encodes its version as major << 16 | minor
+        Implementation::OpenSbi => info!(
+            "firmware version {}.{}",
+            features.impl_version >> 16,
+            features.impl_version & 0xffff
+        ),
+        _ => info!("firmware version 0x{:x}", features.impl_version),
+    }
+    info!(
+        "mvendorid 0x{:x}, marchid 0x{:x}, mimpid 0x{:x}",
+        features.mvendorid, features.marchid, features.mimpid
+    );
+    for extension in Extension::ALL {
+        info!(
+            "  0x{:08x} {:<34} {}",
+            extension.id(),
+            extension.name(),
+            if features.has(extension) { "yes" } else { "no" }
+        );
+    }
+}
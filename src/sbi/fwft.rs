@@ -0,0 +1,54 @@
+//! The Firmware Features extension (FWFT)
+//!
+//! Features are per hart, every hart has to set them for itself.
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x46574654;
+
+pub mod fid {
+    pub const SET: usize = 0;
+    pub const GET: usize = 1;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    /// Delegate misaligned load/store exceptions to S-mode instead of emulating them
+    MisalignedExceptionDelegation,
+    LandingPad,
+    ShadowStack,
+    DoubleTrap,
+    /// Let the hardware update the A and D bits of page table entries
+    PteAdHardwareUpdating,
+    /// The number of masked upper pointer bits (Ssnpm)
+    PointerMaskingLength,
+}
+
+impl Feature {
+    pub fn id(&self) -> usize {
+        match self {
+            Feature::MisalignedExceptionDelegation => 0,
+            Feature::LandingPad => 1,
+            Feature::ShadowStack => 2,
+            Feature::DoubleTrap => 3,
+            Feature::PteAdHardwareUpdating => 4,
+            Feature::PointerMaskingLength => 5,
+        }
+    }
+}
+
+/// Flags for [`set`]
+pub mod flags {
+    /// Prevent further changes until the next hart reset
+    pub const LOCK: usize = 1 << 0;
+}
+
+pub fn set(feature: Feature, value: usize, flags: usize) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, fid::SET, [feature.id(), value, flags]) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn get(feature: Feature) -> SbiResult<usize> {
+    unsafe { sbi_call(EXTENSION_ID, fid::GET, [feature.id()]) }.into_result()
+}
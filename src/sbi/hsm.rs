@@ -0,0 +1,102 @@
+//! The Hart State Management extension (HSM)
+
+use crate::sbi::{SbiError, SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x48534D;
+
+pub mod fid {
+    pub const HART_START: usize = 0;
+    pub const HART_STOP: usize = 1;
+    pub const HART_GET_STATUS: usize = 2;
+    pub const HART_SUSPEND: usize = 3;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+    /// A state the spec doesn't define, with the value the firmware returned
+    Unknown(usize),
+}
+
+impl HartState {
+    fn from_value(value: usize) -> Self {
+        match value {
+            0 => Self::Started,
+            1 => Self::Stopped,
+            2 => Self::StartPending,
+            3 => Self::StopPending,
+            4 => Self::Suspended,
+            5 => Self::SuspendPending,
+            6 => Self::ResumePending,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SuspendType {
+    /// Returns from `hart_suspend` like `wfi` would
+    DefaultRetentive,
+    /// Resumes at `resume_addr` with the MMU off and `a1` set to `opaque`
+    DefaultNonRetentive,
+    /// A platform specific type, retentive if bit 31 is clear
+    Platform(u32),
+}
+
+impl SuspendType {
+    fn value(&self) -> usize {
+        match self {
+            Self::DefaultRetentive => 0x0000_0000,
+            Self::DefaultNonRetentive => 0x8000_0000,
+            Self::Platform(value) => *value as usize,
+        }
+    }
+}
+
+/// Starts the stopped hart `hart_id` in S-mode at `start_addr`, with `a0` set to its
+/// hart id and `a1` to `opaque`. The MMU is off when it starts.
+///
+/// # Safety
+/// `start_addr` must be the physical address of code that can run in that environment.
+pub unsafe fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, fid::HART_START, [hart_id, start_addr, opaque]) }
+        .into_result()
+        .map(|_| ())
+}
+
+/// Stops the calling hart. Only returns if the firmware refused.
+pub fn hart_stop() -> SbiError {
+    match unsafe { sbi_call(EXTENSION_ID, fid::HART_STOP, []) }.into_result() {
+        Ok(_) => unreachable!("hart_stop returned without an error"),
+        Err(e) => e,
+    }
+}
+
+/// The state of hart `hart_id`
+pub fn hart_get_status(hart_id: usize) -> SbiResult<HartState> {
+    unsafe { sbi_call(EXTENSION_ID, fid::HART_GET_STATUS, [hart_id]) }
+        .into_result()
+        .map(HartState::from_value)
+}
+
+/// Suspends the calling hart.
+///
+/// # Safety
+/// For non-retentive suspend types, `resume_addr` must be the physical address of code
+/// that can restore the kernel's state, since the hart doesn't return from this call.
+pub unsafe fn hart_suspend(
+    suspend_type: SuspendType,
+    resume_addr: usize,
+    opaque: usize,
+) -> SbiResult<()> {
+    let args = [suspend_type.value(), resume_addr, opaque];
+    unsafe { sbi_call(EXTENSION_ID, fid::HART_SUSPEND, args) }
+        .into_result()
+        .map(|_| ())
+}
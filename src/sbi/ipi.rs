@@ -0,0 +1,17 @@
+//! The IPI extension (sPI)
+
+use crate::sbi::{HartMask, SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x735049;
+
+pub mod fid {
+    pub const SEND_IPI: usize = 0;
+}
+
+/// Sends a supervisor software interrupt to every hart in `hart_mask`.
+pub fn send_ipi(hart_mask: HartMask) -> SbiResult<()> {
+    let (mask, base) = hart_mask.as_args();
+    unsafe { sbi_call(EXTENSION_ID, fid::SEND_IPI, [mask, base]) }
+        .into_result()
+        .map(|_| ())
+}
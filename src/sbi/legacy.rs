@@ -1,79 +1,60 @@
-use core::arch::asm;
+//! The legacy extensions from SBI v0.1
+//!
+//! Each function is an extension of its own and only returns a value in `a0`, which ends
+//! up in [`SbiRet::error`](crate::sbi::SbiRet).
 
-use crate::sbi::HartMask;
+use crate::sbi::{HartMask, SbiError, SbiResult, sbi_call};
 
-pub fn set_timer(stime_value: u64) -> isize {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            inout("a0") stime_value => result,
-            in("a7") 0x00,
-        )
-    }
+pub const SET_TIMER: usize = 0x00;
+pub const CONSOLE_PUTCHAR: usize = 0x01;
+pub const CONSOLE_GETCHAR: usize = 0x02;
+pub const CLEAR_IPI: usize = 0x03;
+pub const SEND_IPI: usize = 0x04;
+pub const REMOTE_FENCE_I: usize = 0x05;
+pub const REMOTE_SFENCE_VMA: usize = 0x06;
+pub const REMOTE_SFENCE_VMA_ASID: usize = 0x07;
+pub const SHUTDOWN: usize = 0x08;
 
-    result
+fn call<const N: usize>(extension_id: usize, args: [usize; N]) -> isize {
+    unsafe { sbi_call(extension_id, 0, args) }.error
 }
 
-pub fn console_getchar() -> Option<u8> {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            out("a0") result,
-            in("a7") 0x02
-        )
-    }
-
+fn to_result(result: isize) -> SbiResult<()> {
     match result {
-        -1 => None,
-        _ => Some((result & 0xFF) as u8),
+        0 => Ok(()),
+        e => Err(SbiError::new(e)),
     }
 }
 
-pub fn send_ipi(hart_mask: *mut HartMask) -> Result<(), isize> {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            inout("a0") hart_mask => result,
-            in("a7") 0x04
-        )
-    }
+pub fn set_timer(stime_value: u64) -> SbiResult<()> {
+    to_result(call(SET_TIMER, [stime_value as usize]))
+}
 
-    match result {
-        0 => Ok(()),
-        e => Err(e),
-    }
+pub fn console_putchar(c: u8) {
+    call(CONSOLE_PUTCHAR, [c as usize]);
 }
 
-pub fn remote_fence_i(hart_mask: *mut HartMask) -> Result<(), isize> {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            inout("a0") hart_mask => result,
-            in("a7") 0x05
-        )
+pub fn console_getchar() -> Option<u8> {
+    match call(CONSOLE_GETCHAR, []) {
+        -1 => None,
+        result => Some((result & 0xFF) as u8),
     }
+}
 
-    match result {
-        0 => Ok(()),
-        e => Err(e),
-    }
+pub fn clear_ipi() -> SbiResult<()> {
+    to_result(call(CLEAR_IPI, []))
 }
 
-pub fn remote_sfence_vma(hart_mask: *mut HartMask, start: usize, size: usize) -> Result<(), isize> {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            inout("a0") hart_mask => result,
-            in("a1") start,
-            in("a2") size,
-            in("a7") 0x06
-        )
-    }
+pub fn send_ipi(hart_mask: *mut HartMask) -> SbiResult<()> {
+    to_result(call(SEND_IPI, [hart_mask as usize]))
+}
 
-    match result {
-        0 => Ok(()),
-        e => Err(e),
-    }
+pub fn remote_fence_i(hart_mask: *mut HartMask) -> SbiResult<()> {
+    to_result(call(REMOTE_FENCE_I, [hart_mask as usize]))
+}
+
+pub fn remote_sfence_vma(hart_mask: *mut HartMask, start: usize, size: usize) -> SbiResult<()> {
+    to_result(call(REMOTE_SFENCE_VMA, [hart_mask as usize, start, size]))
 }
 
 pub fn remote_sfence_vma_asid(
@@ -81,28 +62,22 @@ pub fn remote_sfence_vma_asid(
     start: usize,
     size: usize,
     asid: usize,
-) -> Result<(), isize> {
-    let result: isize;
-    unsafe {
-        asm!("ecall",
-            inout("a0") hart_mask => result,
-            in("a1") start,
-            in("a2") size,
-            in("a3") asid,
-            in("a7") 0x07
-        )
-    }
-
-    match result {
-        0 => Ok(()),
-        e => Err(e),
-    }
+) -> SbiResult<()> {
+    to_result(call(
+        REMOTE_SFENCE_VMA_ASID,
+        [hart_mask as usize, start, size, asid],
+    ))
 }
 
 pub fn shutdown() -> ! {
     // this already doesn't return
-    unsafe {
-        asm!("ecall", in("a7") 0x08);
+    call(SHUTDOWN, []);
+    loop {
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!("wfi");
+        }
+        #[cfg(not(target_arch = "riscv64"))]
+        core::hint::spin_loop();
     }
-    loop {}
 }
@@ -0,0 +1,26 @@
+//! A stand-in for the firmware when building for the host, so code making SBI calls can
+//! be unit tested without qemu.
+//!
+//! Calls go to the handler installed with [`set_handler`]. Without one, every call fails
+//! with `NotSupported`, like firmware that implements nothing.
+
+use crate::{
+    sbi::{SbiErrorType, SbiRet},
+    sync::SpinLock,
+};
+
+pub type Handler = fn(extension_id: usize, function_id: usize, args: [usize; 6]) -> SbiRet;
+
+static HANDLER: SpinLock<Option<Handler>> = SpinLock::new(None);
+
+pub fn set_handler(handler: Option<Handler>) {
+    *HANDLER.lock() = handler;
+}
+
+pub(super) unsafe fn ecall(extension_id: usize, function_id: usize, args: [usize; 6]) -> SbiRet {
+    let handler = *HANDLER.lock();
+    match handler {
+        Some(handler) => handler(extension_id, function_id, args),
+        None => SbiRet::failure(SbiErrorType::NotSupported),
+    }
+}
@@ -1,211 +1,296 @@
-use core::{arch::asm, num::NonZeroIsize};
+use core::fmt;
 
 pub mod base;
+pub mod cppc;
+pub mod dbcn;
+// These two need the rest of the kernel, the others build for the host as well to be tested
+// against the mock.
+#[cfg(target_arch = "riscv64")]
+pub mod features;
+pub mod fwft;
+pub mod hsm;
+pub mod ipi;
 pub mod legacy;
+pub mod pmu;
+pub mod rfence;
+pub mod srst;
+#[cfg(target_arch = "riscv64")]
+pub mod sse;
+pub mod sta;
+pub mod susp;
+pub mod time;
 
-#[repr(isize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SbiErrorType {
-    Failed = -1,
-    NotSupported = -2,
-    InvalidParam = -3,
-    Denied = -4,
-    InvalidAddress = -5,
-    AlreadyAvailable = -6,
-    AlreadyStarted = -7,
-    AlreadyStopped = -8,
-    NoSHMEM = -9,
-    InvalidState = -10,
-    BadRange = -11,
-    Timeout = -12,
-    IO = -13,
-    DeniedLocked = -14,
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    NoSHMEM,
+    InvalidState,
+    BadRange,
+    Timeout,
+    IO,
+    DeniedLocked,
+    /// An error code not defined by the spec
+    Unknown(isize),
 }
 
-#[derive(Debug)]
-pub struct SbiError(Option<NonZeroIsize>);
+impl SbiErrorType {
+    pub fn from_code(code: isize) -> Self {
+        match code {
+            -1 => Self::Failed,
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            -7 => Self::AlreadyStarted,
+            -8 => Self::AlreadyStopped,
+            -9 => Self::NoSHMEM,
+            -10 => Self::InvalidState,
+            -11 => Self::BadRange,
+            -12 => Self::Timeout,
+            -13 => Self::IO,
+            -14 => Self::DeniedLocked,
+            code => Self::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> isize {
+        match self {
+            Self::Failed => -1,
+            Self::NotSupported => -2,
+            Self::InvalidParam => -3,
+            Self::Denied => -4,
+            Self::InvalidAddress => -5,
+            Self::AlreadyAvailable => -6,
+            Self::AlreadyStarted => -7,
+            Self::AlreadyStopped => -8,
+            Self::NoSHMEM => -9,
+            Self::InvalidState => -10,
+            Self::BadRange => -11,
+            Self::Timeout => -12,
+            Self::IO => -13,
+            Self::DeniedLocked => -14,
+            Self::Unknown(code) => *code,
+        }
+    }
+
+    /// The name the spec gives this error
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Failed => "SBI_ERR_FAILED",
+            Self::NotSupported => "SBI_ERR_NOT_SUPPORTED",
+            Self::InvalidParam => "SBI_ERR_INVALID_PARAM",
+            Self::Denied => "SBI_ERR_DENIED",
+            Self::InvalidAddress => "SBI_ERR_INVALID_ADDRESS",
+            Self::AlreadyAvailable => "SBI_ERR_ALREADY_AVAILABLE",
+            Self::AlreadyStarted => "SBI_ERR_ALREADY_STARTED",
+            Self::AlreadyStopped => "SBI_ERR_ALREADY_STOPPED",
+            Self::NoSHMEM => "SBI_ERR_NO_SHMEM",
+            Self::InvalidState => "SBI_ERR_INVALID_STATE",
+            Self::BadRange => "SBI_ERR_BAD_RANGE",
+            Self::Timeout => "SBI_ERR_TIMEOUT",
+            Self::IO => "SBI_ERR_IO",
+            Self::DeniedLocked => "SBI_ERR_DENIED_LOCKED",
+            Self::Unknown(_) => "unknown SBI error",
+        }
+    }
+}
+
+impl fmt::Display for SbiErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.code())
+    }
+}
+
+/// An error returned by the firmware, decoded from the raw code in `a0`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SbiError(SbiErrorType);
 
 impl SbiError {
     pub fn new(error: isize) -> Self {
-        Self(NonZeroIsize::new(error))
+        Self(SbiErrorType::from_code(error))
+    }
+
+    /// The raw error code returned in `a0`
+    pub fn code(&self) -> isize {
+        self.0.code()
+    }
+
+    pub fn kind(&self) -> SbiErrorType {
+        self.0
+    }
+}
+
+impl From<SbiErrorType> for SbiError {
+    fn from(kind: SbiErrorType) -> Self {
+        Self(kind)
+    }
+}
+
+impl fmt::Display for SbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for SbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SbiError({})", self.0)
     }
 }
 
 pub type SbiResult<T> = Result<T, SbiError>;
 
+/// A set of harts, given as a bitmask of up to `usize::BITS` harts starting at `base`.
 #[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct HartMask {
     base: usize,
     mask: usize,
 }
 
-/// Zero argument call to sbi
-pub unsafe fn call_sbi0(extension_id: usize, function_id: usize) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            lateout("a0") error,
-            lateout("a1") value);
-    }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+impl HartMask {
+    /// `hart_mask_base` of -1 tells the firmware to ignore the mask and target all harts.
+    const ALL_BASE: usize = usize::MAX;
+
+    /// An empty set starting at hart `base`, add harts with [`HartMask::with`].
+    pub const fn new(base: usize) -> Self {
+        Self { base, mask: 0 }
     }
-}
 
-/// One argument call to sbi
-pub unsafe fn call_sbi1(
-    extension_id: usize,
-    function_id: usize,
-    arg0: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            lateout("a1") value);
-    }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+    pub const fn from_mask(base: usize, mask: usize) -> Self {
+        Self { base, mask }
     }
-}
 
-/// Two argument call to sbi
-pub unsafe fn call_sbi2(
-    extension_id: usize,
-    function_id: usize,
-    arg0: usize,
-    arg1: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            inlateout("a1") arg1 => value);
-    }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+    pub const fn single(hart_id: usize) -> Self {
+        Self {
+            base: hart_id,
+            mask: 1,
+        }
     }
-}
 
-/// Three argument call to sbi
-pub unsafe fn call_sbi3(
-    extension_id: usize,
-    function_id: usize,
-    arg0: usize,
-    arg1: usize,
-    arg2: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            inlateout("a1") arg1 => value,
-            in("a2") arg2,
-        );
+    pub const fn all() -> Self {
+        Self {
+            base: Self::ALL_BASE,
+            mask: 0,
+        }
     }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+
+    pub const fn is_all(&self) -> bool {
+        self.base == Self::ALL_BASE
     }
-}
 
-/// Four argument call to sbi
-pub unsafe fn call_sbi4(
-    extension_id: usize,
-    function_id: usize,
-    arg0: usize,
-    arg1: usize,
-    arg2: usize,
-    arg3: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            inlateout("a1") arg1 => value,
-            in("a2") arg2,
-            in("a3") arg3
+    /// Adds a hart to the set. Panics if it doesn't fit into the mask.
+    pub fn with(mut self, hart_id: usize) -> Self {
+        self.insert(hart_id);
+        self
+    }
+
+    pub fn insert(&mut self, hart_id: usize) {
+        if self.is_all() {
+            return;
+        }
+        assert!(
+            hart_id >= self.base && hart_id - self.base < usize::BITS as usize,
+            "hart {} can't be represented in a mask based at {}",
+            hart_id,
+            self.base
         );
+        self.mask |= 1 << (hart_id - self.base);
     }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+
+    pub fn contains(&self, hart_id: usize) -> bool {
+        self.is_all()
+            || (hart_id >= self.base
+                && hart_id - self.base < usize::BITS as usize
+                && self.mask & (1 << (hart_id - self.base)) != 0)
+    }
+
+    /// The `(hart_mask, hart_mask_base)` argument pair as passed to the firmware
+    pub const fn as_args(&self) -> (usize, usize) {
+        (self.mask, self.base)
     }
 }
 
-/// Five argument call to sbi
-pub unsafe fn call_sbi5(
-    extension_id: usize,
-    function_id: usize,
-    arg0: usize,
-    arg1: usize,
-    arg2: usize,
-    arg3: usize,
-    arg4: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            inlateout("a1") arg1 => value,
-            in("a2") arg2,
-            in("a3") arg3,
-            in("a4") arg4,
-        );
+/// What every call returns in `a0` and `a1`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SbiRet {
+    pub error: isize,
+    pub value: usize,
+}
+
+impl SbiRet {
+    pub const fn success(value: usize) -> Self {
+        Self { error: 0, value }
+    }
+
+    pub fn failure(kind: SbiErrorType) -> Self {
+        Self {
+            error: kind.code(),
+            value: 0,
+        }
     }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+
+    pub fn into_result(self) -> SbiResult<usize> {
+        match self.error {
+            0 => Ok(self.value),
+            e => Err(SbiError::new(e)),
+        }
     }
 }
 
-/// Six argument call to sbi
-pub unsafe fn call_sbi6(
+/// Calls function `function_id` of extension `extension_id`, passing `args` in `a0` to
+/// `a5`. Unused argument registers are zeroed.
+///
+/// # Safety
+/// The arguments must be valid for the called extension and function,
+/// pointers in particular are passed on to the firmware as is.
+pub unsafe fn sbi_call<const N: usize>(
     extension_id: usize,
     function_id: usize,
-    arg0: usize,
-    arg1: usize,
-    arg2: usize,
-    arg3: usize,
-    arg4: usize,
-    arg5: usize,
-) -> Result<usize, SbiError> {
-    let error: isize;
-    let value: usize;
-    unsafe {
-        asm!("ecall",
-            in("a7") extension_id,
-            in("a6") function_id,
-            inlateout("a0") arg0 => error,
-            inlateout("a1") arg1 => value,
-            in("a2") arg2,
-            in("a3") arg3,
-            in("a4") arg4,
-            in("a5") arg5
-        );
-    }
-    match error {
-        0 => Ok(value),
-        _ => Err(SbiError::new(error)),
+    args: [usize; N],
+) -> SbiRet {
+    const { assert!(N <= 6, "SBI calls take at most six arguments") };
+
+    let mut regs = [0; 6];
+    regs[..N].copy_from_slice(&args);
+    unsafe { backend::ecall(extension_id, function_id, regs) }
+}
+
+#[cfg(target_arch = "riscv64")]
+mod backend {
+    use core::arch::asm;
+
+    use super::SbiRet;
+
+    pub unsafe fn ecall(extension_id: usize, function_id: usize, args: [usize; 6]) -> SbiRet {
+        let error: isize;
+        let value: usize;
+        unsafe {
+            asm!("ecall",
+                in("a7") extension_id,
+                in("a6") function_id,
+                inlateout("a0") args[0] => error,
+                inlateout("a1") args[1] => value,
+                in("a2") args[2],
+                in("a3") args[3],
+                in("a4") args[4],
+                in("a5") args[5],
+            );
+        }
+        SbiRet { error, value }
     }
 }
+
+#[cfg(not(target_arch = "riscv64"))]
+pub mod mock;
+
+#[cfg(not(target_arch = "riscv64"))]
+use mock as backend;
@@ -0,0 +1,237 @@
+//! The Performance Monitoring Unit extension (PMU)
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x504D55;
+
+pub mod fid {
+    pub const NUM_COUNTERS: usize = 0;
+    pub const COUNTER_GET_INFO: usize = 1;
+    pub const COUNTER_CONFIG_MATCHING: usize = 2;
+    pub const COUNTER_START: usize = 3;
+    pub const COUNTER_STOP: usize = 4;
+    pub const COUNTER_FW_READ: usize = 5;
+    pub const COUNTER_FW_READ_HI: usize = 6;
+    pub const SNAPSHOT_SET_SHMEM: usize = 7;
+}
+
+/// Flags for [`counter_config_matching`]
+pub mod config_flags {
+    pub const SKIP_MATCH: usize = 1 << 0;
+    pub const CLEAR_VALUE: usize = 1 << 1;
+    pub const AUTO_START: usize = 1 << 2;
+    pub const SET_VUINH: usize = 1 << 3;
+    pub const SET_VSINH: usize = 1 << 4;
+    pub const SET_UINH: usize = 1 << 5;
+    pub const SET_SINH: usize = 1 << 6;
+    pub const SET_MINH: usize = 1 << 7;
+}
+
+/// Flags for [`counter_start`]
+pub mod start_flags {
+    pub const SET_INIT_VALUE: usize = 1 << 0;
+    pub const INIT_SNAPSHOT: usize = 1 << 1;
+}
+
+/// Flags for [`counter_stop`]
+pub mod stop_flags {
+    pub const RESET: usize = 1 << 0;
+    pub const TAKE_SNAPSHOT: usize = 1 << 1;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HardwareEvent {
+    CpuCycles = 1,
+    Instructions = 2,
+    CacheReferences = 3,
+    CacheMisses = 4,
+    BranchInstructions = 5,
+    BranchMisses = 6,
+    BusCycles = 7,
+    StalledCyclesFrontend = 8,
+    StalledCyclesBackend = 9,
+    RefCpuCycles = 10,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FirmwareEvent {
+    MisalignedLoad = 0,
+    MisalignedStore = 1,
+    AccessLoad = 2,
+    AccessStore = 3,
+    IllegalInstruction = 4,
+    SetTimer = 5,
+    IpiSent = 6,
+    IpiReceived = 7,
+    FenceISent = 8,
+    FenceIReceived = 9,
+    SfenceVmaSent = 10,
+    SfenceVmaReceived = 11,
+    SfenceVmaAsidSent = 12,
+    SfenceVmaAsidReceived = 13,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheId {
+    L1Data = 0,
+    L1Instruction = 1,
+    LastLevel = 2,
+    DataTlb = 3,
+    InstructionTlb = 4,
+    BranchPredictor = 5,
+    Node = 6,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheOp {
+    Read = 0,
+    Write = 1,
+    Prefetch = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheResult {
+    Access = 0,
+    Miss = 1,
+}
+
+/// An event as encoded in the `event_idx` and `event_data` arguments
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Hardware(HardwareEvent),
+    Cache(CacheId, CacheOp, CacheResult),
+    /// A platform specific event, passed in `event_data`
+    Raw(u64),
+    Firmware(FirmwareEvent),
+}
+
+impl Event {
+    pub fn index(&self) -> usize {
+        let (event_type, code) = match *self {
+            Event::Hardware(e) => (0, e as usize),
+            Event::Cache(id, op, result) => {
+                (1, (id as usize) << 3 | (op as usize) << 1 | result as usize)
+            }
+            Event::Raw(_) => (2, 0),
+            Event::Firmware(e) => (15, e as usize),
+        };
+        event_type << 16 | code
+    }
+
+    pub fn data(&self) -> usize {
+        match *self {
+            Event::Raw(data) => data as usize,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CounterInfo {
+    Hardware {
+        /// The CSR number of the counter, e.g. `0xC00` for `cycle`
+        csr: u16,
+        width: u8,
+    },
+    Firmware,
+}
+
+impl CounterInfo {
+    fn from_value(value: usize) -> Self {
+        if value >> (usize::BITS - 1) != 0 {
+            CounterInfo::Firmware
+        } else {
+            CounterInfo::Hardware {
+                csr: (value & 0xfff) as u16,
+                width: ((value >> 12) & 0x3f) as u8 + 1,
+            }
+        }
+    }
+}
+
+/// Layout of the snapshot shared memory
+#[repr(C)]
+pub struct Snapshot {
+    pub counter_overflow_bitmap: u64,
+    pub counter_values: [u64; 64],
+    _reserved: [u64; 447],
+}
+
+const _: () = assert!(size_of::<Snapshot>() == 4096);
+
+pub fn num_counters() -> SbiResult<usize> {
+    unsafe { sbi_call(EXTENSION_ID, fid::NUM_COUNTERS, []) }.into_result()
+}
+
+pub fn counter_get_info(counter_idx: usize) -> SbiResult<CounterInfo> {
+    unsafe { sbi_call(EXTENSION_ID, fid::COUNTER_GET_INFO, [counter_idx]) }
+        .into_result()
+        .map(CounterInfo::from_value)
+}
+
+/// Finds and configures a counter out of the ones selected by `counter_idx_base` and
+/// `counter_idx_mask` that can count `event`. Returns the chosen counter's index.
+pub fn counter_config_matching(
+    counter_idx_base: usize,
+    counter_idx_mask: usize,
+    config_flags: usize,
+    event: Event,
+) -> SbiResult<usize> {
+    let args = [
+        counter_idx_base,
+        counter_idx_mask,
+        config_flags,
+        event.index(),
+        event.data(),
+    ];
+    unsafe { sbi_call(EXTENSION_ID, fid::COUNTER_CONFIG_MATCHING, args) }.into_result()
+}
+
+pub fn counter_start(
+    counter_idx_base: usize,
+    counter_idx_mask: usize,
+    start_flags: usize,
+    initial_value: u64,
+) -> SbiResult<()> {
+    let args = [
+        counter_idx_base,
+        counter_idx_mask,
+        start_flags,
+        initial_value as usize,
+    ];
+    unsafe { sbi_call(EXTENSION_ID, fid::COUNTER_START, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn counter_stop(
+    counter_idx_base: usize,
+    counter_idx_mask: usize,
+    stop_flags: usize,
+) -> SbiResult<()> {
+    let args = [counter_idx_base, counter_idx_mask, stop_flags];
+    unsafe { sbi_call(EXTENSION_ID, fid::COUNTER_STOP, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+/// Reads a firmware counter
+pub fn counter_fw_read(counter_idx: usize) -> SbiResult<u64> {
+    unsafe { sbi_call(EXTENSION_ID, fid::COUNTER_FW_READ, [counter_idx]) }
+        .into_result()
+        .map(|v| v as u64)
+}
+
+/// Sets the calling hart's snapshot shared memory, passing `None` disables it.
+///
+/// # Safety
+/// The snapshot must stay alive and at the same physical address until it is disabled.
+pub unsafe fn snapshot_set_shmem(snapshot: Option<*mut Snapshot>) -> SbiResult<()> {
+    let (lo, hi) = match snapshot {
+        Some(ptr) => (ptr as usize, 0),
+        None => (usize::MAX, usize::MAX),
+    };
+    unsafe { sbi_call(EXTENSION_ID, fid::SNAPSHOT_SET_SHMEM, [lo, hi, 0]) }
+        .into_result()
+        .map(|_| ())
+}
@@ -0,0 +1,76 @@
+//! The RFENCE extension (RFNC), executing fences on remote harts
+//!
+//! A `start` and `size` of 0 or a `size` of `usize::MAX` flush the whole address space.
+
+use crate::sbi::{HartMask, SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x52464E43;
+
+pub mod fid {
+    pub const REMOTE_FENCE_I: usize = 0;
+    pub const REMOTE_SFENCE_VMA: usize = 1;
+    pub const REMOTE_SFENCE_VMA_ASID: usize = 2;
+    pub const REMOTE_HFENCE_GVMA_VMID: usize = 3;
+    pub const REMOTE_HFENCE_GVMA: usize = 4;
+    pub const REMOTE_HFENCE_VVMA_ASID: usize = 5;
+    pub const REMOTE_HFENCE_VVMA: usize = 6;
+}
+
+/// All functions take the hart mask first, followed by the function's own arguments
+fn call<const N: usize>(
+    function_id: usize,
+    hart_mask: HartMask,
+    args: [usize; N],
+) -> SbiResult<()> {
+    let (mask, base) = hart_mask.as_args();
+    let mut regs = [0; 6];
+    regs[0] = mask;
+    regs[1] = base;
+    regs[2..2 + N].copy_from_slice(&args);
+    unsafe { sbi_call(EXTENSION_ID, function_id, regs) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn remote_fence_i(hart_mask: HartMask) -> SbiResult<()> {
+    call(fid::REMOTE_FENCE_I, hart_mask, [])
+}
+
+pub fn remote_sfence_vma(hart_mask: HartMask, start: usize, size: usize) -> SbiResult<()> {
+    call(fid::REMOTE_SFENCE_VMA, hart_mask, [start, size])
+}
+
+pub fn remote_sfence_vma_asid(
+    hart_mask: HartMask,
+    start: usize,
+    size: usize,
+    asid: usize,
+) -> SbiResult<()> {
+    call(fid::REMOTE_SFENCE_VMA_ASID, hart_mask, [start, size, asid])
+}
+
+pub fn remote_hfence_gvma_vmid(
+    hart_mask: HartMask,
+    start: usize,
+    size: usize,
+    vmid: usize,
+) -> SbiResult<()> {
+    call(fid::REMOTE_HFENCE_GVMA_VMID, hart_mask, [start, size, vmid])
+}
+
+pub fn remote_hfence_gvma(hart_mask: HartMask, start: usize, size: usize) -> SbiResult<()> {
+    call(fid::REMOTE_HFENCE_GVMA, hart_mask, [start, size])
+}
+
+pub fn remote_hfence_vvma_asid(
+    hart_mask: HartMask,
+    start: usize,
+    size: usize,
+    asid: usize,
+) -> SbiResult<()> {
+    call(fid::REMOTE_HFENCE_VVMA_ASID, hart_mask, [start, size, asid])
+}
+
+pub fn remote_hfence_vvma(hart_mask: HartMask, start: usize, size: usize) -> SbiResult<()> {
+    call(fid::REMOTE_HFENCE_VVMA, hart_mask, [start, size])
+}
@@ -0,0 +1,40 @@
+//! The System Reset extension (SRST)
+
+use crate::sbi::{SbiError, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x53525354;
+
+pub mod fid {
+    pub const SYSTEM_RESET: usize = 0;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetType {
+    Shutdown,
+    ColdReboot,
+    WarmReboot,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetReason {
+    NoReason,
+    SystemFailure,
+}
+
+/// Resets the whole system. Only returns if the firmware refused.
+pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> SbiError {
+    let reset_type = match reset_type {
+        ResetType::Shutdown => 0,
+        ResetType::ColdReboot => 1,
+        ResetType::WarmReboot => 2,
+    };
+    let reason = match reason {
+        ResetReason::NoReason => 0,
+        ResetReason::SystemFailure => 1,
+    };
+
+    match unsafe { sbi_call(EXTENSION_ID, fid::SYSTEM_RESET, [reset_type, reason]) }.into_result() {
+        Ok(_) => unreachable!("system_reset returned without an error"),
+        Err(e) => e,
+    }
+}
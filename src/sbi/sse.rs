@@ -0,0 +1,286 @@
+//! The Supervisor Software Events extension (SSE)
+//!
+//! Events are delivered like NMIs: the firmware jumps to `sse_entry` whatever the hart
+//! was doing, even with interrupts disabled. The entry stub switches to a stack of the
+//! event's own, saves all registers, loads the kernel's `gp` and `tp` in case the event
+//! interrupted U-mode and calls the registered handler. Handlers must not take locks
+//! that the interrupted code may hold.
+
+use core::{arch::global_asm, mem::offset_of};
+
+use crate::{
+    alloc::{PAGE_SIZE, dealloc, zalloc},
+    percpu::{self, PerCpu},
+    sbi::{SbiResult, sbi_call},
+    trap::TrapFrame,
+};
+
+pub const EXTENSION_ID: usize = 0x535345;
+
+pub mod fid {
+    pub const READ_ATTRS: usize = 0;
+    pub const WRITE_ATTRS: usize = 1;
+    pub const REGISTER: usize = 2;
+    pub const UNREGISTER: usize = 3;
+    pub const ENABLE: usize = 4;
+    pub const DISABLE: usize = 5;
+    pub const COMPLETE: usize = 6;
+    pub const INJECT: usize = 7;
+    pub const HART_UNMASK: usize = 8;
+    pub const HART_MASK: usize = 9;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    LocalHighPriorityRas,
+    LocalDoubleTrap,
+    GlobalHighPriorityRas,
+    LocalPmuOverflow,
+    LocalLowPriorityRas,
+    GlobalLowPriorityRas,
+    LocalSoftware,
+    GlobalSoftware,
+    Platform(u32),
+}
+
+impl Event {
+    pub fn id(&self) -> usize {
+        match self {
+            Event::LocalHighPriorityRas => 0x0000_0000,
+            Event::LocalDoubleTrap => 0x0000_0001,
+            Event::GlobalHighPriorityRas => 0x0000_8000,
+            Event::LocalPmuOverflow => 0x0001_0000,
+            Event::LocalLowPriorityRas => 0x0010_0000,
+            Event::GlobalLowPriorityRas => 0x0010_8000,
+            Event::LocalSoftware => 0xffff_0000,
+            Event::GlobalSoftware => 0xffff_8000,
+            Event::Platform(id) => *id as usize,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Attribute {
+    Status,
+    Priority,
+    Config,
+    PreferredHart,
+    EntryPc,
+    EntryArg,
+    InterruptedSepc,
+    InterruptedFlags,
+    InterruptedA6,
+    InterruptedA7,
+}
+
+/// For the functions that only take plain values
+fn call<const N: usize>(function_id: usize, args: [usize; N]) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, function_id, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn read_attrs(event: Event, base: Attribute, values: &mut [usize]) -> SbiResult<()> {
+    let addr = values.as_mut_ptr() as usize;
+    let args = [event.id(), base as usize, values.len(), addr, 0];
+    unsafe { sbi_call(EXTENSION_ID, fid::READ_ATTRS, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn write_attrs(event: Event, base: Attribute, values: &[usize]) -> SbiResult<()> {
+    let addr = values.as_ptr() as usize;
+    let args = [event.id(), base as usize, values.len(), addr, 0];
+    unsafe { sbi_call(EXTENSION_ID, fid::WRITE_ATTRS, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+/// # Safety
+/// `entry_pc` must be able to handle the event at any time, see [`register_handler`].
+pub unsafe fn register(event: Event, entry_pc: usize, entry_arg: usize) -> SbiResult<()> {
+    let args = [event.id(), entry_pc, entry_arg];
+    unsafe { sbi_call(EXTENSION_ID, fid::REGISTER, args) }
+        .into_result()
+        .map(|_| ())
+}
+
+pub fn unregister(event: Event) -> SbiResult<()> {
+    call(fid::UNREGISTER, [event.id()])
+}
+
+pub fn enable(event: Event) -> SbiResult<()> {
+    call(fid::ENABLE, [event.id()])
+}
+
+pub fn disable(event: Event) -> SbiResult<()> {
+    call(fid::DISABLE, [event.id()])
+}
+
+/// Tells the firmware the running handler is done. Only `sse_entry` should call this.
+pub fn complete() -> SbiResult<()> {
+    call(fid::COMPLETE, [])
+}
+
+pub fn inject(event: Event, hart_id: usize) -> SbiResult<()> {
+    call(fid::INJECT, [event.id(), hart_id])
+}
+
+pub fn hart_unmask() -> SbiResult<()> {
+    call(fid::HART_UNMASK, [])
+}
+
+pub fn hart_mask() -> SbiResult<()> {
+    call(fid::HART_MASK, [])
+}
+
+pub type Handler = fn(event: Event, frame: &mut TrapFrame);
+
+/// Passed to `sse_entry` in `a7`, lives at the bottom of the event's stack pages.
+#[repr(C)]
+struct EventContext {
+    stack_top: usize,
+    interrupted_sp: usize,
+    /// The area of the hart the event was registered on, for `tp`. Around the `tp` and
+    /// `sscratch` swaps on the way into and out of U-mode, either of them can hold the
+    /// program's `tp`, so neither can be trusted.
+    cpu: *const PerCpu,
+    event: Event,
+    handler: Handler,
+}
+
+const STACK_PAGES: usize = 4;
+
+/// Registers and enables `handler` for `event` on the calling hart. Only local events
+/// can be handled, since the entry stub expects to run on the hart that registered it.
+pub fn register_handler(event: Event, handler: Handler) -> SbiResult<()> {
+    let pages = zalloc(STACK_PAGES);
+    assert!(!pages.is_null(), "out of memory for an SSE stack");
+
+    let ctx = pages as *mut EventContext;
+    unsafe {
+        ctx.write(EventContext {
+            stack_top: pages as usize + STACK_PAGES * PAGE_SIZE,
+            interrupted_sp: 0,
+            cpu: percpu::this_cpu_raw(),
+            event,
+            handler,
+        });
+    }
+
+    let result = unsafe { register(event, sse_entry as *const () as usize, ctx as usize) }
+        .and_then(|_| enable(event));
+    if result.is_err() {
+        let _ = unregister(event);
+        dealloc(pages);
+    }
+    result
+}
+
+unsafe extern "C" {
+    fn sse_entry();
+}
+
+extern "C" fn sse_handler(frame: &mut TrapFrame, ctx: &EventContext) {
+    let mut sepc = [0];
+    if read_attrs(ctx.event, Attribute::InterruptedSepc, &mut sepc).is_ok() {
+        frame.sepc = sepc[0];
+    }
+    (ctx.handler)(ctx.event, frame);
+}
+
+// Entered with a7 pointing to the EventContext. The firmware saved the interrupted a6,
+// a7 and sepc and restores them on `complete`, everything else is up to us. The
+// interrupted gp and tp are only restored on the way out, like in `trap_vector`.
+global_asm!(
+    ".section .text
+.balign 4
+.global sse_entry
+sse_entry:
+        sd sp, 8(a7)
+        ld sp, 0(a7)
+        addi sp, sp, -{size}
+        sd x1, 8(sp)
+        sd x3, 24(sp)
+        sd x4, 32(sp)
+        sd x5, 40(sp)
+        sd x6, 48(sp)
+        sd x7, 56(sp)
+        sd x8, 64(sp)
+        sd x9, 72(sp)
+        sd x10, 80(sp)
+        sd x11, 88(sp)
+        sd x12, 96(sp)
+        sd x13, 104(sp)
+        sd x14, 112(sp)
+        sd x15, 120(sp)
+        sd x16, 128(sp)
+        sd x17, 136(sp)
+        sd x18, 144(sp)
+        sd x19, 152(sp)
+        sd x20, 160(sp)
+        sd x21, 168(sp)
+        sd x22, 176(sp)
+        sd x23, 184(sp)
+        sd x24, 192(sp)
+        sd x25, 200(sp)
+        sd x26, 208(sp)
+        sd x27, 216(sp)
+        sd x28, 224(sp)
+        sd x29, 232(sp)
+        sd x30, 240(sp)
+        sd x31, 248(sp)
+        ld t0, 8(a7)
+        sd t0, 16(sp)
+
+        ld tp, {cpu}(a7)
+        .option push
+        .option norelax
+        la gp, __global_pointer
+        .option pop
+
+        mv a0, sp
+        mv a1, a7
+        call {handler}
+
+        ld x1, 8(sp)
+        ld x3, 24(sp)
+        ld x4, 32(sp)
+        ld x5, 40(sp)
+        ld x6, 48(sp)
+        ld x7, 56(sp)
+        ld x8, 64(sp)
+        ld x9, 72(sp)
+        ld x10, 80(sp)
+        ld x11, 88(sp)
+        ld x12, 96(sp)
+        ld x13, 104(sp)
+        ld x14, 112(sp)
+        ld x15, 120(sp)
+        ld x18, 144(sp)
+        ld x19, 152(sp)
+        ld x20, 160(sp)
+        ld x21, 168(sp)
+        ld x22, 176(sp)
+        ld x23, 184(sp)
+        ld x24, 192(sp)
+        ld x25, 200(sp)
+        ld x26, 208(sp)
+        ld x27, 216(sp)
+        ld x28, 224(sp)
+        ld x29, 232(sp)
+        ld x30, 240(sp)
+        ld x31, 248(sp)
+        ld sp, 16(sp)
+        li a7, {eid}
+        li a6, {complete}
+        ecall
+1:
+        wfi
+        j 1b",
+    size = const size_of::<TrapFrame>(),
+    cpu = const offset_of!(EventContext, cpu),
+    handler = sym sse_handler,
+    eid = const EXTENSION_ID,
+    complete = const fid::COMPLETE,
+);
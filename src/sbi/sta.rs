@@ -0,0 +1,78 @@
+//! The Steal-time Accounting extension (STA)
+
+use core::{
+    ptr::{addr_of, read_volatile},
+    sync::atomic::{Ordering, fence},
+};
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x535441;
+
+pub mod fid {
+    pub const STEAL_TIME_SET_SHMEM: usize = 0;
+}
+
+/// The shared memory the hypervisor keeps updated, one per hart
+#[repr(C, align(64))]
+pub struct StealTime {
+    sequence: u32,
+    flags: u32,
+    /// Stolen time in nanoseconds
+    steal: u64,
+    preempted: u8,
+    _pad: [u8; 47],
+}
+
+const _: () = assert!(size_of::<StealTime>() == 64);
+
+impl StealTime {
+    pub const fn new() -> Self {
+        Self {
+            sequence: 0,
+            flags: 0,
+            steal: 0,
+            preempted: 0,
+            _pad: [0; 47],
+        }
+    }
+
+    /// Reads the stolen time in nanoseconds. The hypervisor bumps `sequence` to an odd
+    /// value while updating, so retry until we get a consistent read.
+    pub fn steal(&self) -> u64 {
+        loop {
+            let seq = unsafe { read_volatile(addr_of!(self.sequence)) };
+            fence(Ordering::Acquire);
+            let steal = unsafe { read_volatile(addr_of!(self.steal)) };
+            fence(Ordering::Acquire);
+            if seq & 1 == 0 && seq == unsafe { read_volatile(addr_of!(self.sequence)) } {
+                return steal;
+            }
+        }
+    }
+
+    /// Whether the hart was preempted since the hypervisor last cleared the flag
+    pub fn preempted(&self) -> bool {
+        unsafe { read_volatile(addr_of!(self.preempted)) != 0 }
+    }
+}
+
+impl Default for StealTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Registers the calling hart's steal-time shared memory, `None` disables it.
+///
+/// # Safety
+/// The memory must stay alive and identity mapped until it is disabled again.
+pub unsafe fn set_shmem(shmem: Option<*mut StealTime>) -> SbiResult<()> {
+    let (lo, hi) = match shmem {
+        Some(ptr) => (ptr as usize, 0),
+        None => (usize::MAX, usize::MAX),
+    };
+    unsafe { sbi_call(EXTENSION_ID, fid::STEAL_TIME_SET_SHMEM, [lo, hi, 0]) }
+        .into_result()
+        .map(|_| ())
+}
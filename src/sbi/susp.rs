@@ -0,0 +1,42 @@
+//! The System Suspend extension (SUSP)
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x53555350;
+
+pub mod fid {
+    pub const SYSTEM_SUSPEND: usize = 0;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SleepType {
+    SuspendToRam,
+    Platform(u32),
+}
+
+impl SleepType {
+    pub fn value(&self) -> usize {
+        match self {
+            Self::SuspendToRam => 0,
+            Self::Platform(value) => *value as usize,
+        }
+    }
+}
+
+/// Suspends the system. All other harts have to be stopped first.
+///
+/// On success the call doesn't return; instead the calling hart resumes at `resume_addr`
+/// in S-mode with the MMU off, `a0` set to its hart id and `a1` to `opaque`.
+///
+/// # Safety
+/// `resume_addr` must be the physical address of code able to restore the kernel's state.
+pub unsafe fn system_suspend(
+    sleep_type: SleepType,
+    resume_addr: usize,
+    opaque: usize,
+) -> SbiResult<()> {
+    let args = [sleep_type.value(), resume_addr, opaque];
+    unsafe { sbi_call(EXTENSION_ID, fid::SYSTEM_SUSPEND, args) }
+        .into_result()
+        .map(|_| ())
+}
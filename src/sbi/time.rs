@@ -0,0 +1,17 @@
+//! The Timer extension (TIME)
+
+use crate::sbi::{SbiResult, sbi_call};
+
+pub const EXTENSION_ID: usize = 0x54494D45;
+
+pub mod fid {
+    pub const SET_TIMER: usize = 0;
+}
+
+/// Programs the calling hart's timer to fire once `time` reaches `stime_value`, which
+/// also clears a pending timer interrupt.
+pub fn set_timer(stime_value: u64) -> SbiResult<()> {
+    unsafe { sbi_call(EXTENSION_ID, fid::SET_TIMER, [stime_value as usize]) }
+        .into_result()
+        .map(|_| ())
+}
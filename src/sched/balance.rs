@@ -0,0 +1,43 @@
+//! Moving threads between harts.
+//!
+//! A hart running out of work steals from the busiest other hart right away, and every
+//! hart periodically pulls a thread over if it is much less loaded than the busiest one.
+//! A hart's load counts its queued threads and the one it runs. Only one run queue is
+//! ever locked at a time.
+
+use core::sync::atomic::Ordering;
+
+use crate::{cpu::MAX_HARTS, percpu, smp, thread::Thread};
+
+use super::{NR_QUEUED, load};
+
+fn busiest(hart: usize) -> Option<usize> {
+    (0..MAX_HARTS)
+        .filter(|&h| h != hart && smp::is_online(h))
+        .filter(|&h| NR_QUEUED[h].load(Ordering::Relaxed) != 0)
+        .max_by_key(|&h| load(h))
+}
+
+fn steal_from(from: usize, hart: usize) -> Option<*mut Thread> {
+    percpu::get(from)?.run_queue.lock().steal(hart)
+}
+
+/// Takes a thread allowed to run on `hart` from the busiest other hart.
+pub(super) fn steal(hart: usize) -> Option<*mut Thread> {
+    steal_from(busiest(hart)?, hart)
+}
+
+/// Moves a thread to `hart` if the busiest hart is at least two threads more loaded.
+pub(super) fn pull(hart: usize) -> bool {
+    let Some(from) = busiest(hart) else {
+        return false;
+    };
+    if load(from) < load(hart) + 2 {
+        return false;
+    }
+    let Some(thread) = steal_from(from, hart) else {
+        return false;
+    };
+    percpu::this_cpu_raw().run_queue.lock().enqueue(thread);
+    true
+}
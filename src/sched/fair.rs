@@ -0,0 +1,82 @@
+//! The fair class, modelled after Linux' CFS.
+//!
+//! Every thread accumulates virtual runtime: the time it ran, scaled down by its weight.
+//! The thread with the least virtual runtime runs next, so over time each thread gets CPU
+//! time in proportion to its weight, which follows from its nice value.
+
+use core::sync::atomic::Ordering;
+
+use crate::thread::Thread;
+
+use super::rq::{SchedClass, ThreadList, can_migrate};
+
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weights for nice -20 to 19, each step is about 10% CPU time (same as Linux)
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+
+fn weight(nice: i8) -> u64 {
+    WEIGHTS[(nice.clamp(MIN_NICE, MAX_NICE) - MIN_NICE) as usize]
+}
+
+/// Adds `ticks` of runtime to the thread's virtual runtime.
+pub(super) fn charge(thread: &Thread, nice: i8, ticks: u64) {
+    let delta = ticks * NICE_0_WEIGHT / weight(nice);
+    thread.sched.vruntime.fetch_add(delta, Ordering::Relaxed);
+}
+
+fn vruntime(thread: &Thread) -> u64 {
+    thread.sched.vruntime.load(Ordering::Relaxed)
+}
+
+pub(super) struct FairClass {
+    /// Sorted by virtual runtime
+    queue: ThreadList,
+    /// Never goes backwards, new and migrated threads start here so they can't starve
+    /// everyone who has been running for long
+    min_vruntime: u64,
+}
+
+impl FairClass {
+    pub const fn new() -> Self {
+        Self {
+            queue: ThreadList::new(),
+            min_vruntime: 0,
+        }
+    }
+}
+
+impl SchedClass for FairClass {
+    fn enqueue(&mut self, thread: *mut Thread) {
+        let t = unsafe { &*thread };
+        t.sched
+            .vruntime
+            .fetch_max(self.min_vruntime, Ordering::Relaxed);
+        self.queue.insert_sorted(thread, vruntime);
+    }
+
+    fn pick_next(&mut self) -> Option<*mut Thread> {
+        let thread = self.queue.pop_front()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime(unsafe { &*thread }));
+        Some(thread)
+    }
+
+    fn remove(&mut self, thread: *mut Thread) -> bool {
+        self.queue.remove(thread)
+    }
+
+    fn steal(&mut self, hart: usize) -> Option<*mut Thread> {
+        self.queue.remove_first(|t| can_migrate(t, hart))
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
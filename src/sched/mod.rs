@@ -0,0 +1,574 @@
+//! The scheduler: per-hart run queues with pluggable scheduling classes.
+//!
+//! Every thread has a [`Policy`]. Real-time threads (FIFO and round robin, see [`rt`]) run
+//! by strict priority before any fair thread (see [`fair`]), which share the rest of the
+//! time by virtual runtime. Every hart has its own run queue and a periodic timer
+//! interrupt; a thread whose time slice is over is preempted when the interrupt returns,
+//! unless it holds a lock. A hart with an empty run queue steals work from the busiest
+//! one, and all harts pull work over now and then (see [`balance`]). Threads only ever
+//! run on the harts in their affinity mask. Threads sleeping for some time are woken
+//! from the timer interrupt, see [`sleep_until`].
+//!
+//! A thread is marked `on_cpu` while it runs, and only loses the mark in
+//! [`finish_switch`], on the next thread, once it is off its stack. Until then no other
+//! hart may run it.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    cpu::{self, IRQ_S_TIMER, MAX_HARTS},
+    guest, percpu, process, smp,
+    sync::WaitQueue,
+    thread::{self, State, Thread, switch_to},
+    time, warn,
+};
+
+mod balance;
+pub mod fair;
+mod rq;
+pub mod rt;
+
+pub use rq::{RunLink, RunQueue};
+pub use rt::RT_PRIORITIES;
+
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// Ticks between two load balancing runs
+const BALANCE_TICKS: u64 = 4;
+
+const NOT_QUEUED: usize = usize::MAX;
+
+/// The thread each hart switched away from, for `finish_switch` to deal with
+static PREV: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Idle threads, which never sit in a run queue
+static IDLE: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Set once the running thread should give way, checked when a trap returns
+static NEED_RESCHED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+/// When the running thread's time slice ends, in timebase ticks
+static SLICE_END: [AtomicU64; MAX_HARTS] = [const { AtomicU64::new(0) }; MAX_HARTS];
+
+/// The [`rank`] of the thread running on each hart
+static CURRENT_RANK: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Number of threads in each hart's run queue, readable without taking its lock
+static NR_QUEUED: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Threads queued on `hart` plus the one running there, unless the hart is idle
+fn load(hart: usize) -> usize {
+    let running = CURRENT_RANK[hart].load(Ordering::Relaxed) != 0;
+    NR_QUEUED[hart].load(Ordering::Relaxed) + running as usize
+}
+
+static TICKS: [AtomicU64; MAX_HARTS] = [const { AtomicU64::new(0) }; MAX_HARTS];
+
+/// Threads sleeping until a deadline, see [`sleep_until`]
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// The earliest deadline in `SLEEPERS` in timebase ticks, `u64::MAX` if there is none
+static NEXT_WAKEUP: AtomicU64 = AtomicU64::new(u64::MAX);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Policy {
+    /// Real-time with a priority below [`RT_PRIORITIES`], runs until it gives up the hart
+    /// or a higher priority thread wakes up
+    Fifo(u8),
+    /// Like `Fifo`, but takes turns with threads of the same priority
+    RoundRobin(u8),
+    /// Shares the hart with the other fair threads, weighted by a nice value between
+    /// [`fair::MIN_NICE`] and [`fair::MAX_NICE`]
+    Fair(i8),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Fair(0)
+    }
+}
+
+const CLASS_FAIR: u8 = 0;
+const CLASS_FIFO: u8 = 1;
+const CLASS_RR: u8 = 2;
+
+/// How important a policy is, a thread preempts the running one if it ranks higher.
+/// Idle threads have rank 0.
+fn rank(policy: Policy) -> usize {
+    match policy {
+        Policy::Fair(_) => 1,
+        Policy::Fifo(priority) | Policy::RoundRobin(priority) => 2 + priority as usize,
+    }
+}
+
+/// Whether a thread with policy `a` would preempt one with policy `b`
+pub fn outranks(a: Policy, b: Policy) -> bool {
+    rank(a) > rank(b)
+}
+
+/// A thread's scheduling state
+pub struct SchedEntity {
+    class: AtomicU8,
+    /// The priority of real-time threads, the nice value of fair ones
+    param: AtomicU8,
+    affinity: AtomicUsize,
+    /// In timebase ticks, weighted by the nice value
+    vruntime: AtomicU64,
+    on_cpu: AtomicBool,
+    /// The hart whose run queue the thread is in, or `NOT_QUEUED`
+    queued_on: AtomicUsize,
+    /// The rank the thread was queued with, its policy may change in the meantime
+    queued_rank: AtomicUsize,
+}
+
+impl SchedEntity {
+    pub(crate) const fn new() -> Self {
+        Self {
+            class: AtomicU8::new(CLASS_FAIR),
+            param: AtomicU8::new(0),
+            affinity: AtomicUsize::new(usize::MAX),
+            vruntime: AtomicU64::new(0),
+            on_cpu: AtomicBool::new(false),
+            queued_on: AtomicUsize::new(NOT_QUEUED),
+            queued_rank: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        let param = self.param.load(Ordering::Relaxed);
+        match self.class.load(Ordering::Relaxed) {
+            CLASS_FIFO => Policy::Fifo(param),
+            CLASS_RR => Policy::RoundRobin(param),
+            _ => Policy::Fair(param as i8),
+        }
+    }
+
+    fn store_policy(&self, policy: Policy) {
+        let (class, param) = match policy {
+            Policy::Fifo(priority) => (CLASS_FIFO, priority.min(RT_PRIORITIES as u8 - 1)),
+            Policy::RoundRobin(priority) => (CLASS_RR, priority.min(RT_PRIORITIES as u8 - 1)),
+            Policy::Fair(nice) => (CLASS_FAIR, nice.clamp(fair::MIN_NICE, fair::MAX_NICE) as u8),
+        };
+        self.class.store(class, Ordering::Relaxed);
+        self.param.store(param, Ordering::Relaxed);
+    }
+
+    /// The harts the thread may run on, bit `n` standing for hart `n`
+    pub fn affinity(&self) -> usize {
+        self.affinity.load(Ordering::Relaxed)
+    }
+
+    pub fn allows(&self, hart: usize) -> bool {
+        self.affinity() & (1 << hart) != 0
+    }
+
+    /// The real-time priority the thread was queued with
+    fn queued_priority(&self) -> usize {
+        self.queued_rank.load(Ordering::Relaxed).saturating_sub(2)
+    }
+}
+
+impl Default for SchedEntity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Makes `idle` the calling hart's running thread.
+pub(crate) fn set_idle(idle: *mut Thread) {
+    let hart = cpu::hart_id();
+    let idle_ref = unsafe { &*idle };
+    idle_ref.set_state(State::Running);
+    idle_ref.sched.on_cpu.store(true, Ordering::Relaxed);
+    switched_in(idle_ref, hart);
+
+    IDLE[hart].store(idle as usize, Ordering::Relaxed);
+    percpu::this_cpu_raw().set_current_task(idle as usize);
+}
+
+pub(crate) fn current() -> *mut Thread {
+    percpu::this_cpu_raw().current_task() as *mut Thread
+}
+
+/// Starts the calling hart's timer tick. Every hart has to call this after
+/// `thread::init_hart`.
+pub fn init_hart() {
+    let hart = cpu::hart_id();
+    percpu::this_cpu_raw().run_queue.lock().set_hart(hart);
+
+    if let Err(e) = arm_timer() {
+        warn!("no timer, threads won't be preempted: {}", e);
+        return;
+    }
+    cpu::sie::set(1 << IRQ_S_TIMER);
+}
+
+/// Starts a new time slice on the calling hart.
+fn arm_timer() -> crate::sbi::SbiResult<()> {
+    let hart = cpu::hart_id();
+    let now = time::read_time();
+    let end = now + time::duration_to_ticks(TIME_SLICE);
+    SLICE_END[hart].store(end, Ordering::Relaxed);
+    program_timer(hart)
+}
+
+/// Sets the hart's timer for the end of its time slice, or for the first sleeping thread
+/// that is due if that is sooner.
+fn program_timer(hart: usize) -> crate::sbi::SbiResult<()> {
+    let end = SLICE_END[hart].load(Ordering::Relaxed);
+    time::set_timer(end.min(NEXT_WAKEUP.load(Ordering::Relaxed)))
+}
+
+/// Blocks the calling thread until `time` reaches `deadline`.
+///
+/// Every sleeping thread waits in the same queue, and the timer interrupt wakes them all
+/// once the earliest one is due. Those that aren't due yet go back to sleep and put
+/// their deadline back.
+pub(crate) fn sleep_until(deadline: u64) {
+    SLEEPERS.wait_until(|| {
+        if time::read_time() >= deadline {
+            return true;
+        }
+        // interrupts are off, so this is still the hart the thread runs on
+        let hart = cpu::hart_id();
+        if NEXT_WAKEUP.fetch_min(deadline, Ordering::Relaxed) > deadline
+            && deadline < SLICE_END[hart].load(Ordering::Relaxed)
+        {
+            let _ = program_timer(hart);
+        }
+        false
+    });
+}
+
+/// Wakes the sleeping threads if the earliest of them is due.
+fn wake_sleepers(now: u64) {
+    if now >= NEXT_WAKEUP.load(Ordering::Relaxed) {
+        // before the wakeups, which put the deadlines that aren't due back
+        NEXT_WAKEUP.store(u64::MAX, Ordering::Relaxed);
+        SLEEPERS.wake_all();
+    }
+}
+
+/// Picks the hart a thread that is about to become runnable should go to: the least
+/// loaded one it may run on, preferring the hart it ran on last.
+fn select_hart(thread: &Thread) -> usize {
+    let me = cpu::hart_id();
+    let last = thread.last_hart();
+    (0..MAX_HARTS)
+        .filter(|&h| thread.sched.allows(h) && (h == me || smp::is_online(h)))
+        .min_by_key(|&h| (load(h), h != last, h != me))
+        .unwrap_or(me)
+}
+
+/// Queues a runnable thread on `hart` and preempts what runs there if the thread ranks
+/// higher.
+fn enqueue_on(hart: usize, thread: *mut Thread) {
+    let Some(cpu) = percpu::get(hart) else {
+        return;
+    };
+    cpu.run_queue.lock().enqueue(thread);
+
+    let rank = rank(unsafe { &*thread }.sched.policy());
+    if rank > CURRENT_RANK[hart].load(Ordering::Relaxed) {
+        resched(hart);
+    }
+}
+
+fn kick(_: usize) {}
+
+/// Makes `hart` reschedule when it next returns from a trap, which an IPI forces.
+fn resched(hart: usize) {
+    NEED_RESCHED[hart].store(true, Ordering::Release);
+    if hart != cpu::hart_id() {
+        smp::smp_call_function(1 << hart, kick, 0, false);
+    }
+}
+
+/// Makes a thread that never ran runnable.
+pub(crate) fn start(thread: *mut Thread) {
+    let hart = select_hart(unsafe { &*thread });
+    enqueue_on(hart, thread);
+}
+
+/// Makes a blocked thread runnable again. Can be called from interrupt handlers.
+/// Returns false if the thread wasn't blocked.
+pub(crate) fn wake(thread: *mut Thread) -> bool {
+    let t = unsafe { &*thread };
+    {
+        // The hart the thread last ran on decides under its run queue lock whether to
+        // requeue a thread that is still switching away, see `schedule` and
+        // `finish_switch`.
+        let Some(cpu) = percpu::get(t.last_hart()) else {
+            return false;
+        };
+        let _rq = cpu.run_queue.lock();
+        if !t.transition(State::Blocked, State::Ready) {
+            return false;
+        }
+        if t.sched.on_cpu.load(Ordering::Acquire) {
+            return true;
+        }
+    }
+    enqueue_on(select_hart(t), thread);
+    true
+}
+
+/// Switches to the most important runnable thread, or to the idle thread if there is
+/// none and the current one can't continue. The current thread has to set its state
+/// beforehand.
+pub(crate) fn schedule() {
+    let cpu = percpu::this_cpu_raw();
+    debug_assert!(cpu.preempt_count() == 0, "scheduling while holding a lock");
+
+    let irqs_enabled = cpu::disable_interrupts();
+    let hart = cpu::hart_id();
+    let prev = current();
+    let prev_ref = unsafe { &*prev };
+    NEED_RESCHED[hart].store(false, Ordering::Relaxed);
+
+    let next = {
+        let mut rq = cpu.run_queue.lock();
+        // still on_cpu, so no other hart can take it before we are off its stack
+        if prev_ref.state() == State::Ready && !prev_ref.is_idle() && prev_ref.sched.allows(hart) {
+            rq.enqueue(prev);
+        }
+        rq.pick_next()
+    };
+    let next = next
+        .or_else(|| balance::steal(hart))
+        .unwrap_or(IDLE[hart].load(Ordering::Relaxed) as *mut Thread);
+
+    if next != prev {
+        let next_ref = unsafe { &*next };
+        // woken here right after it blocked on another hart, which may not be done yet
+        while next_ref.sched.on_cpu.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        next_ref.sched.on_cpu.store(true, Ordering::Relaxed);
+
+        switched_out(prev_ref);
+        switched_in(next_ref, hart);
+        cpu.stats.context_switches.fetch_add(1, Ordering::Relaxed);
+
+        PREV[hart].store(prev as usize, Ordering::Relaxed);
+        cpu.set_current_task(next as usize);
+        next_ref.set_state(State::Running);
+        let _ = arm_timer();
+        process::switch(prev_ref, next_ref);
+        unsafe { switch_to(&raw mut (*prev).context, &raw const (*next).context) };
+        finish_switch();
+    } else {
+        // its policy may have changed since it was switched to
+        set_current_rank(prev_ref, hart);
+        prev_ref.set_state(State::Running);
+    }
+
+    if irqs_enabled {
+        cpu::enable_interrupts();
+    }
+}
+
+fn switched_in(thread: &Thread, hart: usize) {
+    let stats = &thread.stats;
+    stats
+        .switched_in
+        .store(time::read_time(), Ordering::Relaxed);
+    stats
+        .steal_in
+        .store(guest::steal_time().as_nanos() as u64, Ordering::Relaxed);
+    stats.switches.fetch_add(1, Ordering::Relaxed);
+    stats.last_hart.store(hart, Ordering::Relaxed);
+    set_current_rank(thread, hart);
+}
+
+fn set_current_rank(thread: &Thread, hart: usize) {
+    let rank = if thread.is_idle() {
+        0
+    } else {
+        rank(thread.sched.policy())
+    };
+    CURRENT_RANK[hart].store(rank, Ordering::Relaxed);
+}
+
+/// Adds the time since the thread was switched to to its runtime, minus what the
+/// hypervisor stole from the hart in the meantime.
+fn switched_out(thread: &Thread) {
+    let stats = &thread.stats;
+    let ran = time::read_time().saturating_sub(stats.switched_in.load(Ordering::Relaxed));
+    let stolen_ns = (guest::steal_time().as_nanos() as u64)
+        .saturating_sub(stats.steal_in.load(Ordering::Relaxed));
+    let stolen = time::duration_to_ticks(Duration::from_nanos(stolen_ns));
+    let ran = ran.saturating_sub(stolen);
+    stats.runtime.fetch_add(ran, Ordering::Relaxed);
+
+    if let Policy::Fair(nice) = thread.sched.policy() {
+        fair::charge(thread, nice, ran);
+    }
+}
+
+/// Runs on the thread we just switched to, once the previous one is off its stack.
+pub(crate) fn finish_switch() {
+    let hart = cpu::hart_id();
+    let prev = PREV[hart].swap(0, Ordering::Relaxed) as *mut Thread;
+    let Some(prev_ref) = (unsafe { prev.as_ref() }) else {
+        return;
+    };
+
+    let place = {
+        let mut rq = percpu::this_cpu_raw().run_queue.lock();
+        prev_ref.sched.on_cpu.store(false, Ordering::Release);
+        match prev_ref.state() {
+            // not allowed on this hart anymore
+            State::Ready
+                if !prev_ref.is_idle()
+                    && prev_ref.sched.queued_on.load(Ordering::Relaxed) == NOT_QUEUED =>
+            {
+                if prev_ref.sched.allows(hart) {
+                    rq.enqueue(prev);
+                    false
+                } else {
+                    true
+                }
+            }
+            State::Exited => {
+                drop(rq);
+                Thread::release(prev);
+                false
+            }
+            _ => false,
+        }
+    };
+    if place {
+        enqueue_on(select_hart(prev_ref), prev);
+    }
+}
+
+/// Takes a queued thread out of its run queue and queues it again, wherever it should
+/// go now. Does nothing if the thread isn't queued.
+fn requeue(thread: &Thread) {
+    let ptr = thread as *const Thread as *mut Thread;
+    let hart = thread.sched.queued_on.load(Ordering::Acquire);
+    let Some(cpu) = percpu::get(hart) else {
+        return;
+    };
+    if cpu.run_queue.lock().remove(ptr) {
+        enqueue_on(select_hart(thread), ptr);
+    }
+}
+
+/// Makes a running thread check whether it may go on running where it is, with the
+/// policy and affinity it has now.
+fn reconsider_running(thread: &Thread) {
+    if thread.state() != State::Running {
+        return;
+    }
+    if !core::ptr::eq(thread, thread::current()) {
+        // its hart updates the rank it runs with when it reschedules
+        resched(thread.last_hart());
+        return;
+    }
+
+    let (hart, outranked) = {
+        // locking it keeps the thread on this hart
+        let rq = percpu::this_cpu_raw().run_queue.lock();
+        let hart = cpu::hart_id();
+        set_current_rank(thread, hart);
+        (
+            hart,
+            rq.top_rank() > CURRENT_RANK[hart].load(Ordering::Relaxed),
+        )
+    };
+    if outranked || !thread.sched.allows(hart) {
+        if percpu::preemptible() {
+            thread::yield_now();
+        } else {
+            resched(hart);
+        }
+    }
+}
+
+/// Changes a thread's policy. Priorities and nice values out of range are clamped.
+pub fn set_policy(thread: &Thread, policy: Policy) {
+    thread.sched.store_policy(policy);
+    requeue(thread);
+    reconsider_running(thread);
+}
+
+/// Restricts a thread to the harts in `mask`, bit `n` standing for hart `n`. Returns
+/// false and leaves the affinity alone if none of them is online.
+pub fn set_affinity(thread: &Thread, mask: usize) -> bool {
+    if mask & smp::online_mask() == 0 && mask & (1 << cpu::hart_id()) == 0 {
+        return false;
+    }
+    thread.sched.affinity.store(mask, Ordering::Relaxed);
+    requeue(thread);
+    reconsider_running(thread);
+    true
+}
+
+/// Called from the timer interrupt.
+pub fn tick() {
+    let hart = cpu::hart_id();
+    let current = thread::current();
+    let now = time::read_time();
+    wake_sleepers(now);
+
+    let slice_over = now >= SLICE_END[hart].load(Ordering::Relaxed);
+    if slice_over && !matches!(current.sched.policy(), Policy::Fifo(_)) {
+        NEED_RESCHED[hart].store(true, Ordering::Relaxed);
+    }
+
+    if TICKS[hart]
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(BALANCE_TICKS)
+        && balance::pull(hart)
+        && current.is_idle()
+    {
+        NEED_RESCHED[hart].store(true, Ordering::Relaxed);
+    }
+
+    // keeps the tick going even if nothing else is runnable, and clears the interrupt.
+    // A wakeup before the end of the slice doesn't start a new one.
+    let _ = if slice_over {
+        arm_timer()
+    } else {
+        program_timer(hart)
+    };
+}
+
+/// Called by the trap handler just before returning to the interrupted code, which is
+/// where a thread whose time slice is over gets preempted.
+pub fn preempt_if_needed() {
+    let hart = cpu::hart_id();
+    // a thread that is about to block or exit isn't running anymore, and keeps its state
+    if NEED_RESCHED[hart].load(Ordering::Relaxed)
+        && percpu::preemptible()
+        && thread::current().transition(State::Running, State::Ready)
+    {
+        schedule();
+    }
+}
+
+/// What a hart does once it has nothing left to do but run threads.
+pub fn idle_loop() -> ! {
+    let hart = cpu::hart_id();
+    let cpu = percpu::this_cpu_raw();
+    loop {
+        let empty = cpu.run_queue.lock().is_empty();
+        if empty {
+            // rather than wait for the next pull while other harts have work queued
+            match balance::steal(hart) {
+                Some(thread) => cpu.run_queue.lock().enqueue(thread),
+                None => {
+                    cpu::wait();
+                    continue;
+                }
+            }
+        }
+        thread::yield_now();
+    }
+}
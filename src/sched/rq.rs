@@ -0,0 +1,294 @@
+//! Per-hart run queues.
+//!
+//! Queued threads are linked through a [`RunLink`] of their own, so queues never fill up.
+//! Every scheduling class keeps its own lists; the run queue asks them in priority order.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::thread::Thread;
+
+use super::{NOT_QUEUED, Policy, fair::FairClass, rank, rt::RtClass};
+
+/// Links a task into a run queue, every task has one
+pub struct RunLink {
+    next: AtomicPtr<RunLink>,
+    /// The handle the queue gives back for the task
+    task: AtomicUsize,
+}
+
+impl RunLink {
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            task: AtomicUsize::new(0),
+        }
+    }
+
+    fn next(&self) -> *mut RunLink {
+        self.next.load(Ordering::Relaxed)
+    }
+
+    fn set_next(&self, next: *mut RunLink) {
+        self.next.store(next, Ordering::Relaxed);
+    }
+
+    fn task(&self) -> usize {
+        self.task.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for RunLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An intrusive singly linked list of tasks
+pub(super) struct List {
+    head: *mut RunLink,
+    tail: *mut RunLink,
+    len: usize,
+}
+
+impl List {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends `task`, which `link` belongs to. The link must not be in any list.
+    pub fn push_back(&mut self, link: &RunLink, task: usize) {
+        let link_ptr = link as *const RunLink as *mut RunLink;
+        link.task.store(task, Ordering::Relaxed);
+        link.set_next(ptr::null_mut());
+        match unsafe { self.tail.as_ref() } {
+            Some(tail) => tail.set_next(link_ptr),
+            None => self.head = link_ptr,
+        }
+        self.tail = link_ptr;
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<usize> {
+        self.remove_first(|_| true)
+    }
+
+    /// Inserts `task` after every task with a key less or equal to its own.
+    pub fn insert_sorted(&mut self, link: &RunLink, task: usize, key: impl Fn(usize) -> u64) {
+        let link_ptr = link as *const RunLink as *mut RunLink;
+        link.task.store(task, Ordering::Relaxed);
+        let k = key(task);
+        let mut prev: *mut RunLink = ptr::null_mut();
+        let mut cur = self.head;
+        while let Some(c) = unsafe { cur.as_ref() } {
+            if key(c.task()) > k {
+                break;
+            }
+            prev = cur;
+            cur = c.next();
+        }
+
+        link.set_next(cur);
+        match unsafe { prev.as_ref() } {
+            Some(p) => p.set_next(link_ptr),
+            None => self.head = link_ptr,
+        }
+        if cur.is_null() {
+            self.tail = link_ptr;
+        }
+        self.len += 1;
+    }
+
+    /// Removes the first task `f` returns true for.
+    pub fn remove_first(&mut self, mut f: impl FnMut(usize) -> bool) -> Option<usize> {
+        let mut prev: *mut RunLink = ptr::null_mut();
+        let mut cur = self.head;
+        while let Some(link) = unsafe { cur.as_ref() } {
+            if f(link.task()) {
+                match unsafe { prev.as_ref() } {
+                    Some(p) => p.set_next(link.next()),
+                    None => self.head = link.next(),
+                }
+                if self.tail == cur {
+                    self.tail = prev;
+                }
+                link.set_next(ptr::null_mut());
+                self.len -= 1;
+                return Some(link.task());
+            }
+            prev = cur;
+            cur = link.next();
+        }
+        None
+    }
+}
+
+fn thread<'a>(task: usize) -> &'a Thread {
+    unsafe { &*(task as *const Thread) }
+}
+
+/// A [`List`] of threads
+pub(super) struct ThreadList(List);
+
+impl ThreadList {
+    pub const fn new() -> Self {
+        Self(List::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn push_back(&mut self, t: *mut Thread) {
+        self.0.push_back(&thread(t as usize).link, t as usize);
+    }
+
+    pub fn pop_front(&mut self) -> Option<*mut Thread> {
+        self.0.pop_front().map(|task| task as *mut Thread)
+    }
+
+    /// Inserts `t` after every thread with a key less or equal to its own.
+    pub fn insert_sorted(&mut self, t: *mut Thread, key: impl Fn(&Thread) -> u64) {
+        self.0
+            .insert_sorted(&thread(t as usize).link, t as usize, |task| {
+                key(thread(task))
+            });
+    }
+
+    pub fn remove_first(&mut self, mut f: impl FnMut(&Thread) -> bool) -> Option<*mut Thread> {
+        self.0
+            .remove_first(|task| f(thread(task)))
+            .map(|task| task as *mut Thread)
+    }
+
+    pub fn remove(&mut self, t: *mut Thread) -> bool {
+        self.0.remove_first(|task| task == t as usize).is_some()
+    }
+}
+
+/// A scheduling class decides the order of the threads with its policies.
+pub(super) trait SchedClass {
+    fn enqueue(&mut self, thread: *mut Thread);
+
+    /// Takes the thread that should run next.
+    fn pick_next(&mut self) -> Option<*mut Thread>;
+
+    fn remove(&mut self, thread: *mut Thread) -> bool;
+
+    /// Takes a thread that isn't running and may run on `hart`, for load balancing.
+    fn steal(&mut self, hart: usize) -> Option<*mut Thread>;
+
+    fn len(&self) -> usize;
+}
+
+/// Whether a queued thread can be moved to `hart`
+pub(super) fn can_migrate(thread: &Thread, hart: usize) -> bool {
+    !thread.sched.on_cpu.load(Ordering::Acquire) && thread.sched.allows(hart)
+}
+
+pub struct RunQueue {
+    hart: usize,
+    rt: RtClass,
+    fair: FairClass,
+}
+
+// the threads are only reached through the queue while its lock is held
+unsafe impl Send for RunQueue {}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        Self {
+            hart: 0,
+            rt: RtClass::new(),
+            fair: FairClass::new(),
+        }
+    }
+
+    pub(super) fn set_hart(&mut self, hart: usize) {
+        self.hart = hart;
+    }
+
+    /// The classes, most important first
+    fn classes(&mut self) -> [&mut dyn SchedClass; 2] {
+        [&mut self.rt, &mut self.fair]
+    }
+
+    /// The class the thread was queued in
+    fn class_of(&mut self, thread: &Thread) -> &mut dyn SchedClass {
+        if thread.sched.queued_rank.load(Ordering::Relaxed) > 1 {
+            &mut self.rt
+        } else {
+            &mut self.fair
+        }
+    }
+
+    pub(super) fn enqueue(&mut self, thread: *mut Thread) {
+        let t = unsafe { &*thread };
+        t.sched.queued_on.store(self.hart, Ordering::Relaxed);
+        t.sched
+            .queued_rank
+            .store(rank(t.sched.policy()), Ordering::Relaxed);
+        self.class_of(t).enqueue(thread);
+        super::NR_QUEUED[self.hart].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn dequeued(&self, thread: *mut Thread) -> *mut Thread {
+        unsafe { &*thread }
+            .sched
+            .queued_on
+            .store(NOT_QUEUED, Ordering::Relaxed);
+        super::NR_QUEUED[self.hart].fetch_sub(1, Ordering::Relaxed);
+        thread
+    }
+
+    pub(super) fn pick_next(&mut self) -> Option<*mut Thread> {
+        let thread = self.classes().into_iter().find_map(|c| c.pick_next())?;
+        Some(self.dequeued(thread))
+    }
+
+    pub(super) fn remove(&mut self, thread: *mut Thread) -> bool {
+        let removed = self.class_of(unsafe { &*thread }).remove(thread);
+        if removed {
+            self.dequeued(thread);
+        }
+        removed
+    }
+
+    pub(super) fn steal(&mut self, hart: usize) -> Option<*mut Thread> {
+        let thread = self.classes().into_iter().find_map(|c| c.steal(hart))?;
+        Some(self.dequeued(thread))
+    }
+
+    /// The [`rank`] of the thread `pick_next` would take, 0 if there is none
+    pub(super) fn top_rank(&self) -> usize {
+        match self.rt.top_priority() {
+            Some(priority) => rank(Policy::Fifo(priority)),
+            None if self.fair.len() != 0 => rank(Policy::default()),
+            None => 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rt.len() + self.fair.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
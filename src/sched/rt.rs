@@ -0,0 +1,57 @@
+//! The real-time class: strict priorities, FIFO or round robin within a priority.
+//!
+//! Real-time threads always run before fair ones. A FIFO thread runs until it yields,
+//! blocks or a higher priority thread wakes up; round robin threads additionally give way
+//! to their equals when their time slice is over.
+
+use crate::thread::Thread;
+
+use super::rq::{SchedClass, ThreadList, can_migrate};
+
+/// Priorities go from 0 to `RT_PRIORITIES - 1`, higher runs first
+pub const RT_PRIORITIES: usize = 16;
+
+pub(super) struct RtClass {
+    queues: [ThreadList; RT_PRIORITIES],
+}
+
+impl RtClass {
+    pub const fn new() -> Self {
+        Self {
+            queues: [const { ThreadList::new() }; RT_PRIORITIES],
+        }
+    }
+
+    /// The highest priority a thread is queued with
+    pub fn top_priority(&self) -> Option<u8> {
+        let priority = self.queues.iter().rposition(|q| q.len() != 0)?;
+        Some(priority as u8)
+    }
+}
+
+impl SchedClass for RtClass {
+    fn enqueue(&mut self, thread: *mut Thread) {
+        let priority = unsafe { &*thread }.sched.queued_priority();
+        self.queues[priority].push_back(thread);
+    }
+
+    fn pick_next(&mut self) -> Option<*mut Thread> {
+        self.queues.iter_mut().rev().find_map(|q| q.pop_front())
+    }
+
+    fn remove(&mut self, thread: *mut Thread) -> bool {
+        let priority = unsafe { &*thread }.sched.queued_priority();
+        self.queues[priority].remove(thread)
+    }
+
+    fn steal(&mut self, hart: usize) -> Option<*mut Thread> {
+        self.queues
+            .iter_mut()
+            .rev()
+            .find_map(|q| q.remove_first(|t| can_migrate(t, hart)))
+    }
+
+    fn len(&self) -> usize {
+        self.queues.iter().map(ThreadList::len).sum()
+    }
+}
@@ -0,0 +1,137 @@
+//! Running functions on other harts.
+//!
+//! Each hart has a queue of pending calls. A caller queues its function on every target
+//! and raises a supervisor software interrupt there through SBI, whose handler drains the
+//! queue. Waiting callers keep draining their own queue, so two harts calling each other
+//! with interrupts disabled don't deadlock.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    cpu::{self, IRQ_S_SOFT, MAX_HARTS},
+    sbi::{
+        HartMask,
+        features::{self, Extension},
+        ipi, legacy,
+    },
+    sync::IrqSpinLock,
+    warn,
+};
+
+use super::online_mask;
+
+const QUEUE_SIZE: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Call {
+    func: fn(usize),
+    arg: usize,
+    /// Counts down the harts still running the call, null for callers that don't wait
+    pending: *const AtomicUsize,
+}
+
+struct CallQueue {
+    calls: [Option<Call>; QUEUE_SIZE],
+}
+
+// `pending` points into the stack of a caller that waits until every target is done
+unsafe impl Send for CallQueue {}
+
+static QUEUES: [IrqSpinLock<CallQueue>; MAX_HARTS] = [const {
+    IrqSpinLock::new(CallQueue {
+        calls: [None; QUEUE_SIZE],
+    })
+}; MAX_HARTS];
+
+/// Enables software interrupts on the calling hart. Every hart has to call this.
+pub fn init_hart() {
+    cpu::sie::set(1 << IRQ_S_SOFT);
+}
+
+/// Runs `func(arg)` on every online hart in `mask`, including the calling one if it is in
+/// the mask. With `wait`, returns once all of them are done, otherwise as soon as the
+/// calls are queued.
+pub fn smp_call_function(mask: usize, func: fn(usize), arg: usize, wait: bool) {
+    let me = cpu::hart_id();
+    let targets = mask & online_mask() & !(1 << me);
+
+    let pending = AtomicUsize::new(targets.count_ones() as usize);
+    let call = Call {
+        func,
+        arg,
+        pending: if wait { &pending } else { core::ptr::null() },
+    };
+
+    for hart in (0..MAX_HARTS).filter(|hart| targets & (1 << hart) != 0) {
+        queue(hart, call);
+    }
+    if targets != 0 {
+        send_ipi(targets);
+    }
+
+    if mask & (1 << me) != 0 {
+        let enabled = cpu::disable_interrupts();
+        func(arg);
+        if enabled {
+            cpu::enable_interrupts();
+        }
+    }
+
+    if wait {
+        while pending.load(Ordering::Acquire) != 0 {
+            handle_ipi();
+            spin_loop();
+        }
+    }
+}
+
+/// Runs `func(arg)` on every other online hart and waits for them.
+pub fn smp_call_function_others(func: fn(usize), arg: usize) {
+    smp_call_function(online_mask() & !(1 << cpu::hart_id()), func, arg, true);
+}
+
+fn queue(hart: usize, call: Call) {
+    loop {
+        if let Some(slot) = QUEUES[hart].lock().calls.iter_mut().find(|c| c.is_none()) {
+            *slot = Some(call);
+            return;
+        }
+        // the target is busy, make sure we aren't the reason it can't make progress
+        handle_ipi();
+        spin_loop();
+    }
+}
+
+fn send_ipi(mask: usize) {
+    let result = if features::has(Extension::Ipi) {
+        ipi::send_ipi(HartMask::from_mask(0, mask))
+    } else {
+        // the legacy call wants a pointer to a plain bitmask starting at hart 0
+        let mut legacy_mask = mask;
+        legacy::send_ipi(&mut legacy_mask as *mut usize as *mut HartMask)
+    };
+    if let Err(e) = result {
+        warn!("failed to send IPI to harts 0x{:x}: {}", mask, e);
+    }
+}
+
+/// Runs the calls queued for the calling hart. Called for supervisor software interrupts.
+pub fn handle_ipi() {
+    cpu::sip::clear(1 << IRQ_S_SOFT);
+
+    let queue = &QUEUES[cpu::hart_id()];
+    loop {
+        let call = queue.lock().calls.iter_mut().find_map(|c| c.take());
+        let Some(call) = call else {
+            return;
+        };
+
+        (call.func)(call.arg);
+        if let Some(pending) = unsafe { call.pending.as_ref() } {
+            pending.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Bringing up the other harts.
+//!
+//! The firmware only starts the boot hart. The others are found in the device tree's
+//! `/cpus` node and started through HSM at `secondary_entry`, each on a stack of its own.
+
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    alloc::{PAGE_SIZE, dealloc, zalloc},
+    cpu::{self, MAX_HARTS},
+    fdt, guest, info, page,
+    percpu::{self, STACK_TOP_OFFSET},
+    sbi::{
+        features::{self, Extension},
+        hsm::{self, HartState},
+    },
+    sched, thread, time, trap, warn,
+};
+
+pub mod call;
+pub mod tlb;
+
+pub use call::{smp_call_function, smp_call_function_others};
+
+/// 64 KiB of kernel stack for each secondary hart
+const STACK_PAGES: usize = 16;
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bitmask of the harts that finished their bring-up
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn online_mask() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+pub fn is_online(hart_id: usize) -> bool {
+    hart_id < MAX_HARTS && online_mask() & (1 << hart_id) != 0
+}
+
+pub fn num_online() -> usize {
+    online_mask().count_ones() as usize
+}
+
+fn set_online() {
+    ONLINE.fetch_or(1 << cpu::hart_id(), Ordering::Release);
+}
+
+/// Starts every hart listed in the device tree and waits for them to come online.
+/// Called on the boot hart once paging and traps are set up.
+pub fn init() {
+    set_online();
+
+    if !features::has(Extension::Hsm) {
+        info!("no HSM, running on the boot hart only");
+        return;
+    }
+    let Some(cpus) = fdt::get().and_then(|fdt| fdt.find_node("/cpus")) else {
+        warn!("no /cpus node, running on the boot hart only");
+        return;
+    };
+
+    let boot_hart = cpu::hart_id();
+    for node in cpus.children() {
+        if node.property_str("device_type") != Some("cpu") {
+            continue;
+        }
+        // "okay" or no status at all means usable, "disabled" and "fail" don't
+        if node
+            .property_str("status")
+            .is_some_and(|status| status != "okay")
+        {
+            continue;
+        }
+        let Some(hart_id) = node.property_u32("reg").map(|reg| reg as usize) else {
+            continue;
+        };
+        if hart_id == boot_hart {
+            continue;
+        }
+        if hart_id >= MAX_HARTS {
+            warn!(
+                "ignoring hart {}, only {} are supported",
+                hart_id, MAX_HARTS
+            );
+            continue;
+        }
+        start(hart_id);
+    }
+
+    info!("{} harts online", num_online());
+}
+
+fn start(hart_id: usize) {
+    match hsm::hart_get_status(hart_id) {
+        Ok(HartState::Stopped) => {}
+        Ok(state) => {
+            warn!("not starting hart {}, it is {:?}", hart_id, state);
+            return;
+        }
+        Err(e) => {
+            warn!("failed to get the status of hart {}: {}", hart_id, e);
+            return;
+        }
+    }
+
+    let stack = zalloc(STACK_PAGES);
+    if stack.is_null() {
+        warn!("out of memory for the stack of hart {}", hart_id);
+        return;
+    }
+    let cpu = percpu::get(hart_id).unwrap();
+    cpu.init(hart_id, stack as usize + STACK_PAGES * PAGE_SIZE);
+
+    let entry = secondary_entry as *const () as usize;
+    if let Err(e) = unsafe { hsm::hart_start(hart_id, entry, cpu as *const _ as usize) } {
+        warn!("failed to start hart {}: {}", hart_id, e);
+        dealloc(stack);
+        return;
+    }
+
+    // the stack stays allocated on timeout, the hart may still show up
+    let deadline = time::uptime() + ONLINE_TIMEOUT;
+    while !is_online(hart_id) {
+        if time::uptime() > deadline {
+            warn!("hart {} didn't come online", hart_id);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Where secondary harts start, with the MMU off, the hart id in `a0` and their
+/// [`PerCpu`](percpu::PerCpu) area in `a1`.
+#[unsafe(naked)]
+unsafe extern "C" fn secondary_entry() {
+    naked_asm!(
+        ".option push;
+        .option norelax;
+        la gp, __global_pointer;
+        .option pop;
+
+        mv tp, a1;
+        ld sp, {stack_top}(tp);
+        j {main}",
+        stack_top = const STACK_TOP_OFFSET,
+        main = sym secondary_main,
+    );
+}
+
+extern "C" fn secondary_main(hart_id: usize) -> ! {
+    page::init_hart();
+    percpu::init_hart();
+    trap::init();
+    trap::misaligned::init();
+    trap::events::init();
+    call::init_hart();
+    thread::init_hart();
+    sched::init_hart();
+    guest::init_hart();
+
+    set_online();
+    info!("hart {} online", hart_id);
+
+    cpu::enable_interrupts();
+    sched::idle_loop()
+}
@@ -0,0 +1,48 @@
+//! TLB shootdown.
+//!
+//! Every hart may have cached translations of the kernel page table. After a mapping is
+//! removed or changed, the stale entries have to be flushed everywhere before the memory
+//! is reused.
+
+use core::arch::asm;
+
+use crate::alloc::PAGE_SIZE;
+
+use super::call::smp_call_function_others;
+
+/// Above this many pages it is cheaper to flush the whole TLB
+const MAX_FLUSH_PAGES: usize = 64;
+
+#[derive(Clone, Copy)]
+struct Range {
+    start: usize,
+    size: usize,
+}
+
+fn flush_local(range: &Range) {
+    if range.size == usize::MAX || range.size / PAGE_SIZE > MAX_FLUSH_PAGES {
+        unsafe { asm!("sfence.vma") };
+        return;
+    }
+
+    let start = range.start & !(PAGE_SIZE - 1);
+    for addr in (start..range.start + range.size).step_by(PAGE_SIZE) {
+        unsafe { asm!("sfence.vma {}, zero", in(reg) addr) };
+    }
+}
+
+fn flush_ipi(arg: usize) {
+    flush_local(unsafe { &*(arg as *const Range) });
+}
+
+/// Flushes `start..start + size` from the TLBs of all online harts and waits until they
+/// are done.
+pub fn flush_range(start: usize, size: usize) {
+    let range = Range { start, size };
+    flush_local(&range);
+    smp_call_function_others(flush_ipi, &range as *const Range as usize);
+}
+
+pub fn flush_all() {
+    flush_range(0, usize::MAX);
+}
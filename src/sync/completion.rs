@@ -0,0 +1,72 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::WaitQueue;
+
+/// Completed for good by `complete_all`
+const DONE: usize = usize::MAX;
+
+/// An event threads wait for, such as a device finishing a request. Every `complete`
+/// lets one `wait` return, `complete_all` lets all of them return until `reinit`. Both
+/// can be called from interrupt handlers.
+pub struct Completion {
+    done: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Self {
+            done: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Sleeps until the event happened.
+    pub fn wait(&self) {
+        if !self.try_wait() {
+            self.waiters.wait_until(|| self.try_wait());
+        }
+    }
+
+    /// Consumes a completion if there is one, without sleeping.
+    pub fn try_wait(&self) -> bool {
+        self.done
+            .try_update(Ordering::Acquire, Ordering::Relaxed, |n| match n {
+                0 => None,
+                DONE => Some(DONE),
+                n => Some(n - 1),
+            })
+            .is_ok()
+    }
+
+    /// Lets one waiter, present or future, through.
+    pub fn complete(&self) {
+        let _ = self
+            .done
+            .try_update(Ordering::Release, Ordering::Relaxed, |n| {
+                (n < DONE - 1).then_some(n + 1)
+            });
+        self.waiters.wake_one();
+    }
+
+    /// Lets every waiter through, and every later one until `reinit`.
+    pub fn complete_all(&self) {
+        self.done.store(DONE, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire) != 0
+    }
+
+    /// Makes the event happen anew. Nobody may wait for it at the time.
+    pub fn reinit(&self) {
+        self.done.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,68 @@
+use super::{MutexGuard, WaitQueue, waitqueue::Waiter};
+
+/// Lets threads sleep until the data a [`Mutex`](super::Mutex) protects is in the state
+/// they need. Notifying can be done from interrupt handlers, which can't take the mutex,
+/// so a handler has to publish what it changed some other way, such as in an atomic.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex, sleeps until notified and locks the mutex again. Wakeups may
+    /// be spurious, see [`wait_while`](Self::wait_while).
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let mut waiter = Waiter::new();
+        let waiter = &raw mut waiter;
+        // queued before the unlock, so a notify right after it still finds us
+        self.waiters.lock().push(waiter);
+        drop(guard);
+        self.waiters.sleep_until_woken(waiter);
+        mutex.lock()
+    }
+
+    /// Sleeps for as long as `cond` holds for the protected data. The thread is queued
+    /// before `cond` is checked, so a notify from a waker that doesn't take the mutex,
+    /// such as an interrupt handler, can't get lost between the check and the sleep.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        loop {
+            let mut waiter = Waiter::new();
+            let waiter = &raw mut waiter;
+            self.waiters.lock().push(waiter);
+            if !cond(&mut guard) {
+                self.waiters.lock().remove(waiter);
+                return guard;
+            }
+            let mutex = guard.mutex();
+            drop(guard);
+            self.waiters.sleep_until_woken(waiter);
+            guard = mutex.lock();
+        }
+    }
+
+    /// Wakes one waiting thread. Returns false if nobody waited.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    /// Wakes every waiting thread and returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,109 @@
+//! Debug checks shared by the locks, only active in debug builds.
+//!
+//! Every lock remembers the hart holding it. Taking a lock the calling hart already holds
+//! panics right away instead of spinning forever, and spinning for very long reports the
+//! holder. Locks can also be given a level: a hart must take them in increasing order,
+//! taking one at or below the highest level it holds panics.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+};
+
+use crate::{console, cpu::MAX_HARTS, percpu};
+
+const NO_HOLDER: usize = usize::MAX;
+
+/// Spins until a waiting hart reports the holder
+const SPINS_BEFORE_REPORT: usize = 100_000_000;
+
+/// Highest level of the ordered locks each hart holds
+static HELD_LEVEL: [AtomicU8; MAX_HARTS] = [const { AtomicU8::new(0) }; MAX_HARTS];
+
+/// NO_HOLDER before `tp` is set up
+fn current_hart() -> usize {
+    percpu::try_this_cpu().map_or(NO_HOLDER, |cpu| cpu.hart_id())
+}
+
+pub(super) struct LockInfo {
+    holder: AtomicUsize,
+    /// 0 for locks outside the ordering
+    level: u8,
+}
+
+impl LockInfo {
+    pub const fn new(level: u8) -> Self {
+        Self {
+            holder: AtomicUsize::new(NO_HOLDER),
+            level,
+        }
+    }
+
+    pub fn holder(&self) -> Option<usize> {
+        match self.holder.load(Ordering::Relaxed) {
+            NO_HOLDER => None,
+            hart => Some(hart),
+        }
+    }
+
+    /// Called before waiting for the lock.
+    pub fn check_recursion(&self, lock: *const ()) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let hart = current_hart();
+        if hart != NO_HOLDER && self.holder() == Some(hart) {
+            panic!(
+                "deadlock: hart {} takes lock {:p} it already holds",
+                hart, lock
+            );
+        }
+    }
+
+    /// Called on every failed attempt to take the lock.
+    pub fn spinning(&self, lock: *const (), spins: &mut usize) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        *spins += 1;
+        if *spins == SPINS_BEFORE_REPORT {
+            let holder = self.holder.load(Ordering::Relaxed);
+            console::force_print(format_args!(
+                "possible deadlock: hart {} waits for lock {:p} held by hart {}\r\n",
+                current_hart() as isize,
+                lock,
+                holder as isize
+            ));
+        }
+    }
+
+    /// Records the calling hart as the holder, returns what `released` needs.
+    pub fn acquired(&self, lock: *const ()) -> u8 {
+        let hart = current_hart();
+        self.holder.store(hart, Ordering::Relaxed);
+        if !cfg!(debug_assertions) || self.level == 0 || hart == NO_HOLDER {
+            return 0;
+        }
+
+        let held = HELD_LEVEL[hart].load(Ordering::Relaxed);
+        if held >= self.level {
+            panic!(
+                "lock order violation: hart {} takes lock {:p} of level {} while holding level {}",
+                hart, lock, self.level, held
+            );
+        }
+        HELD_LEVEL[hart].store(self.level, Ordering::Relaxed);
+        held
+    }
+
+    pub fn released(&self, prev_level: u8) {
+        let hart = self.holder.swap(NO_HOLDER, Ordering::Relaxed);
+        if cfg!(debug_assertions) && self.level != 0 && hart != NO_HOLDER {
+            HELD_LEVEL[hart].store(prev_level, Ordering::Relaxed);
+        }
+    }
+}
+
+pub(super) fn addr<T>(lock: &T) -> *const () {
+    ptr::from_ref(lock).cast()
+}
@@ -0,0 +1,48 @@
+//! Synchronization primitives.
+//!
+//! - [`SpinLock`]: the default lock, keeps preemption off while held
+//! - [`IrqSpinLock`]: additionally disables interrupts, for data interrupt handlers use
+//! - [`TicketLock`]: a fair lock for heavily contended data
+//! - [`RwLock`]: many readers or one writer
+//! - [`Once`] and [`Lazy`]: one-time initialization
+//!
+//! The sleeping primitives block the calling thread instead of spinning, so they can't
+//! be waited on in interrupt handlers, but wakeups work from anywhere:
+//!
+//! - [`WaitQueue`]: threads sleeping until woken one at a time or all at once
+//! - [`Mutex`]: a lock with priority inheritance, for data held across blocking calls
+//! - [`Semaphore`]: a counting semaphore
+//! - [`Condvar`]: sleeping until the data behind a [`Mutex`] changes
+//! - [`Completion`]: waiting for an event, such as a device finishing a request
+//!
+//! In debug builds every spinning lock checks for recursive locking, reports the holding hart when
+//! a waiter spins for too long, and checks the order of locks created `with_level`.
+
+mod completion;
+mod condvar;
+mod lockdep;
+mod mutex;
+mod once;
+mod rwlock;
+mod semaphore;
+mod spin;
+mod ticket;
+mod waitqueue;
+
+pub use completion::Completion;
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+
+pub use once::{Lazy, Once};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spin::{IrqSpinLock, IrqSpinLockGuard, SpinLock, SpinLockGuard};
+pub use ticket::{TicketLock, TicketLockGuard};
+pub use waitqueue::WaitQueue;
+
+/// Levels of the ordered locks. A hart holding a lock may only take locks of a higher level.
+pub mod level {
+    pub const ALLOC: u8 = 1;
+    pub const LOG_RING: u8 = 1;
+    pub const CONSOLE: u8 = 2;
+}
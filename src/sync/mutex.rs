@@ -0,0 +1,176 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    cpu, percpu,
+    sched::{self, Policy},
+    thread::{self, Thread},
+};
+
+use super::waitqueue::{WaitQueue, Waiter};
+
+/// A lock that puts waiters to sleep instead of spinning, for data that is held across
+/// operations that may block. Interrupt handlers can't take it.
+///
+/// A waiter that ranks higher than the owner lends the owner its policy until the owner
+/// unlocks, so a thread of lower priority can't hold up one of higher priority for long
+/// while holding the lock. Unlocking hands the lock straight to the waiter that waits
+/// longest.
+pub struct Mutex<T> {
+    owner: AtomicPtr<Thread>,
+    waiters: WaitQueue,
+    /// The owner's own policy while waiters boost it, only touched with `waiters` locked
+    base_policy: UnsafeCell<Option<Policy>>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            owner: AtomicPtr::new(ptr::null_mut()),
+            waiters: WaitQueue::new(),
+            base_policy: UnsafeCell::new(None),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        debug_assert!(
+            !percpu::in_interrupt(),
+            "mutex locked in an interrupt handler"
+        );
+        let me = sched::current();
+        if self
+            .owner
+            .compare_exchange(ptr::null_mut(), me, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_slow(me);
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.owner
+            .compare_exchange(
+                ptr::null_mut(),
+                sched::current(),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.owner.load(Ordering::Relaxed).is_null()
+    }
+
+    fn lock_slow(&self, me: *mut Thread) {
+        assert!(
+            self.owner.load(Ordering::Relaxed) != me,
+            "mutex locked recursively"
+        );
+        let irqs_enabled = cpu::disable_interrupts();
+        let mut waiter = Waiter::new();
+        let waiter = &raw mut waiter;
+        let mut waiters = self.waiters.lock();
+        loop {
+            // an unlock hands the lock over while we sleep
+            let owner = match self.owner.compare_exchange(
+                ptr::null_mut(),
+                me,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(owner) if owner == me => break,
+                Err(owner) => owner,
+            };
+            if !unsafe { &*waiter }.is_queued() {
+                waiters.push(waiter);
+            }
+            // the owner can't unlock, let alone exit, while we hold the queue lock
+            self.boost(unsafe { &*owner }, thread::current().sched.policy());
+            waiters = self.waiters.sleep(waiters);
+        }
+        waiters.remove(waiter);
+        drop(waiters);
+        if irqs_enabled {
+            cpu::enable_interrupts();
+        }
+    }
+
+    /// Lends `policy` to the owner if it ranks higher. The queue has to be locked.
+    fn boost(&self, owner: &Thread, policy: Policy) {
+        let current = owner.sched.policy();
+        if !sched::outranks(policy, current) {
+            return;
+        }
+        unsafe { &mut *self.base_policy.get() }.get_or_insert(current);
+        sched::set_policy(owner, policy);
+    }
+
+    fn unlock(&self) {
+        let base = {
+            let mut waiters = self.waiters.lock();
+            let base = unsafe { &mut *self.base_policy.get() }.take();
+            match waiters.wake_one() {
+                Some(next) => {
+                    self.owner.store(next, Ordering::Release);
+                    if let Some(policy) = waiters.top_policy() {
+                        self.boost(unsafe { &*next }, policy);
+                    }
+                }
+                None => self.owner.store(ptr::null_mut(), Ordering::Release),
+            }
+            base
+        };
+        if let Some(policy) = base {
+            sched::set_policy(thread::current(), policy);
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// The mutex the guard locks, for a condition variable to unlock and relock it
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
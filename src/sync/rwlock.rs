@@ -0,0 +1,138 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::percpu;
+
+use super::lockdep::{self, LockInfo};
+
+const WRITER: usize = 1 << (usize::BITS - 1);
+/// Set by a waiting writer to keep new readers out, so writers don't starve
+const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+const READERS: usize = !(WRITER | WRITER_WAITING);
+
+/// A reader-writer spinlock preferring writers. Keeps preemption off while held.
+///
+/// Only the writer is tracked by the debug checks, so a hart taking the read lock twice
+/// while a writer waits deadlocks silently.
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    info: LockInfo,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            info: LockInfo::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        percpu::preempt_disable();
+        let addr = lockdep::addr(self);
+        self.info.check_recursion(addr);
+
+        let mut spins = 0;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | WRITER_WAITING) == 0
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return RwLockReadGuard { lock: self };
+            }
+            self.info.spinning(addr, &mut spins);
+            spin_loop();
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        percpu::preempt_disable();
+        let addr = lockdep::addr(self);
+        self.info.check_recursion(addr);
+
+        let mut spins = 0;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | READERS) == 0 {
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwLockWriteGuard {
+                        lock: self,
+                        prev_level: self.info.acquired(addr),
+                    };
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            self.info.spinning(addr, &mut spins);
+            spin_loop();
+        }
+    }
+
+    /// The hart holding the write lock, if any
+    pub fn writer(&self) -> Option<usize> {
+        self.info.holder()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+        percpu::preempt_enable();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    prev_level: u8,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.info.released(self.prev_level);
+        // clears WRITER_WAITING as well, other waiting writers set it again
+        self.lock.state.store(0, Ordering::Release);
+        percpu::preempt_enable();
+    }
+}
@@ -0,0 +1,223 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{cpu, percpu};
+
+use super::lockdep::{self, LockInfo};
+
+/// The lock word and debug state shared by [`SpinLock`] and [`IrqSpinLock`]
+struct RawSpinLock {
+    locked: AtomicBool,
+    info: LockInfo,
+}
+
+impl RawSpinLock {
+    const fn new(level: u8) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            info: LockInfo::new(level),
+        }
+    }
+
+    fn lock(&self) -> u8 {
+        let addr = lockdep::addr(self);
+        self.info.check_recursion(addr);
+        let mut spins = 0;
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                self.info.spinning(addr, &mut spins);
+                spin_loop();
+            }
+        }
+        self.info.acquired(addr)
+    }
+
+    fn try_lock(&self) -> Option<u8> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| self.info.acquired(lockdep::addr(self)))
+    }
+
+    fn unlock(&self, prev_level: u8) {
+        self.info.released(prev_level);
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A simple test-and-set spinlock. Preemption stays off while it is held, but interrupts
+/// don't, so data an interrupt handler touches needs an [`IrqSpinLock`].
+pub struct SpinLock<T> {
+    raw: RawSpinLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self::with_level(data, 0)
+    }
+
+    /// A lock that has to be taken after every lock of a lower level, see `lockdep`.
+    pub const fn with_level(data: T, level: u8) -> Self {
+        Self {
+            raw: RawSpinLock::new(level),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        percpu::preempt_disable();
+        let prev_level = self.raw.lock();
+        SpinLockGuard {
+            lock: self,
+            prev_level,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        percpu::preempt_disable();
+        match self.raw.try_lock() {
+            Some(prev_level) => Some(SpinLockGuard {
+                lock: self,
+                prev_level,
+            }),
+            None => {
+                percpu::preempt_enable();
+                None
+            }
+        }
+    }
+
+    /// The hart holding the lock, if any
+    pub fn holder(&self) -> Option<usize> {
+        self.raw.info.holder()
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    prev_level: u8,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock(self.prev_level);
+        percpu::preempt_enable();
+    }
+}
+
+/// A spinlock that also disables interrupts on the local hart while held, and restores
+/// the previous state when released. Needed for anything an interrupt handler locks.
+pub struct IrqSpinLock<T> {
+    raw: RawSpinLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self::with_level(data, 0)
+    }
+
+    /// A lock that has to be taken after every lock of a lower level, see `lockdep`.
+    pub const fn with_level(data: T, level: u8) -> Self {
+        Self {
+            raw: RawSpinLock::new(level),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let irqs_enabled = cpu::disable_interrupts();
+        let prev_level = self.raw.lock();
+        IrqSpinLockGuard {
+            lock: self,
+            prev_level,
+            irqs_enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let irqs_enabled = cpu::disable_interrupts();
+        match self.raw.try_lock() {
+            Some(prev_level) => Some(IrqSpinLockGuard {
+                lock: self,
+                prev_level,
+                irqs_enabled,
+                _not_send: PhantomData,
+            }),
+            None => {
+                if irqs_enabled {
+                    cpu::enable_interrupts();
+                }
+                None
+            }
+        }
+    }
+
+    /// The hart holding the lock, if any
+    pub fn holder(&self) -> Option<usize> {
+        self.raw.info.holder()
+    }
+}
+
+/// Must be dropped on the hart that took the lock, since it restores that hart's
+/// interrupt state.
+pub struct IrqSpinLockGuard<'a, T> {
+    lock: &'a IrqSpinLock<T>,
+    prev_level: u8,
+    irqs_enabled: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock(self.prev_level);
+        if self.irqs_enabled {
+            cpu::enable_interrupts();
+        }
+    }
+}
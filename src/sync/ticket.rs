@@ -0,0 +1,117 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::percpu;
+
+use super::lockdep::{self, LockInfo};
+
+/// A fair spinlock: harts get the lock in the order they asked for it.
+/// Like [`SpinLock`](super::SpinLock), it keeps preemption but not interrupts off.
+pub struct TicketLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    info: LockInfo,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+unsafe impl<T: Send> Send for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self::with_level(data, 0)
+    }
+
+    /// A lock that has to be taken after every lock of a lower level, see `lockdep`.
+    pub const fn with_level(data: T, level: u8) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            info: LockInfo::new(level),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        percpu::preempt_disable();
+        let addr = lockdep::addr(self);
+        self.info.check_recursion(addr);
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0;
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            self.info.spinning(addr, &mut spins);
+            spin_loop();
+        }
+
+        TicketLockGuard {
+            lock: self,
+            prev_level: self.info.acquired(addr),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        percpu::preempt_disable();
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        if self
+            .next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            percpu::preempt_enable();
+            return None;
+        }
+
+        Some(TicketLockGuard {
+            lock: self,
+            prev_level: self.info.acquired(lockdep::addr(self)),
+        })
+    }
+
+    /// The hart holding the lock, if any
+    pub fn holder(&self) -> Option<usize> {
+        self.info.holder()
+    }
+}
+
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+    prev_level: u8,
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.info.released(self.prev_level);
+        // only the holder ever changes now_serving
+        let next = self
+            .lock
+            .now_serving
+            .load(Ordering::Relaxed)
+            .wrapping_add(1);
+        self.lock.now_serving.store(next, Ordering::Release);
+        percpu::preempt_enable();
+    }
+}
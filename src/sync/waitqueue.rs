@@ -0,0 +1,217 @@
+use core::ptr;
+
+use crate::{
+    cpu, percpu, sched,
+    thread::{self, State, Thread},
+};
+
+use super::{IrqSpinLock, IrqSpinLockGuard};
+
+/// A sleeping thread, linked into the queue from its own stack
+pub(super) struct Waiter {
+    thread: *mut Thread,
+    next: *mut Waiter,
+    /// Cleared by whoever takes the waiter out of the queue to wake it
+    queued: bool,
+}
+
+impl Waiter {
+    pub(super) fn new() -> Self {
+        Self {
+            thread: sched::current(),
+            next: ptr::null_mut(),
+            queued: false,
+        }
+    }
+
+    pub(super) fn is_queued(&self) -> bool {
+        self.queued
+    }
+}
+
+/// The waiters of a queue, first come first served
+pub(super) struct Waiters {
+    head: *mut Waiter,
+    tail: *mut Waiter,
+}
+
+unsafe impl Send for Waiters {}
+
+impl Waiters {
+    pub(super) fn push(&mut self, waiter: *mut Waiter) {
+        let w = unsafe { &mut *waiter };
+        w.next = ptr::null_mut();
+        w.queued = true;
+        match unsafe { self.tail.as_mut() } {
+            Some(tail) => tail.next = waiter,
+            None => self.head = waiter,
+        }
+        self.tail = waiter;
+    }
+
+    /// Takes the first waiter out of the queue and returns its thread.
+    fn pop(&mut self) -> Option<*mut Thread> {
+        let w = unsafe { self.head.as_mut() }?;
+        self.head = w.next;
+        if self.head.is_null() {
+            self.tail = ptr::null_mut();
+        }
+        w.queued = false;
+        Some(w.thread)
+    }
+
+    /// Takes a waiter out of the queue if it is still in it.
+    pub(super) fn remove(&mut self, waiter: *mut Waiter) {
+        if !unsafe { &*waiter }.is_queued() {
+            return;
+        }
+        let mut prev: *mut Waiter = ptr::null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let next = unsafe { &*cur }.next;
+            if cur == waiter {
+                match unsafe { prev.as_mut() } {
+                    Some(p) => p.next = next,
+                    None => self.head = next,
+                }
+                if self.tail == waiter {
+                    self.tail = prev;
+                }
+                unsafe { &mut *waiter }.queued = false;
+                return;
+            }
+            prev = cur;
+            cur = next;
+        }
+    }
+
+    /// Wakes the first waiter. Returns its thread, or `None` if the queue was empty.
+    pub(super) fn wake_one(&mut self) -> Option<*mut Thread> {
+        let thread = self.pop()?;
+        sched::wake(thread);
+        Some(thread)
+    }
+
+    pub(super) fn wake_all(&mut self) -> usize {
+        let mut woken = 0;
+        while self.wake_one().is_some() {
+            woken += 1;
+        }
+        woken
+    }
+
+    /// The most important policy among the waiting threads
+    pub(super) fn top_policy(&self) -> Option<sched::Policy> {
+        let mut top = None;
+        let mut cur = self.head;
+        while let Some(w) = unsafe { cur.as_ref() } {
+            let policy = unsafe { &*w.thread }.sched.policy();
+            if top.is_none_or(|top| sched::outranks(policy, top)) {
+                top = Some(policy);
+            }
+            cur = w.next;
+        }
+        top
+    }
+}
+
+/// Threads sleeping until something happens.
+///
+/// Waiting blocks the calling thread, so it must not hold a spinlock or run in an
+/// interrupt handler. Waking doesn't block and works from interrupt handlers.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: IrqSpinLock::new(Waiters {
+                head: ptr::null_mut(),
+                tail: ptr::null_mut(),
+            }),
+        }
+    }
+
+    pub(super) fn lock(&self) -> IrqSpinLockGuard<'_, Waiters> {
+        self.waiters.lock()
+    }
+
+    /// Blocks the calling thread and returns the relocked queue once something woke it.
+    /// Interrupts have to be off since before `waiters` was locked, an interrupt
+    /// preempting the thread while it is marked blocked would lose it.
+    pub(super) fn sleep<'a>(
+        &'a self,
+        waiters: IrqSpinLockGuard<'a, Waiters>,
+    ) -> IrqSpinLockGuard<'a, Waiters> {
+        debug_assert!(!percpu::in_interrupt(), "sleeping in an interrupt handler");
+        debug_assert!(!cpu::interrupts_enabled());
+        // a waker has to take the queue lock, so it sees the thread blocked
+        thread::current().set_state(State::Blocked);
+        drop(waiters);
+        sched::schedule();
+        self.waiters.lock()
+    }
+
+    /// Sleeps until `waiter` was taken out of the queue by a wakeup.
+    pub(super) fn sleep_until_woken(&self, waiter: *mut Waiter) {
+        let irqs_enabled = cpu::disable_interrupts();
+        let mut waiters = self.lock();
+        while unsafe { &*waiter }.is_queued() {
+            waiters = self.sleep(waiters);
+        }
+        drop(waiters);
+        if irqs_enabled {
+            cpu::enable_interrupts();
+        }
+    }
+
+    /// Sleeps until the next wakeup.
+    pub fn wait(&self) {
+        let mut waiter = Waiter::new();
+        let waiter = &raw mut waiter;
+        self.lock().push(waiter);
+        self.sleep_until_woken(waiter);
+    }
+
+    /// Sleeps until `cond` holds. `cond` is checked with the queue locked, so a wakeup
+    /// after the condition changed can't get lost, and again after every wakeup. It must
+    /// not block.
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        let irqs_enabled = cpu::disable_interrupts();
+        let mut waiter = Waiter::new();
+        let waiter = &raw mut waiter;
+        let mut waiters = self.lock();
+        while !cond() {
+            if !unsafe { &*waiter }.is_queued() {
+                waiters.push(waiter);
+            }
+            waiters = self.sleep(waiters);
+        }
+        waiters.remove(waiter);
+        drop(waiters);
+        if irqs_enabled {
+            cpu::enable_interrupts();
+        }
+    }
+
+    /// Wakes the thread that waits longest. Returns false if nobody waited.
+    pub fn wake_one(&self) -> bool {
+        self.lock().wake_one().is_some()
+    }
+
+    /// Wakes every waiting thread and returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        self.lock().wake_all()
+    }
+
+    pub fn has_waiters(&self) -> bool {
+        !self.lock().head.is_null()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
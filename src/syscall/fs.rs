@@ -0,0 +1,105 @@
+//! File system calls, on the devices in [`process::file`]
+
+use crate::{
+    console, process,
+    process::{file::File, tty},
+    uaccess::{copy_from_user, copy_to_user, strncpy_from_user},
+};
+
+use super::{Errno, PATH_MAX, SysResult};
+
+/// `openat` takes paths relative to this instead of a directory descriptor
+const AT_FDCWD: isize = -100;
+
+/// Bytes copied through the kernel stack at a time
+const CHUNK: usize = 256;
+
+fn file(fd: usize) -> Result<File, Errno> {
+    let process = process::current().ok_or(Errno::BadF)?;
+    process.files.lock().get(fd).ok_or(Errno::BadF)
+}
+
+pub fn openat(dirfd: isize, path: usize, _flags: usize) -> SysResult {
+    if dirfd != AT_FDCWD && path_is_relative(path)? {
+        return Err(Errno::NoEnt);
+    }
+
+    let mut buf = [0u8; PATH_MAX];
+    let len = strncpy_from_user(&mut buf, path)?;
+    if len == buf.len() {
+        return Err(Errno::NameTooLong);
+    }
+    let file = File::open(&buf[..len]).ok_or(Errno::NoEnt)?;
+
+    let process = process::current().ok_or(Errno::BadF)?;
+    process.files.lock().insert(file).ok_or(Errno::MFile)
+}
+
+fn path_is_relative(path: usize) -> Result<bool, Errno> {
+    let mut first = [0];
+    copy_from_user(&mut first, path)?;
+    Ok(first[0] != b'/')
+}
+
+pub fn close(fd: usize) -> SysResult {
+    let process = process::current().ok_or(Errno::BadF)?;
+    process.files.lock().close(fd).map(|_| 0).ok_or(Errno::BadF)
+}
+
+pub fn read(fd: usize, buf: usize, count: usize) -> SysResult {
+    match file(fd)? {
+        File::Null => Ok(0),
+        File::Console => {
+            if count == 0 {
+                return Ok(0);
+            }
+            let mut chunk = [0u8; CHUNK];
+            let len = tty::read(&mut chunk[..count.min(CHUNK)]).ok_or(Errno::Intr)?;
+            copy_to_user(buf, &chunk[..len])?;
+            Ok(len)
+        }
+    }
+}
+
+pub fn write(fd: usize, buf: usize, count: usize) -> SysResult {
+    let file = file(fd)?;
+    let mut chunk = [0u8; CHUNK];
+    let mut done = 0;
+    while done < count {
+        let len = (count - done).min(CHUNK);
+        copy_from_user(&mut chunk[..len], buf + done)?;
+        if file == File::Console {
+            console::lock().write_bytes(&chunk[..len]);
+        }
+        done += len;
+    }
+    Ok(count)
+}
+
+/// `struct iovec`
+#[repr(C)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+pub fn writev(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
+    if iovcnt > 1024 {
+        return Err(Errno::Inval);
+    }
+    let mut total = 0;
+    for i in 0..iovcnt {
+        let mut bytes = [0u8; size_of::<IoVec>()];
+        copy_from_user(&mut bytes, iov + i * size_of::<IoVec>())?;
+        let v: IoVec = unsafe { core::mem::transmute(bytes) };
+        total += write(fd, v.base, v.len)?;
+    }
+    Ok(total)
+}
+
+/// No terminal controls, programs asking whether the console is a terminal learn it
+/// isn't one.
+pub fn ioctl(fd: usize, _request: usize) -> SysResult {
+    file(fd)?;
+    Err(Errno::NoTty)
+}
@@ -0,0 +1,78 @@
+//! Memory management system calls
+
+use crate::{alloc::PAGE_SIZE, page::EntryBits, process};
+
+use super::{Errno, SysResult};
+
+const PROT_READ: usize = 1;
+const PROT_WRITE: usize = 2;
+const PROT_EXEC: usize = 4;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+pub fn brk(addr: usize) -> SysResult {
+    let process = process::current().ok_or(Errno::NoMem)?;
+    let mut space = process.space.lock();
+    if addr == 0 {
+        return Ok(space.brk());
+    }
+    Ok(space.set_brk(addr))
+}
+
+/// Page table bits for `prot`, 0 for `PROT_NONE`. Writable pages have to be readable
+/// in Sv39.
+fn prot_bits(prot: usize) -> i64 {
+    let mut bits = 0;
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        bits |= EntryBits::Read as i64;
+    }
+    if prot & PROT_WRITE != 0 {
+        bits |= EntryBits::Write as i64;
+    }
+    if prot & PROT_EXEC != 0 {
+        bits |= EntryBits::Execute as i64;
+    }
+    bits
+}
+
+/// Only private anonymous mappings, there are no files to map.
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: isize) -> SysResult {
+    if len == 0 || flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return Err(Errno::Inval);
+    }
+    if flags & MAP_ANONYMOUS == 0 || fd != -1 {
+        return Err(Errno::NoSys);
+    }
+    // a length that can't be rounded up to pages is more than there is, as in Linux
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(Errno::NoMem)?;
+
+    let process = process::current().ok_or(Errno::NoMem)?;
+    let fixed = (flags & MAP_FIXED != 0).then_some(addr);
+    process
+        .space
+        .lock()
+        .map_anonymous(fixed, len, prot_bits(prot))
+        .ok_or(if fixed.is_some() {
+            Errno::Inval
+        } else {
+            Errno::NoMem
+        })
+}
+
+pub fn munmap(addr: usize, len: usize) -> SysResult {
+    if len == 0 || !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::Inval);
+    }
+    let process = process::current().ok_or(Errno::Inval)?;
+    let end = addr.checked_add(len).ok_or(Errno::Inval)?;
+    if process.space.lock().unmap(addr, end) {
+        Ok(0)
+    } else {
+        Err(Errno::Inval)
+    }
+}
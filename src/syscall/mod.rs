@@ -0,0 +1,128 @@
+//! System calls, with the numbers and calling convention of Linux on RISC-V.
+//!
+//! The number is in `a7`, the arguments in `a0` to `a5`, and the result goes back in
+//! `a0`, as a negative errno on failure. That's enough for statically linked musl
+//! programs to run unmodified, as long as they stick to the calls implemented here.
+
+use core::sync::atomic::Ordering;
+
+use crate::{debug, percpu, process, trap::TrapFrame};
+
+mod fs;
+mod mm;
+mod proc;
+mod signal;
+mod time;
+
+/// System call numbers
+pub mod nr {
+    pub const IOCTL: usize = 29;
+    pub const OPENAT: usize = 56;
+    pub const CLOSE: usize = 57;
+    pub const READ: usize = 63;
+    pub const WRITE: usize = 64;
+    pub const WRITEV: usize = 66;
+    pub const EXIT: usize = 93;
+    pub const EXIT_GROUP: usize = 94;
+    pub const SET_TID_ADDRESS: usize = 96;
+    pub const NANOSLEEP: usize = 101;
+    pub const CLOCK_GETTIME: usize = 113;
+    pub const SCHED_YIELD: usize = 124;
+    pub const KILL: usize = 129;
+    pub const TKILL: usize = 130;
+    pub const TGKILL: usize = 131;
+    pub const RT_SIGACTION: usize = 134;
+    pub const RT_SIGPROCMASK: usize = 135;
+    pub const RT_SIGRETURN: usize = 139;
+    pub const GETPID: usize = 172;
+    pub const GETPPID: usize = 173;
+    pub const BRK: usize = 214;
+    pub const MUNMAP: usize = 215;
+    pub const CLONE: usize = 220;
+    pub const EXECVE: usize = 221;
+    pub const MMAP: usize = 222;
+    pub const WAIT4: usize = 260;
+}
+
+#[repr(isize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Errno {
+    Perm = 1,
+    NoEnt = 2,
+    Srch = 3,
+    Intr = 4,
+    Io = 5,
+    TooBig = 7,
+    NoExec = 8,
+    BadF = 9,
+    Child = 10,
+    Again = 11,
+    NoMem = 12,
+    Fault = 14,
+    Inval = 22,
+    MFile = 24,
+    NoTty = 25,
+    NameTooLong = 36,
+    NoSys = 38,
+}
+
+pub type SysResult = Result<usize, Errno>;
+
+/// The longest path system calls take, with the terminating NUL
+const PATH_MAX: usize = 256;
+
+/// Handles the `ecall` in `frame` and puts the result in `a0`.
+pub fn dispatch(frame: &mut TrapFrame) {
+    percpu::this_cpu_raw()
+        .stats
+        .syscalls
+        .fetch_add(1, Ordering::Relaxed);
+
+    // return after the ecall, unless the call changes where the program continues
+    frame.sepc += 4;
+
+    let nr = frame.regs[17];
+    let args = [
+        frame.regs[10],
+        frame.regs[11],
+        frame.regs[12],
+        frame.regs[13],
+        frame.regs[14],
+        frame.regs[15],
+    ];
+    let result = match nr {
+        nr::IOCTL => fs::ioctl(args[0], args[1]),
+        nr::OPENAT => fs::openat(args[0] as isize, args[1], args[2]),
+        nr::CLOSE => fs::close(args[0]),
+        nr::READ => fs::read(args[0], args[1], args[2]),
+        nr::WRITE => fs::write(args[0], args[1], args[2]),
+        nr::WRITEV => fs::writev(args[0], args[1], args[2]),
+        nr::EXIT | nr::EXIT_GROUP => process::exit(args[0] as i32),
+        nr::SET_TID_ADDRESS => proc::getpid(),
+        nr::NANOSLEEP => time::nanosleep(args[0], args[1]),
+        nr::CLOCK_GETTIME => time::clock_gettime(args[0], args[1]),
+        nr::SCHED_YIELD => proc::sched_yield(),
+        nr::KILL | nr::TKILL => signal::kill(args[0] as isize, args[1]),
+        nr::TGKILL => signal::tgkill(args[0] as isize, args[1] as isize, args[2]),
+        nr::RT_SIGACTION => signal::rt_sigaction(args[0], args[1], args[2], args[3]),
+        nr::RT_SIGPROCMASK => signal::rt_sigprocmask(args[0], args[1], args[2], args[3]),
+        nr::RT_SIGRETURN => signal::rt_sigreturn(frame),
+        nr::GETPID => proc::getpid(),
+        nr::GETPPID => proc::getppid(),
+        nr::BRK => mm::brk(args[0]),
+        nr::MUNMAP => mm::munmap(args[0], args[1]),
+        nr::CLONE => proc::clone(frame, args[0], args[1], args[2], args[3]),
+        nr::EXECVE => proc::execve(frame, args[0], args[1], args[2]),
+        nr::MMAP => mm::mmap(args[0], args[1], args[2], args[3], args[4] as isize),
+        nr::WAIT4 => proc::wait4(args[0] as isize, args[1], args[2]),
+        _ => {
+            debug!("unknown system call {} at 0x{:x}", nr, frame.sepc - 4);
+            Err(Errno::NoSys)
+        }
+    };
+
+    frame.regs[10] = match result {
+        Ok(value) => value,
+        Err(errno) => -(errno as isize) as usize,
+    };
+}
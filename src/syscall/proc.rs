@@ -0,0 +1,180 @@
+//! Process related system calls
+
+use crate::{
+    alloc::{PAGE_SIZE, dealloc, zalloc},
+    process::{self, WCONTINUED, WNOHANG, WUNTRACED, loader::LoadError, programs},
+    thread,
+    trap::TrapFrame,
+    uaccess::{copy_from_user, copy_to_user, strncpy_from_user},
+};
+
+use super::{Errno, PATH_MAX, SysResult};
+
+/// `clone` flags
+const CLONE_VM: usize = 0x100;
+const CLONE_VFORK: usize = 0x4000;
+const CLONE_THREAD: usize = 0x10000;
+const CLONE_SETTLS: usize = 0x80000;
+const CLONE_PARENT_SETTID: usize = 0x100000;
+
+/// Arguments or environment strings `execve` takes at most
+const MAX_ARGS: usize = 64;
+/// Space for the strings of one list
+const ARG_PAGES: usize = 4;
+
+pub fn getpid() -> SysResult {
+    process::current()
+        .map(process::Process::pid)
+        .ok_or(Errno::Srch)
+}
+
+pub fn getppid() -> SysResult {
+    process::current()
+        .map(process::Process::parent)
+        .ok_or(Errno::Srch)
+}
+
+pub fn sched_yield() -> SysResult {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// Only creates processes: threads sharing the address space aren't supported. A
+/// `vfork` gets a copy of the memory, which its caller can't tell apart.
+pub fn clone(
+    frame: &TrapFrame,
+    flags: usize,
+    stack: usize,
+    parent_tid: usize,
+    tls: usize,
+) -> SysResult {
+    if flags & CLONE_THREAD != 0 || (flags & CLONE_VM != 0 && flags & CLONE_VFORK == 0) {
+        return Err(Errno::Inval);
+    }
+
+    // the child returns from the same call, with 0
+    let mut start = *frame;
+    start.regs[10] = 0;
+    if stack != 0 {
+        start.regs[2] = stack;
+    }
+    if flags & CLONE_SETTLS != 0 {
+        start.regs[4] = tls;
+    }
+    let pid = process::fork(start).ok_or(Errno::NoMem)?;
+
+    if flags & CLONE_PARENT_SETTID != 0 {
+        copy_to_user(parent_tid, &(pid as u32).to_le_bytes())?;
+    }
+    Ok(pid)
+}
+
+/// The strings of a NULL terminated `argv` or `envp` array, copied into the kernel
+struct StringList {
+    buf: *mut u8,
+    used: usize,
+    /// Offset and length of each string in `buf`
+    strings: [(usize, usize); MAX_ARGS],
+    count: usize,
+}
+
+impl StringList {
+    fn read(array: usize) -> Result<Self, Errno> {
+        let buf = zalloc(ARG_PAGES);
+        if buf.is_null() {
+            return Err(Errno::NoMem);
+        }
+        let mut list = Self {
+            buf,
+            used: 0,
+            strings: [(0, 0); MAX_ARGS],
+            count: 0,
+        };
+        // a NULL array is an empty list
+        if array == 0 {
+            return Ok(list);
+        }
+
+        loop {
+            let mut ptr = [0u8; size_of::<usize>()];
+            copy_from_user(&mut ptr, array + list.count * size_of::<usize>())?;
+            let ptr = usize::from_le_bytes(ptr);
+            if ptr == 0 {
+                return Ok(list);
+            }
+            if list.count == MAX_ARGS {
+                return Err(Errno::TooBig);
+            }
+
+            let space = unsafe {
+                core::slice::from_raw_parts_mut(
+                    buf.add(list.used),
+                    ARG_PAGES * PAGE_SIZE - list.used,
+                )
+            };
+            let len = strncpy_from_user(space, ptr)?;
+            if len == space.len() {
+                return Err(Errno::TooBig);
+            }
+            list.strings[list.count] = (list.used, len);
+            list.count += 1;
+            list.used += len + 1;
+        }
+    }
+
+    fn strings(&self) -> [&[u8]; MAX_ARGS] {
+        let mut strings: [&[u8]; MAX_ARGS] = [&[]; MAX_ARGS];
+        for (s, &(offset, len)) in strings.iter_mut().zip(&self.strings[..self.count]) {
+            *s = unsafe { core::slice::from_raw_parts(self.buf.add(offset), len) };
+        }
+        strings
+    }
+}
+
+impl Drop for StringList {
+    fn drop(&mut self) {
+        dealloc(self.buf);
+    }
+}
+
+pub fn execve(frame: &mut TrapFrame, path: usize, argv: usize, envp: usize) -> SysResult {
+    let mut name = [0u8; PATH_MAX];
+    let len = strncpy_from_user(&mut name, path)?;
+    if len == name.len() {
+        return Err(Errno::NameTooLong);
+    }
+    let data = programs::find(&name[..len]).ok_or(Errno::NoEnt)?;
+
+    let args = StringList::read(argv)?;
+    let env = StringList::read(envp)?;
+    let (arg_strings, env_strings) = (args.strings(), env.strings());
+    process::exec(
+        frame,
+        data,
+        &arg_strings[..args.count],
+        &env_strings[..env.count],
+    )
+    .map_err(|e| match e {
+        LoadError::NoMemory => Errno::NoMem,
+        LoadError::TooManyArgs => Errno::TooBig,
+        LoadError::Elf(_) | LoadError::Interpreter | LoadError::BadSegment => Errno::NoExec,
+    })?;
+    // the new program starts with a0 = 0
+    Ok(0)
+}
+
+/// Resource usage isn't tracked, so there is no `rusage` argument.
+pub fn wait4(pid: isize, wstatus: usize, options: usize) -> SysResult {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+        return Err(Errno::Inval);
+    }
+    match process::wait(pid, options)? {
+        Some((child, status)) => {
+            if wstatus != 0 {
+                copy_to_user(wstatus, &(status as u32).to_le_bytes())?;
+            }
+            Ok(child)
+        }
+        None => Ok(0),
+    }
+}
@@ -0,0 +1,90 @@
+//! Signal system calls, on top of [`process::signal`]
+
+use crate::{
+    process::{
+        self,
+        signal::{self, NSIG, SIGKILL, SIGSTOP, SigAction},
+    },
+    trap::TrapFrame,
+    uaccess::{copy_from_user, copy_to_user},
+};
+
+use super::{Errno, SysResult};
+
+/// `rt_sigprocmask` operations
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// The only `sigsetsize` there is, 64 signals
+const SIGSET_SIZE: usize = size_of::<u64>();
+
+fn read_mask(addr: usize) -> Result<u64, Errno> {
+    let mut bytes = [0; SIGSET_SIZE];
+    copy_from_user(&mut bytes, addr)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn kill(pid: isize, sig: usize) -> SysResult {
+    signal::kill(pid, sig).map(|_| 0)
+}
+
+/// Processes have a single thread whose tid is the pid.
+pub fn tgkill(tgid: isize, tid: isize, sig: usize) -> SysResult {
+    if tgid != tid {
+        return Err(Errno::Srch);
+    }
+    kill(tid, sig)
+}
+
+pub fn rt_sigaction(sig: usize, act: usize, oldact: usize, sigsetsize: usize) -> SysResult {
+    if sigsetsize != SIGSET_SIZE || sig == 0 || sig > NSIG {
+        return Err(Errno::Inval);
+    }
+    let process = process::current().ok_or(Errno::Srch)?;
+
+    let old = if act != 0 {
+        if sig == SIGKILL || sig == SIGSTOP {
+            return Err(Errno::Inval);
+        }
+        let mut bytes = [0; size_of::<SigAction>()];
+        copy_from_user(&mut bytes, act)?;
+        let action: SigAction = unsafe { core::mem::transmute(bytes) };
+        process.signals.set_action(sig, action)
+    } else {
+        process.signals.action(sig)
+    };
+
+    if oldact != 0 {
+        let bytes: [u8; size_of::<SigAction>()] = unsafe { core::mem::transmute(old) };
+        copy_to_user(oldact, &bytes)?;
+    }
+    Ok(0)
+}
+
+pub fn rt_sigprocmask(how: usize, set: usize, oldset: usize, sigsetsize: usize) -> SysResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::Inval);
+    }
+    let process = process::current().ok_or(Errno::Srch)?;
+    let old = process.signals.blocked();
+
+    if set != 0 {
+        let mask = read_mask(set)?;
+        let new = match how {
+            SIG_BLOCK => old | mask,
+            SIG_UNBLOCK => old & !mask,
+            SIG_SETMASK => mask,
+            _ => return Err(Errno::Inval),
+        };
+        process.signals.set_blocked(new);
+    }
+    if oldset != 0 {
+        copy_to_user(oldset, &old.to_le_bytes())?;
+    }
+    Ok(0)
+}
+
+pub fn rt_sigreturn(frame: &mut TrapFrame) -> SysResult {
+    signal::sigreturn(frame)
+}
@@ -0,0 +1,74 @@
+//! Clocks and sleeping
+
+use core::time::Duration;
+
+use crate::{
+    thread, time,
+    uaccess::{copy_from_user, copy_to_user},
+};
+
+use super::{Errno, SysResult};
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+/// `struct timespec`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Timespec {
+    sec: i64,
+    nsec: i64,
+}
+
+impl Timespec {
+    fn from_duration(d: Duration) -> Self {
+        Self {
+            sec: d.as_secs() as i64,
+            nsec: d.subsec_nanos() as i64,
+        }
+    }
+
+    fn to_duration(self) -> Option<Duration> {
+        if self.sec < 0 || !(0..1_000_000_000).contains(&self.nsec) {
+            return None;
+        }
+        Some(Duration::new(self.sec as u64, self.nsec as u32))
+    }
+
+    fn read(addr: usize) -> Result<Self, Errno> {
+        let mut bytes = [0; size_of::<Self>()];
+        copy_from_user(&mut bytes, addr)?;
+        Ok(unsafe { core::mem::transmute::<[u8; 16], Self>(bytes) })
+    }
+
+    fn write(self, addr: usize) -> Result<(), Errno> {
+        let bytes: [u8; 16] = unsafe { core::mem::transmute(self) };
+        copy_to_user(addr, &bytes)
+    }
+}
+
+/// Every clock counts from boot, there is no real-time clock to read the date from.
+pub fn clock_gettime(clock: usize, tp: usize) -> SysResult {
+    match clock {
+        CLOCK_REALTIME
+        | CLOCK_MONOTONIC
+        | CLOCK_MONOTONIC_RAW
+        | CLOCK_REALTIME_COARSE
+        | CLOCK_MONOTONIC_COARSE
+        | CLOCK_BOOTTIME => {
+            Timespec::from_duration(time::uptime()).write(tp)?;
+            Ok(0)
+        }
+        _ => Err(Errno::Inval),
+    }
+}
+
+pub fn nanosleep(req: usize, _rem: usize) -> SysResult {
+    let duration = Timespec::read(req)?.to_duration().ok_or(Errno::Inval)?;
+    thread::sleep(duration);
+    Ok(0)
+}
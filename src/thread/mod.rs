@@ -0,0 +1,296 @@
+//! Kernel threads.
+//!
+//! Every thread has a stack of its own. Which thread runs when is up to the
+//! [`sched`](crate::sched) module; each hart's boot flow is adopted as its idle thread,
+//! which runs whenever the hart has nothing else to do.
+
+use core::{
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    alloc::{PAGE_SIZE, dealloc, zalloc},
+    process::Process,
+    sched::{self, Policy, RunLink, SchedEntity},
+    sync::Completion,
+    time,
+};
+
+mod switch;
+
+pub(crate) use switch::{Context, switch_to};
+
+/// 16 KiB of stack for each thread
+const STACK_PAGES: usize = 4;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    Ready = 0,
+    Running = 1,
+    Blocked = 2,
+    Exited = 3,
+}
+
+impl State {
+    fn from_u8(val: u8) -> Self {
+        match val {
+            0 => State::Ready,
+            1 => State::Running,
+            2 => State::Blocked,
+            _ => State::Exited,
+        }
+    }
+}
+
+/// Accounting kept by the scheduler
+#[derive(Default)]
+pub struct ThreadStats {
+    /// Time spent running in timebase ticks, without the time the hypervisor stole
+    pub(crate) runtime: AtomicU64,
+    pub(crate) switches: AtomicU64,
+    pub(crate) last_hart: AtomicUsize,
+    /// `time` and the hart's steal time in nanoseconds when the thread was switched to
+    pub(crate) switched_in: AtomicU64,
+    pub(crate) steal_in: AtomicU64,
+}
+
+pub struct Thread {
+    pub(crate) context: Context,
+    id: usize,
+    state: AtomicU8,
+    /// Held by the thread until it is switched away from for the last time, and by the
+    /// `JoinHandle`. Whoever drops the last one frees the thread.
+    refs: AtomicU8,
+    /// Null for idle threads, which run on their hart's boot stack
+    stack: *mut u8,
+    entry: fn(usize),
+    arg: usize,
+    pub(crate) stats: ThreadStats,
+    /// Links the thread into a run queue while it is ready
+    pub(crate) link: RunLink,
+    pub(crate) sched: SchedEntity,
+    /// The process the thread runs in U-mode for, null for kernel threads
+    process: AtomicPtr<Process>,
+    /// Completed for good when the thread exits, for `join`
+    exited: Completion,
+}
+
+impl Thread {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn state(&self) -> State {
+        State::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Changes the state to `to` if it is `from`. Returns false if it wasn't.
+    pub(crate) fn transition(&self, from: State, to: State) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stack.is_null()
+    }
+
+    /// Time spent running, not counting time stolen by the hypervisor
+    pub fn runtime(&self) -> Duration {
+        time::ticks_to_duration(self.stats.runtime.load(Ordering::Relaxed))
+    }
+
+    /// How often the thread was switched to
+    pub fn switches(&self) -> u64 {
+        self.stats.switches.load(Ordering::Relaxed)
+    }
+
+    /// The hart the thread last ran on
+    pub fn last_hart(&self) -> usize {
+        self.stats.last_hart.load(Ordering::Relaxed)
+    }
+
+    /// Top of the kernel stack, 0 for idle threads
+    pub fn stack_top(&self) -> usize {
+        if self.stack.is_null() {
+            0
+        } else {
+            self.stack as usize + STACK_PAGES * PAGE_SIZE
+        }
+    }
+
+    pub fn process(&self) -> Option<&'static Process> {
+        unsafe { self.process_ptr().as_ref() }
+    }
+
+    pub(crate) fn process_ptr(&self) -> *mut Process {
+        self.process.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_process(&self, process: *mut Process) {
+        self.process.store(process, Ordering::Release);
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.sched.policy()
+    }
+
+    /// The harts the thread may run on, bit `n` standing for hart `n`
+    pub fn affinity(&self) -> usize {
+        self.sched.affinity()
+    }
+
+    pub(crate) fn release(thread: *mut Thread) {
+        if unsafe { &*thread }.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let stack = unsafe { &*thread }.stack;
+            if !stack.is_null() {
+                dealloc(stack);
+            }
+            dealloc(thread as *mut u8);
+        }
+    }
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn alloc_thread(stack: *mut u8, entry: fn(usize), arg: usize, refs: u8) -> *mut Thread {
+    let thread = zalloc(size_of::<Thread>().div_ceil(PAGE_SIZE)) as *mut Thread;
+    if !thread.is_null() {
+        unsafe {
+            thread.write(Thread {
+                context: Context::default(),
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                state: AtomicU8::new(State::Ready as u8),
+                refs: AtomicU8::new(refs),
+                stack,
+                entry,
+                arg,
+                stats: ThreadStats::default(),
+                link: RunLink::new(),
+                sched: SchedEntity::new(),
+                process: AtomicPtr::new(core::ptr::null_mut()),
+                exited: Completion::new(),
+            });
+        }
+    }
+    thread
+}
+
+/// Adopts the calling flow of control as the hart's idle thread. Every hart has to call
+/// this before it runs any threads.
+pub fn init_hart() {
+    let idle = alloc_thread(core::ptr::null_mut(), |_| {}, 0, 1);
+    assert!(!idle.is_null(), "out of memory for an idle thread");
+    sched::set_idle(idle);
+}
+
+/// The running thread
+pub fn current() -> &'static Thread {
+    unsafe { sched::current().as_ref() }
+        .expect("no thread running, thread::init_hart wasn't called")
+}
+
+/// Owns a thread that hasn't been joined yet. Dropping it detaches the thread.
+pub struct JoinHandle {
+    thread: *mut Thread,
+}
+
+unsafe impl Send for JoinHandle {}
+
+impl JoinHandle {
+    pub fn id(&self) -> usize {
+        unsafe { &*self.thread }.id
+    }
+
+    pub fn thread(&self) -> &Thread {
+        unsafe { &*self.thread }
+    }
+
+    /// Sleeps until the thread exited.
+    pub fn join(self) {
+        self.thread().exited.wait();
+        // drop releases the thread
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        Thread::release(self.thread);
+    }
+}
+
+/// Starts a fair thread running `entry(arg)` on any hart. Returns `None` if there is no
+/// memory for it.
+pub fn spawn(entry: fn(usize), arg: usize) -> Option<JoinHandle> {
+    spawn_with(entry, arg, Policy::default(), usize::MAX)
+}
+
+/// Starts a thread running `entry(arg)` with `policy`, on the harts in `affinity`.
+pub fn spawn_with(
+    entry: fn(usize),
+    arg: usize,
+    policy: Policy,
+    affinity: usize,
+) -> Option<JoinHandle> {
+    let stack = zalloc(STACK_PAGES);
+    if stack.is_null() {
+        return None;
+    }
+    let thread = alloc_thread(stack, entry, arg, 2);
+    if thread.is_null() {
+        dealloc(stack);
+        return None;
+    }
+
+    let t = unsafe { &mut *thread };
+    t.context.ra = switch::thread_trampoline as *const () as usize;
+    t.context.sp = t.stack_top();
+    t.context.s[0] = thread as usize;
+    t.stats
+        .last_hart
+        .store(crate::cpu::hart_id(), Ordering::Relaxed);
+    sched::set_policy(t, policy);
+    sched::set_affinity(t, affinity);
+
+    sched::start(thread);
+    Some(JoinHandle { thread })
+}
+
+/// Lets other threads run.
+pub fn yield_now() {
+    current().set_state(State::Ready);
+    sched::schedule();
+}
+
+/// Sleeps for at least `duration`.
+pub fn sleep(duration: Duration) {
+    let deadline = time::read_time().saturating_add(time::duration_to_ticks(duration));
+    sched::sleep_until(deadline);
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    let thread = current();
+    assert!(!thread.is_idle(), "the idle thread can't exit");
+    // preempted once it is marked exited, it would be freed and never switched back to
+    crate::cpu::disable_interrupts();
+    thread.exited.complete_all();
+    thread.set_state(State::Exited);
+    sched::schedule();
+    unreachable!("exited thread {} was scheduled again", thread.id);
+}
+
+extern "C" fn thread_start(thread: *mut Thread) -> ! {
+    sched::finish_switch();
+    crate::cpu::enable_interrupts();
+
+    let thread = unsafe { &*thread };
+    (thread.entry)(thread.arg);
+    exit()
+}
@@ -0,0 +1,66 @@
+//! The context switch itself.
+
+use core::arch::naked_asm;
+
+/// The callee-saved registers of a thread that isn't running. Everything else is either
+/// saved by the caller of `switch_to` or per hart, like `tp`.
+#[repr(C)]
+#[derive(Default)]
+pub struct Context {
+    pub ra: usize,
+    pub sp: usize,
+    /// s0 to s11
+    pub s: [usize; 12],
+}
+
+/// Saves the running thread's registers to `prev` and continues the thread `next`
+/// describes. Returns once something switches back to `prev`.
+///
+/// # Safety
+/// `next` must hold the registers of a thread that isn't running, with a valid stack.
+#[unsafe(naked)]
+pub unsafe extern "C" fn switch_to(prev: *mut Context, next: *const Context) {
+    naked_asm!(
+        "sd ra, 0(a0)
+        sd sp, 8(a0)
+        sd s0, 16(a0)
+        sd s1, 24(a0)
+        sd s2, 32(a0)
+        sd s3, 40(a0)
+        sd s4, 48(a0)
+        sd s5, 56(a0)
+        sd s6, 64(a0)
+        sd s7, 72(a0)
+        sd s8, 80(a0)
+        sd s9, 88(a0)
+        sd s10, 96(a0)
+        sd s11, 104(a0)
+
+        ld ra, 0(a1)
+        ld sp, 8(a1)
+        ld s0, 16(a1)
+        ld s1, 24(a1)
+        ld s2, 32(a1)
+        ld s3, 40(a1)
+        ld s4, 48(a1)
+        ld s5, 56(a1)
+        ld s6, 64(a1)
+        ld s7, 72(a1)
+        ld s8, 80(a1)
+        ld s9, 88(a1)
+        ld s10, 96(a1)
+        ld s11, 104(a1)
+        ret"
+    );
+}
+
+/// Where new threads start: the first `switch_to` into them returns here with the thread
+/// in `s0`.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn thread_trampoline() {
+    naked_asm!(
+        "mv a0, s0
+        j {start}",
+        start = sym super::thread_start,
+    );
+}
@@ -0,0 +1,74 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    fdt,
+    sbi::{
+        SbiErrorType, SbiResult,
+        features::{self, Extension},
+        legacy, time,
+    },
+};
+
+/// The timebase of the qemu virt machine, used if the device tree doesn't tell us otherwise.
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+static TIMEBASE_FREQUENCY: AtomicU64 = AtomicU64::new(DEFAULT_TIMEBASE_FREQUENCY);
+
+pub fn init() {
+    let freq = fdt::get()
+        .and_then(|fdt| fdt.find_node("/cpus"))
+        .and_then(|cpus| cpus.property_u32("timebase-frequency"));
+
+    if let Some(freq) = freq {
+        TIMEBASE_FREQUENCY.store(freq as u64, Ordering::Relaxed);
+    }
+}
+
+pub fn timebase_frequency() -> u64 {
+    TIMEBASE_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Reads the `time` CSR
+pub fn read_time() -> u64 {
+    let time: u64;
+    unsafe {
+        asm!("rdtime {}", out(reg) time);
+    }
+    time
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let freq = timebase_frequency();
+    let secs = ticks / freq;
+    let nanos = (ticks % freq) * 1_000_000_000 / freq;
+    Duration::new(secs, nanos as u32)
+}
+
+/// Saturates at `u64::MAX` for durations too long to count in ticks.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let freq = timebase_frequency();
+    duration
+        .as_secs()
+        .saturating_mul(freq)
+        .saturating_add(duration.subsec_nanos() as u64 * freq / 1_000_000_000)
+}
+
+/// Arms the calling hart's timer interrupt for when `time` reaches `deadline`.
+pub fn set_timer(deadline: u64) -> SbiResult<()> {
+    if features::has(Extension::Time) {
+        time::set_timer(deadline)
+    } else if features::has(Extension::LegacySetTimer) {
+        legacy::set_timer(deadline)
+    } else {
+        Err(SbiErrorType::NotSupported.into())
+    }
+}
+
+/// Time since the machine was reset
+pub fn uptime() -> Duration {
+    ticks_to_duration(read_time())
+}
@@ -0,0 +1,40 @@
+//! Handlers for high priority supervisor software events (SSE).
+//!
+//! These run like NMIs, so they only use the lock-free console path.
+
+use crate::{
+    console, debug,
+    sbi::{
+        features::{self, Extension},
+        sse::{self, Event},
+    },
+    trap::TrapFrame,
+};
+
+/// Registers the event handlers on the calling hart. Every hart has to call this.
+pub fn init() {
+    if !features::has(Extension::Sse) {
+        return;
+    }
+
+    for (event, handler) in [
+        (Event::LocalHighPriorityRas, ras_error as sse::Handler),
+        (Event::LocalLowPriorityRas, ras_error),
+        (Event::LocalDoubleTrap, double_trap),
+    ] {
+        if let Err(e) = sse::register_handler(event, handler) {
+            debug!("not handling {:?}: {}", event, e);
+        }
+    }
+}
+
+fn ras_error(event: Event, frame: &mut TrapFrame) {
+    console::force_print(format_args!(
+        "RAS error ({:?}) reported at 0x{:x}\r\n",
+        event, frame.sepc
+    ));
+}
+
+fn double_trap(_event: Event, frame: &mut TrapFrame) {
+    panic!("double trap at 0x{:x}", frame.sepc);
+}
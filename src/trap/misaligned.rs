@@ -0,0 +1,165 @@
+//! Emulation of misaligned loads and stores.
+//!
+//! By default the firmware emulates misaligned accesses itself, slowly and invisibly.
+//! With FWFT we ask for the exceptions to be delegated to us instead.
+
+use crate::{
+    debug,
+    sbi::{
+        features::{self, Extension},
+        fwft::{self, Feature},
+    },
+    trap::TrapFrame,
+};
+
+/// Asks the firmware to delegate misaligned access exceptions. Every hart has to call this.
+pub fn init() {
+    if !features::has(Extension::Fwft) {
+        return;
+    }
+
+    match fwft::set(Feature::MisalignedExceptionDelegation, 1, 0) {
+        Ok(()) => debug!("handling misaligned accesses in the kernel"),
+        Err(e) => debug!("misaligned accesses stay with the firmware: {}", e),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Load {
+        rd: usize,
+        width: usize,
+        signed: bool,
+    },
+    Store {
+        rs2: usize,
+        width: usize,
+    },
+}
+
+/// Decodes the integer loads and stores, returning the access and the instruction length.
+fn decode(insn: u32) -> Option<(Access, usize)> {
+    if insn & 0b11 == 0b11 {
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let rs2 = ((insn >> 20) & 0x1f) as usize;
+        let funct3 = (insn >> 12) & 0b111;
+        let access = match (insn & 0x7f, funct3) {
+            (0x03, 1) => Access::Load {
+                rd,
+                width: 2,
+                signed: true,
+            },
+            (0x03, 2) => Access::Load {
+                rd,
+                width: 4,
+                signed: true,
+            },
+            (0x03, 3) => Access::Load {
+                rd,
+                width: 8,
+                signed: false,
+            },
+            (0x03, 5) => Access::Load {
+                rd,
+                width: 2,
+                signed: false,
+            },
+            (0x03, 6) => Access::Load {
+                rd,
+                width: 4,
+                signed: false,
+            },
+            (0x23, 1) => Access::Store { rs2, width: 2 },
+            (0x23, 2) => Access::Store { rs2, width: 4 },
+            (0x23, 3) => Access::Store { rs2, width: 8 },
+            _ => return None,
+        };
+        return Some((access, 4));
+    }
+
+    let insn = insn & 0xffff;
+    let funct3 = insn >> 13;
+    // rd' and rs2' of the CL/CS formats address x8 to x15
+    let reg_prime = (8 + ((insn >> 2) & 0b111)) as usize;
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let rs2 = ((insn >> 2) & 0x1f) as usize;
+    let access = match (insn & 0b11, funct3) {
+        (0b00, 0b010) => Access::Load {
+            rd: reg_prime,
+            width: 4,
+            signed: true,
+        },
+        (0b00, 0b011) => Access::Load {
+            rd: reg_prime,
+            width: 8,
+            signed: false,
+        },
+        (0b00, 0b110) => Access::Store {
+            rs2: reg_prime,
+            width: 4,
+        },
+        (0b00, 0b111) => Access::Store {
+            rs2: reg_prime,
+            width: 8,
+        },
+        (0b10, 0b010) => Access::Load {
+            rd,
+            width: 4,
+            signed: true,
+        },
+        (0b10, 0b011) => Access::Load {
+            rd,
+            width: 8,
+            signed: false,
+        },
+        (0b10, 0b110) => Access::Store { rs2, width: 4 },
+        (0b10, 0b111) => Access::Store { rs2, width: 8 },
+        _ => return None,
+    };
+    Some((access, 2))
+}
+
+/// Fetches the instruction at `pc`, which is only guaranteed to be 2 byte aligned.
+fn fetch(pc: usize) -> u32 {
+    let ptr = pc as *const u16;
+    let low = unsafe { ptr.read_volatile() } as u32;
+    if low & 0b11 != 0b11 {
+        return low;
+    }
+    let high = unsafe { ptr.add(1).read_volatile() } as u32;
+    low | high << 16
+}
+
+/// Emulates the faulting access at `frame.sepc` byte by byte. Returns false if the
+/// instruction isn't one we know how to emulate.
+pub fn handle(frame: &mut TrapFrame, addr: usize) -> bool {
+    let Some((access, len)) = decode(fetch(frame.sepc)) else {
+        return false;
+    };
+
+    match access {
+        Access::Load { rd, width, signed } => {
+            let mut value = 0u64;
+            for i in 0..width {
+                let byte = unsafe { ((addr + i) as *const u8).read_volatile() };
+                value |= (byte as u64) << (8 * i);
+            }
+            if signed && width < 8 {
+                let shift = 64 - 8 * width as u32;
+                value = (((value << shift) as i64) >> shift) as u64;
+            }
+            if rd != 0 {
+                frame.regs[rd] = value as usize;
+            }
+        }
+        Access::Store { rs2, width } => {
+            let value = frame.regs[rs2];
+            for i in 0..width {
+                unsafe { ((addr + i) as *mut u8).write_volatile((value >> (8 * i)) as u8) };
+            }
+        }
+    }
+
+    frame.sepc += len;
+    true
+}
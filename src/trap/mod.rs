@@ -0,0 +1,266 @@
+//! Supervisor trap handling.
+//!
+//! `trap_vector` saves every register into a [`TrapFrame`] on the current stack, or on
+//! the thread's kernel stack when coming from U-mode, and calls [`trap_handler`], which
+//! dispatches on `scause`.
+
+use core::{arch::global_asm, sync::atomic::Ordering};
+
+use crate::{
+    cpu::{self, IRQ_COUNTER_OVERFLOW, IRQ_S_SOFT, IRQ_S_TIMER, SSTATUS_SPP},
+    percpu::{self, SCRATCH_OFFSET, TRAP_STACK_TOP_OFFSET},
+    perf, process, sched, smp, uaccess,
+};
+
+pub mod events;
+pub mod misaligned;
+
+/// The registers of the interrupted context
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TrapFrame {
+    /// x0 to x31, x0 is always 0
+    pub regs: [usize; 32],
+    pub sepc: usize,
+    pub sstatus: usize,
+}
+
+impl TrapFrame {
+    /// Whether the trap came from U-mode
+    pub fn from_user(&self) -> bool {
+        self.sstatus & SSTATUS_SPP == 0
+    }
+}
+
+pub const TRAP_FRAME_SIZE: usize = size_of::<TrapFrame>();
+const _: () = assert!(
+    TRAP_FRAME_SIZE % 16 == 0,
+    "the stack has to stay 16 byte aligned"
+);
+
+const SCAUSE_INTERRUPT: usize = 1 << (usize::BITS - 1);
+
+pub const EXC_INSTRUCTION_MISALIGNED: usize = 0;
+pub const EXC_ILLEGAL_INSTRUCTION: usize = 2;
+pub const EXC_BREAKPOINT: usize = 3;
+pub const EXC_LOAD_MISALIGNED: usize = 4;
+pub const EXC_LOAD_ACCESS: usize = 5;
+pub const EXC_STORE_MISALIGNED: usize = 6;
+pub const EXC_STORE_ACCESS: usize = 7;
+pub const EXC_ECALL_U: usize = 8;
+pub const EXC_INSTRUCTION_PAGE_FAULT: usize = 12;
+pub const EXC_LOAD_PAGE_FAULT: usize = 13;
+pub const EXC_STORE_PAGE_FAULT: usize = 15;
+
+unsafe extern "C" {
+    fn trap_vector();
+}
+
+pub fn init() {
+    cpu::stvec::write(trap_vector as *const () as usize);
+}
+
+// stvec needs the vector to be 4 byte aligned, which naked functions can't guarantee.
+// `sscratch` always holds the hart's `PerCpu`, `tp` does too unless we come from U-mode.
+// Traps from U-mode start on the kernel stack in `PerCpu::trap_stack_top`.
+global_asm!(
+    ".section .text
+.balign 4
+.global trap_vector
+trap_vector:
+        csrrw tp, sscratch, tp
+        sd t0, {scratch}(tp)
+        csrr t0, sstatus
+        andi t0, t0, {spp}
+        bnez t0, 1f
+        ld t0, {trap_stack_top}(tp)
+        j 2f
+1:
+        mv t0, sp
+2:
+        addi t0, t0, -{size}
+        sd sp, 16(t0)
+        mv sp, t0
+        ld t0, {scratch}(tp)
+        sd t0, 40(sp)
+        csrr t0, sscratch
+        sd t0, 32(sp)
+        csrw sscratch, tp
+
+        sd x1, 8(sp)
+        sd x3, 24(sp)
+        sd x6, 48(sp)
+        sd x7, 56(sp)
+        sd x8, 64(sp)
+        sd x9, 72(sp)
+        sd x10, 80(sp)
+        sd x11, 88(sp)
+        sd x12, 96(sp)
+        sd x13, 104(sp)
+        sd x14, 112(sp)
+        sd x15, 120(sp)
+        sd x16, 128(sp)
+        sd x17, 136(sp)
+        sd x18, 144(sp)
+        sd x19, 152(sp)
+        sd x20, 160(sp)
+        sd x21, 168(sp)
+        sd x22, 176(sp)
+        sd x23, 184(sp)
+        sd x24, 192(sp)
+        sd x25, 200(sp)
+        sd x26, 208(sp)
+        sd x27, 216(sp)
+        sd x28, 224(sp)
+        sd x29, 232(sp)
+        sd x30, 240(sp)
+        sd x31, 248(sp)
+        csrr t0, sepc
+        sd t0, 256(sp)
+        csrr t0, sstatus
+        sd t0, 264(sp)
+
+        # the program may have used gp for itself
+        andi t0, t0, {spp}
+        bnez t0, 3f
+        .option push
+        .option norelax
+        la gp, __global_pointer
+        .option pop
+3:
+        mv a0, sp
+        call {handler}
+
+.global trap_return
+trap_return:
+        ld t0, 256(sp)
+        csrw sepc, t0
+        ld t0, 264(sp)
+        csrw sstatus, t0
+        # tp stays in the kernel, a preempted thread may come back on another hart
+        andi t0, t0, {spp}
+        bnez t0, 4f
+        ld x4, 32(sp)
+4:
+        ld x1, 8(sp)
+        ld x3, 24(sp)
+        ld x5, 40(sp)
+        ld x6, 48(sp)
+        ld x7, 56(sp)
+        ld x8, 64(sp)
+        ld x9, 72(sp)
+        ld x10, 80(sp)
+        ld x11, 88(sp)
+        ld x12, 96(sp)
+        ld x13, 104(sp)
+        ld x14, 112(sp)
+        ld x15, 120(sp)
+        ld x16, 128(sp)
+        ld x17, 136(sp)
+        ld x18, 144(sp)
+        ld x19, 152(sp)
+        ld x20, 160(sp)
+        ld x21, 168(sp)
+        ld x22, 176(sp)
+        ld x23, 184(sp)
+        ld x24, 192(sp)
+        ld x25, 200(sp)
+        ld x26, 208(sp)
+        ld x27, 216(sp)
+        ld x28, 224(sp)
+        ld x29, 232(sp)
+        ld x30, 240(sp)
+        ld x31, 248(sp)
+        ld x2, 16(sp)
+        sret",
+    size = const TRAP_FRAME_SIZE,
+    scratch = const SCRATCH_OFFSET,
+    trap_stack_top = const TRAP_STACK_TOP_OFFSET,
+    spp = const SSTATUS_SPP,
+    handler = sym trap_handler,
+);
+
+extern "C" fn trap_handler(frame: &mut TrapFrame) {
+    let scause = cpu::scause::read();
+    let stval = cpu::stval::read();
+
+    if frame.from_user() && scause & SCAUSE_INTERRUPT == 0 {
+        // not an interrupt context, handling the exception may sleep
+        let stats = &percpu::this_cpu_raw().stats;
+        stats.exceptions.fetch_add(1, Ordering::Relaxed);
+        cpu::enable_interrupts();
+        process::handle_exception(frame, scause, stval);
+        cpu::disable_interrupts();
+    } else {
+        percpu::irq_enter();
+        handle(frame, scause, stval);
+        percpu::irq_exit();
+    }
+
+    if frame.from_user() {
+        // signals are delivered on the way back to U-mode
+        cpu::enable_interrupts();
+        process::signal::deliver(frame);
+        cpu::disable_interrupts();
+    }
+    sched::preempt_if_needed();
+}
+
+fn handle(frame: &mut TrapFrame, scause: usize, stval: usize) {
+    let stats = &percpu::this_cpu_raw().stats;
+    if scause & SCAUSE_INTERRUPT != 0 {
+        stats.interrupts.fetch_add(1, Ordering::Relaxed);
+        match scause & !SCAUSE_INTERRUPT {
+            IRQ_S_SOFT => smp::call::handle_ipi(),
+            IRQ_S_TIMER => {
+                sched::tick();
+                process::tty::poll();
+            }
+            IRQ_COUNTER_OVERFLOW => perf::profiler::handle_overflow(frame),
+            code => panic!("unhandled interrupt {} at 0x{:x}", code, frame.sepc),
+        }
+    } else {
+        stats.exceptions.fetch_add(1, Ordering::Relaxed);
+        if matches!(scause, EXC_LOAD_MISALIGNED | EXC_STORE_MISALIGNED)
+            && misaligned::handle(frame, stval)
+        {
+            return;
+        }
+        if matches!(
+            scause,
+            EXC_LOAD_ACCESS | EXC_STORE_ACCESS | EXC_LOAD_PAGE_FAULT | EXC_STORE_PAGE_FAULT
+        ) && uaccess::fixup(frame)
+        {
+            return;
+        }
+
+        panic!(
+            "unhandled exception {} ({}) at 0x{:x}, stval 0x{:x}",
+            scause,
+            exception_name(scause),
+            frame.sepc,
+            stval
+        );
+    }
+}
+
+pub fn exception_name(cause: usize) -> &'static str {
+    match cause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store address misaligned",
+        7 => "store access fault",
+        8 => "environment call from U-mode",
+        9 => "environment call from S-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store page fault",
+        18 => "software check",
+        19 => "hardware error",
+        _ => "unknown exception",
+    }
+}
@@ -0,0 +1,145 @@
+//! Copying to and from the memory of the calling process.
+//!
+//! The range is checked against the process' page tables first, then copied with
+//! `sstatus.SUM` set, so the kernel can reach U pages only for the copy itself. Should
+//! the copy fault anyway, the trap handler finds the faulting instruction in the
+//! exception table and continues at its fixup, which makes the copy fail with `EFAULT`
+//! instead of taking down the kernel.
+
+use core::arch::{global_asm, naked_asm};
+
+use crate::{
+    alloc::PAGE_SIZE, cpu::SSTATUS_SUM, page::EntryBits, process, syscall::Errno, trap::TrapFrame,
+};
+
+global_asm!(
+    ".section .rodata
+.global EX_TABLE_START
+EX_TABLE_START: .dword __ex_table_start
+
+.global EX_TABLE_END
+EX_TABLE_END: .dword __ex_table_end
+"
+);
+
+unsafe extern "C" {
+    static EX_TABLE_START: usize;
+    static EX_TABLE_END: usize;
+}
+
+/// An instruction that may fault on user memory and where to continue if it does
+#[repr(C)]
+struct ExEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+fn ex_table() -> &'static [ExEntry] {
+    unsafe {
+        let start = EX_TABLE_START as *const ExEntry;
+        let len = (EX_TABLE_END - EX_TABLE_START) / size_of::<ExEntry>();
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// Redirects a fault in the kernel to its fixup. Returns false if the faulting
+/// instruction isn't one that accesses user memory.
+pub fn fixup(frame: &mut TrapFrame) -> bool {
+    match ex_table().iter().find(|e| e.insn == frame.sepc) {
+        Some(entry) => {
+            frame.sepc = entry.fixup;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst` with SUM set. Returns how many bytes were
+/// left when a fault stopped it, 0 if the copy went through.
+#[unsafe(naked)]
+unsafe extern "C" fn copy_user(dst: usize, src: usize, len: usize) -> usize {
+    naked_asm!(
+        "li t6, {sum}
+        csrs sstatus, t6
+        beqz a2, 3f
+    1:
+        lb t0, 0(a1)
+    2:
+        sb t0, 0(a0)
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        bnez a2, 1b
+    3:
+        csrc sstatus, t6
+        mv a0, a2
+        ret
+
+        .pushsection __ex_table, \"a\"
+        .balign 8
+        .dword 1b, 3b
+        .dword 2b, 3b
+        .popsection",
+        sum = const SSTATUS_SUM,
+    );
+}
+
+/// Copies `len` bytes from `src` to `dst`, one of which is `user`, if the process may
+/// access all of `user..user + len` with `bits`.
+fn copy(dst: usize, src: usize, len: usize, user: usize, bits: i64) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = user.checked_add(len).ok_or(Errno::Fault)?;
+    let process = process::current().ok_or(Errno::Fault)?;
+    // holding the lock keeps the range mapped until the copy is done
+    let space = process.space.lock();
+    if !space.is_accessible(user, end, bits) {
+        return Err(Errno::Fault);
+    }
+    match unsafe { copy_user(dst, src, len) } {
+        0 => Ok(()),
+        _ => Err(Errno::Fault),
+    }
+}
+
+/// Copies `dst.len()` bytes from the calling process' memory at `src`.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    let len = dst.len();
+    copy(
+        dst.as_mut_ptr() as usize,
+        src,
+        len,
+        src,
+        EntryBits::Read as i64,
+    )
+}
+
+/// Copies `src` to the calling process' memory at `dst`.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), Errno> {
+    copy(
+        dst,
+        src.as_ptr() as usize,
+        src.len(),
+        dst,
+        EntryBits::Write as i64,
+    )
+}
+
+/// Copies the NUL terminated string at `src` into `dst`, one page at a time so that
+/// it doesn't read past the terminator's page. Returns the length of the string, which
+/// is `dst.len()` if it didn't fit.
+pub fn strncpy_from_user(dst: &mut [u8], src: usize) -> Result<usize, Errno> {
+    let mut done = 0;
+    while done < dst.len() {
+        let addr = src.checked_add(done).ok_or(Errno::Fault)?;
+        let page_left = PAGE_SIZE - addr % PAGE_SIZE;
+        let len = page_left.min(dst.len() - done);
+        copy_from_user(&mut dst[done..done + len], addr)?;
+        if let Some(nul) = dst[done..done + len].iter().position(|&b| b == 0) {
+            return Ok(done + nul);
+        }
+        done += len;
+    }
+    Ok(dst.len())
+}
@@ -1,7 +1,14 @@
 use core::fmt::Write;
 
+use crate::sync::Once;
+
+pub const UART_BASE: usize = 0x1000_0000;
+
+static INIT: Once<()> = Once::new();
+
+/// Programs the uart, only the first call does anything.
 pub fn init() {
-    Uart::new(0x1000_0000).init();
+    INIT.call_once(|| Uart::new(UART_BASE).init());
 }
 
 pub struct Uart {
@@ -9,7 +16,7 @@ pub struct Uart {
 }
 
 impl Uart {
-    pub fn new(base_addr: usize) -> Self {
+    pub const fn new(base_addr: usize) -> Self {
         Self { base_addr }
     }
 